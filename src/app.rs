@@ -1,55 +1,351 @@
-use glam::{Mat4, Vec3};
+use std::borrow::Borrow;
+use std::time::{Duration, Instant};
+
+use glam::{Mat3, Mat4, Vec3, Vec4};
+use log::warn;
+use parking_lot::Mutex;
+#[cfg(feature = "graphics")]
 use winit::event::MouseButton as WinitMouseButton;
+#[cfg(feature = "graphics")]
 use winit::keyboard::{KeyCode as WinitKeyCode, PhysicalKey};
 
+#[cfg(feature = "graphics")]
+use crate::input::{KeyCode, MouseButton, NamedKey};
 use crate::{
     data_model::DataModel,
-    input::{KeyCode, MouseButton, NamedKey},
     render::{CameraParams, LightParams},
-    scene::SceneObject,
+    scene::{self, Light, SceneObject, UpAxis},
 };
 
+/// Narrowest and widest vertical field of view, in degrees, that still
+/// produce a usable projection.
+const MIN_FOV: f32 = 1.0;
+const MAX_FOV: f32 = 179.0;
+const DEFAULT_NEAR: f32 = 0.1;
+const DEFAULT_FAR: f32 = 100.0;
+
+/// Last field of view and clip plane warnings, so a bad camera warns once
+/// rather than on every frame it is built.
+static FOV_WARNING: Mutex<Option<String>> = Mutex::new(None);
+static CLIP_WARNING: Mutex<Option<String>> = Mutex::new(None);
+
+/// Builds the camera from the first `camera` object, at a scene scale of
+/// one meter per unit. See [`camera_from_objects_with_active`] to pick the
+/// camera by name or scale it.
 pub fn camera_from_objects(objects: &[SceneObject], aspect: f32) -> CameraParams {
+    camera_from_objects_with_active(objects, None, aspect, 1.0)
+}
+
+/// Builds the camera from the `camera` object named `active_camera`, or the
+/// first camera when there is no such object.
+///
+/// The camera's position and clip planes are multiplied by `scene_scale`,
+/// the meters per scene unit; the fallback camera is already in meters.
+/// Objects are taken as Y-up; [`camera_from_model`] honors the model's
+/// [`UpAxis`].
+pub fn camera_from_objects_with_active(
+    objects: &[SceneObject],
+    active_camera: Option<&str>,
+    aspect: f32,
+    scene_scale: f32,
+) -> CameraParams {
+    let camera = select_camera(objects, active_camera)
+        .map(|camera| (camera, scene::world_matrix(objects, camera)));
+    camera_params(camera, aspect, scene_scale, UpAxis::Y)
+}
+
+/// Builds the camera from the data model's active camera, falling back to
+/// its first `camera` object.
+pub fn camera_from_model(model: &DataModel, aspect: f32) -> CameraParams {
+    let camera = active_or_first_camera(
+        model.active_camera().as_deref(),
+        |name| model.get(name),
+        || model.first_of_type("camera"),
+    );
+    let camera = camera.map(|camera| {
+        let world = model
+            .world_matrix(&camera.name)
+            .unwrap_or_else(|| camera.local_matrix());
+        (camera, world)
+    });
+    camera_params(
+        camera.as_ref().map(|(camera, world)| (camera, *world)),
+        aspect,
+        model.scene_scale(),
+        model.up_axis(),
+    )
+}
+
+fn select_camera<'a>(
+    objects: &'a [SceneObject],
+    active_camera: Option<&str>,
+) -> Option<&'a SceneObject> {
+    active_or_first_camera(
+        active_camera,
+        |name| objects.iter().find(|object| object.name == name),
+        || objects.iter().find(|object| is_camera(object)),
+    )
+}
+
+/// The object `find` returns for `active_camera` if it is a camera, else
+/// the `first` camera, shared by slices and the data model so both pick
+/// the same camera.
+fn active_or_first_camera<T: Borrow<SceneObject>>(
+    active_camera: Option<&str>,
+    find: impl FnOnce(&str) -> Option<T>,
+    first: impl FnOnce() -> Option<T>,
+) -> Option<T> {
+    active_camera
+        .and_then(find)
+        .filter(|object| is_camera(object.borrow()))
+        .or_else(first)
+}
+
+/// Camera uniforms for `camera` placed by its world matrix, or a default
+/// view when there is none.
+fn camera_params(
+    camera: Option<(&SceneObject, Mat4)>,
+    aspect: f32,
+    scene_scale: f32,
+    up_axis: UpAxis,
+) -> CameraParams {
     let default_position = Vec3::new(0.0, 2.0, 6.0);
     let default_target = Vec3::ZERO;
-    let (position, rotation, fov) = objects
-        .iter()
-        .find(|o| o.object_type == "camera")
-        .map(|camera| (camera.position, camera.rotation, camera.fov))
-        .unwrap_or((default_position, Vec3::ZERO, 60.0));
-
-    let rotation_matrix = Mat4::from_rotation_z(rotation.z.to_radians())
-        * Mat4::from_rotation_y(rotation.y.to_radians())
-        * Mat4::from_rotation_x(rotation.x.to_radians());
-    let forward = (rotation_matrix * Vec3::new(0.0, 0.0, -1.0).extend(0.0)).truncate();
-    let up = (rotation_matrix * Vec3::Y.extend(0.0)).truncate();
+    let basis = up_axis.to_y_up();
+    let (position, rotation_matrix, fov, near, far) = camera
+        .map(|(camera, matrix)| {
+            let (near, far) = clip_planes(camera.near, camera.far);
+            (
+                basis.transform_point3(matrix.w_axis.truncate() * scene_scale),
+                basis * Mat4::from_mat3(Mat3::from_mat4(matrix)),
+                clamp_fov(camera.fov),
+                near * scene_scale,
+                far * scene_scale,
+            )
+        })
+        .unwrap_or((
+            default_position,
+            Mat4::IDENTITY,
+            60.0,
+            DEFAULT_NEAR,
+            DEFAULT_FAR,
+        ));
+
+    let forward = rotation_matrix.transform_vector3(Vec3::NEG_Z);
+    let up = rotation_matrix.transform_vector3(Vec3::Y);
     let target = if forward.length_squared() > f32::EPSILON {
         position + forward.normalize()
     } else {
         default_target
     };
     let view = Mat4::look_at_rh(position, target, up);
-    let projection = Mat4::perspective_rh_gl(fov.to_radians(), aspect.max(0.01), 0.1, 100.0);
+    let projection = Mat4::perspective_rh_gl(fov.to_radians(), aspect.max(0.01), near, far);
     CameraParams {
         view_proj: projection * view,
         position,
+        view,
+    }
+}
+
+/// Clamps a degenerate field of view into `MIN_FOV..=MAX_FOV`.
+fn clamp_fov(fov: f32) -> f32 {
+    if fov.is_finite() && fov > 0.0 && fov < 180.0 {
+        return fov.clamp(MIN_FOV, MAX_FOV);
+    }
+    let clamped = if fov.is_nan() {
+        60.0
+    } else {
+        fov.clamp(MIN_FOV, MAX_FOV)
+    };
+    warn_on_change(
+        &FOV_WARNING,
+        format!("camera fov {fov} is degenerate; using {clamped}"),
+    );
+    clamped
+}
+
+/// Ensures `0 < near < far`, falling back to the defaults otherwise.
+fn clip_planes(near: f32, far: f32) -> (f32, f32) {
+    let valid_near = near.is_finite() && near > 0.0;
+    let near_plane = if valid_near { near } else { DEFAULT_NEAR };
+    let far_plane = if far.is_finite() && far > near_plane {
+        far
+    } else {
+        DEFAULT_FAR.max(near_plane * 1000.0)
+    };
+    if !valid_near || far_plane != far {
+        warn_on_change(
+            &CLIP_WARNING,
+            format!("camera clip planes near={near} far={far} are invalid; using near={near_plane} far={far_plane}"),
+        );
+    }
+    (near_plane, far_plane)
+}
+
+/// Logs `message` unless it repeats the last one logged through `last`,
+/// returning whether it was logged.
+fn warn_on_change(last: &Mutex<Option<String>>, message: String) -> bool {
+    let mut last = last.lock();
+    if last.as_deref() == Some(message.as_str()) {
+        return false;
     }
+    warn!("{message}");
+    *last = Some(message);
+    true
 }
 
+/// Turns a pixel position into a world-space ray `(origin, direction)`
+/// through the camera, with `direction` normalized.
+///
+/// `x` and `y` are measured from the top-left corner of a viewport of
+/// `viewport` pixels.
+pub fn screen_to_world_ray(
+    x: f32,
+    y: f32,
+    viewport: (u32, u32),
+    camera: &CameraParams,
+) -> (Vec3, Vec3) {
+    let width = viewport.0.max(1) as f32;
+    let height = viewport.1.max(1) as f32;
+    let ndc_x = x / width * 2.0 - 1.0;
+    let ndc_y = 1.0 - y / height * 2.0;
+    let inverse = camera.view_proj.inverse();
+    let unproject = |z: f32| {
+        let point = inverse * Vec4::new(ndc_x, ndc_y, z, 1.0);
+        point.truncate() / point.w
+    };
+    // Both depths lie inside the frustum for GL and zero-to-one projections.
+    let direction = (unproject(1.0) - unproject(0.0)).normalize_or_zero();
+    (camera.position, direction)
+}
+
+/// Builds the light uniforms from the first `light` object, at a scene
+/// scale of one meter per unit.
 pub fn light_from_objects(objects: &[SceneObject]) -> LightParams {
-    objects
+    light_from_objects_scaled(objects, 1.0)
+}
+
+/// Like [`light_from_objects`], with the light's position multiplied by
+/// `scene_scale`, the meters per scene unit.
+pub fn light_from_objects_scaled(objects: &[SceneObject], scene_scale: f32) -> LightParams {
+    let light = objects
         .iter()
-        .find(|o| o.object_type == "light")
+        .find(|o| is_light(o))
+        .and_then(|light| Light::from_object(light, scene::world_matrix(objects, light)));
+    light_params(light, scene_scale, UpAxis::Y)
+}
+
+/// Builds the light uniforms from the first `light` object stored in the data model.
+pub fn light_from_model(model: &DataModel) -> LightParams {
+    let light = model.first_of_type("light").and_then(|light| {
+        let world = model
+            .world_matrix(&light.name)
+            .unwrap_or_else(|| light.local_matrix());
+        Light::from_object(&light, world)
+    });
+    light_params(light, model.scene_scale(), model.up_axis())
+}
+
+fn light_params(light: Option<Light>, scene_scale: f32, up_axis: UpAxis) -> LightParams {
+    let basis = up_axis.to_y_up();
+    light
         .map(|light| LightParams {
-            position: light.position,
+            position: basis.transform_point3(light.position * scene_scale),
             color: light.color,
             intensity: light.intensity.max(0.1),
+            light_type: light.light_type,
+            direction: basis.transform_vector3(light.direction),
+            spot_angle: light.spot_angle,
         })
-        .unwrap_or(LightParams {
-            position: Vec3::new(3.0, 5.0, -3.0),
-            color: Vec3::splat(1.0),
-            intensity: 1.0,
-        })
+        .unwrap_or_else(|| LightParams::new(Vec3::new(3.0, 5.0, -3.0), Vec3::ONE, 1.0))
+}
+
+fn is_camera(object: &SceneObject) -> bool {
+    object.object_type == "camera"
+}
+
+fn is_light(object: &SceneObject) -> bool {
+    object.object_type == "light"
+}
+
+/// Decides when the native loop redraws under an optional frame-rate cap.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    interval: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl FramePacer {
+    /// Caps redraws at `max_fps` frames per second; 0 leaves them uncapped.
+    pub fn new(max_fps: u32) -> Self {
+        Self {
+            interval: (max_fps > 0).then(|| Duration::from_secs(1) / max_fps),
+            deadline: None,
+        }
+    }
+
+    /// Called once per loop iteration. Returns whether a frame is due at
+    /// `now` and, when capped, the instant to wait until before the next.
+    ///
+    /// Deadlines advance by whole intervals for a steady cadence, but a
+    /// stalled frame restarts the schedule instead of drawing a burst.
+    pub fn tick(&mut self, now: Instant) -> (bool, Option<Instant>) {
+        let Some(interval) = self.interval else {
+            return (true, None);
+        };
+        match self.deadline {
+            Some(deadline) if now < deadline => (false, Some(deadline)),
+            deadline => {
+                let next = deadline
+                    .map(|deadline| deadline + interval)
+                    .filter(|next| *next > now)
+                    .unwrap_or(now + interval);
+                self.deadline = Some(next);
+                (true, Some(next))
+            }
+        }
+    }
+}
+
+/// Decides whether the native loop needs a new frame, so static scenes
+/// stop redrawing.
+///
+/// A frame is due after [`RedrawTracker::mark_dirty`] (input, resizes), when
+/// the [`DataModel::revision`] moved since the last drawn frame, and always
+/// in continuous mode. The first frame is always due.
+#[derive(Debug, Clone)]
+pub struct RedrawTracker {
+    continuous: bool,
+    forced: bool,
+    drawn_revision: Option<u64>,
+}
+
+impl RedrawTracker {
+    /// `continuous` redraws every frame, as the loop did before tracking.
+    pub fn new(continuous: bool) -> Self {
+        Self {
+            continuous,
+            forced: true,
+            drawn_revision: None,
+        }
+    }
+
+    /// Forces the next frame whatever the model revision.
+    pub fn mark_dirty(&mut self) {
+        self.forced = true;
+    }
+
+    /// Whether a frame is needed to show a model at `revision`.
+    pub fn is_dirty(&self, revision: u64) -> bool {
+        self.continuous || self.forced || self.drawn_revision != Some(revision)
+    }
+
+    /// Records a drawn frame showing the model at `revision`, read before
+    /// the model was drawn so later writes still trigger a frame.
+    pub fn frame_drawn(&mut self, revision: u64) {
+        self.forced = false;
+        self.drawn_revision = Some(revision);
+    }
 }
 
 pub fn print_final_state(model: &DataModel) {
@@ -68,6 +364,7 @@ pub fn print_final_state(model: &DataModel) {
     }
 }
 
+#[cfg(feature = "graphics")]
 pub fn map_keycode(key: &PhysicalKey) -> Option<KeyCode> {
     let PhysicalKey::Code(code) = key else {
         return None;
@@ -146,6 +443,7 @@ pub fn map_keycode(key: &PhysicalKey) -> Option<KeyCode> {
     })
 }
 
+#[cfg(feature = "graphics")]
 pub fn map_mouse_button(button: WinitMouseButton) -> MouseButton {
     let index = match button {
         WinitMouseButton::Left => 0,
@@ -157,3 +455,241 @@ pub fn map_mouse_button(button: WinitMouseButton) -> MouseButton {
     } as u8;
     MouseButton::new(index)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LightType;
+
+    fn camera(fov: f32) -> SceneObject {
+        SceneObject::builder()
+            .name("Camera")
+            .object_type("camera")
+            .fov(fov)
+            .build()
+    }
+
+    #[test]
+    fn zero_fov_is_clamped() {
+        assert_eq!(clamp_fov(0.0), MIN_FOV);
+        let params = camera_from_objects(&[camera(0.0)], 1.0);
+        assert!(params.view_proj.is_finite());
+        assert_eq!(
+            params.view_proj,
+            camera_from_objects(&[camera(MIN_FOV)], 1.0).view_proj
+        );
+    }
+
+    #[test]
+    fn straight_angle_fov_is_clamped() {
+        assert_eq!(clamp_fov(180.0), MAX_FOV);
+        let params = camera_from_objects(&[camera(180.0)], 1.0);
+        assert!(params.view_proj.is_finite());
+        assert_eq!(
+            params.view_proj,
+            camera_from_objects(&[camera(MAX_FOV)], 1.0).view_proj
+        );
+    }
+
+    #[test]
+    fn active_camera_selects_second_camera() {
+        let main = camera(60.0);
+        let mut minimap = camera(60.0);
+        minimap.name = "Minimap".into();
+        minimap.position = Vec3::new(0.0, 20.0, 0.0);
+        let model = DataModel::from_objects(vec![main, minimap]);
+
+        let first = camera_from_model(&model, 1.0);
+        model.set_active_camera(Some("Minimap"));
+        let selected = camera_from_model(&model, 1.0);
+        assert_ne!(first.view_proj, selected.view_proj);
+        assert_eq!(selected.position, Vec3::new(0.0, 20.0, 0.0));
+
+        model.set_active_camera(Some("Missing"));
+        assert_eq!(camera_from_model(&model, 1.0).view_proj, first.view_proj);
+
+        let objects = model.all_objects();
+        assert_eq!(
+            camera_from_objects(&objects, 1.0).view_proj,
+            first.view_proj
+        );
+        let named = camera_from_objects_with_active(&objects, Some("Minimap"), 1.0, 1.0);
+        assert_eq!(named.view_proj, selected.view_proj);
+    }
+
+    #[test]
+    fn scene_scale_moves_camera_and_light() {
+        let mut object = camera(60.0);
+        object.position = Vec3::new(0.0, 100.0, 500.0);
+        let light = SceneObject::builder()
+            .name("Sun")
+            .object_type("light")
+            .position(Vec3::new(0.0, 300.0, 0.0))
+            .build();
+        let model = DataModel::from_objects(vec![object, light]);
+        model.set_scene_scale(0.01);
+        assert_eq!(
+            camera_from_model(&model, 1.0).position,
+            Vec3::new(0.0, 1.0, 5.0)
+        );
+        assert_eq!(light_from_model(&model).position, Vec3::new(0.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn z_up_model_turns_camera_and_light_to_y_up() {
+        let mut object = camera(60.0);
+        object.position = Vec3::new(0.0, -5.0, 1.0);
+        object.rotation = Vec3::new(90.0, 0.0, 0.0);
+        let light = SceneObject::builder()
+            .name("Sun")
+            .object_type("light")
+            .position(Vec3::new(0.0, 0.0, 3.0))
+            .build();
+        let model = DataModel::from_objects(vec![object, light]);
+        model.set_up_axis(UpAxis::Z);
+        let params = camera_from_model(&model, 1.0);
+        assert!((params.position - Vec3::new(0.0, 1.0, 5.0)).length() < 1e-5);
+        // A Z-up camera pitched up 90° looks along the scene's +Y, which
+        // is the renderer's -Z, with +Y up.
+        let forward = -params.view.row(2).truncate();
+        assert!((forward - Vec3::NEG_Z).length() < 1e-5);
+        assert!((light_from_model(&model).position - Vec3::new(0.0, 3.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn directional_light_shines_along_rotation() {
+        let light = SceneObject::builder()
+            .name("Sun")
+            .object_type("light")
+            .light_type(LightType::Directional)
+            .position(Vec3::new(0.0, 4.0, 0.0))
+            .rotation([-90.0, 0.0, 0.0])
+            .build();
+        let params = light_from_objects(std::slice::from_ref(&light));
+        assert_eq!(params.light_type, LightType::Directional);
+        assert!((params.direction - Vec3::NEG_Y).length() < 1e-5);
+        assert_eq!(light_from_objects(&[]).light_type, LightType::Point);
+
+        let scaled = light_from_objects_scaled(&[light], 0.5);
+        assert_eq!(scaled.position, Vec3::new(0.0, 2.0, 0.0));
+        assert_eq!(scaled.direction, params.direction);
+    }
+
+    #[test]
+    fn frame_pacer_waits_between_frames() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(50);
+        let interval = Duration::from_millis(20);
+        assert_eq!(pacer.tick(start), (true, Some(start + interval)));
+        assert_eq!(
+            pacer.tick(start + Duration::from_millis(5)),
+            (false, Some(start + interval))
+        );
+        assert_eq!(
+            pacer.tick(start + Duration::from_millis(21)),
+            (true, Some(start + interval * 2))
+        );
+        let late = start + Duration::from_millis(100);
+        assert_eq!(pacer.tick(late), (true, Some(late + interval)));
+
+        assert_eq!(FramePacer::new(0).tick(start), (true, None));
+    }
+
+    #[test]
+    fn redraw_tracker_skips_unchanged_frames() {
+        let mut tracker = RedrawTracker::new(false);
+        assert!(tracker.is_dirty(0), "first frame always draws");
+        tracker.frame_drawn(0);
+        assert!(!tracker.is_dirty(0));
+        assert!(tracker.is_dirty(1));
+        tracker.frame_drawn(1);
+
+        tracker.mark_dirty();
+        assert!(tracker.is_dirty(1), "resizes and input force a frame");
+        tracker.frame_drawn(1);
+        assert!(!tracker.is_dirty(1));
+
+        let mut continuous = RedrawTracker::new(true);
+        continuous.frame_drawn(0);
+        assert!(continuous.is_dirty(0));
+    }
+
+    #[test]
+    fn parented_camera_and_light_use_world_positions() {
+        let rig = SceneObject::builder()
+            .name("Rig")
+            .position(Vec3::new(10.0, 0.0, 0.0))
+            .rotation([0.0, 90.0, 0.0])
+            .build();
+        let mut eye = camera(60.0);
+        eye.parent = Some("Rig".into());
+        eye.position = Vec3::new(0.0, 1.0, 0.0);
+        let lamp = SceneObject::builder()
+            .name("Lamp")
+            .object_type("light")
+            .parent("Rig")
+            .position(Vec3::new(0.0, 0.0, -2.0))
+            .build();
+        let objects = vec![rig, eye, lamp];
+
+        let camera = camera_from_objects(&objects, 1.0);
+        assert_eq!(camera.position, Vec3::new(10.0, 1.0, 0.0));
+        let forward = -camera.view.row(2).truncate();
+        assert!((forward - Vec3::NEG_X).length() < 1e-5, "{forward}");
+
+        let light = light_from_objects(&objects);
+        assert!((light.position - Vec3::new(8.0, 0.0, 0.0)).length() < 1e-5);
+        assert!((light.direction - Vec3::NEG_X).length() < 1e-5);
+
+        let model = DataModel::from_objects(objects);
+        assert_eq!(camera_from_model(&model, 1.0).view_proj, camera.view_proj);
+        assert_eq!(light_from_model(&model).position, light.position);
+        assert_eq!(model.lights()[0].position, light.position);
+    }
+
+    #[test]
+    fn baked_matrix_places_the_camera() {
+        let mut object = camera(60.0);
+        object.position = Vec3::new(9.0, 9.0, 9.0);
+        object.matrix = Some(
+            Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0))
+                * Mat4::from_rotation_y(90f32.to_radians())
+                * Mat4::from_scale(Vec3::splat(2.0)),
+        );
+        let params = camera_from_objects(&[object], 1.0);
+        assert_eq!(params.position, Vec3::new(1.0, 2.0, 3.0));
+        // Turned 90° about Y, local -Z faces world -X.
+        let forward = -params.view.row(2).truncate();
+        assert!((forward - Vec3::NEG_X).length() < 1e-5, "{forward}");
+    }
+
+    #[test]
+    fn screen_center_ray_points_forward() {
+        let mut object = camera(60.0);
+        object.position = Vec3::new(0.0, 1.0, 5.0);
+        let params = camera_from_objects(&[object], 16.0 / 9.0);
+
+        let (origin, direction) = screen_to_world_ray(640.0, 360.0, (1280, 720), &params);
+        assert_eq!(origin, Vec3::new(0.0, 1.0, 5.0));
+        assert!((direction - Vec3::NEG_Z).length() < 1e-4);
+
+        let (_, left) = screen_to_world_ray(0.0, 360.0, (1280, 720), &params);
+        assert!(left.x < 0.0);
+    }
+
+    #[test]
+    fn repeated_warnings_are_logged_once() {
+        let last = Mutex::new(None);
+        assert!(warn_on_change(&last, "camera fov 0 is degenerate".into()));
+        assert!(!warn_on_change(&last, "camera fov 0 is degenerate".into()));
+        assert!(warn_on_change(&last, "camera fov 200 is degenerate".into()));
+        assert!(warn_on_change(&last, "camera fov 0 is degenerate".into()));
+    }
+
+    #[test]
+    fn invalid_clip_planes_fall_back() {
+        assert_eq!(clip_planes(0.5, 50.0), (0.5, 50.0));
+        assert_eq!(clip_planes(0.0, 50.0), (DEFAULT_NEAR, 50.0));
+        assert_eq!(clip_planes(1.0, 0.5), (1.0, 1000.0));
+    }
+}
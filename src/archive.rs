@@ -1,10 +1,69 @@
+//! Reader for `.cgame` archives.
+//!
+//! # Format versions
+//!
+//! Every archive starts with a 16-byte header: the magic `CGME`, a `u32`
+//! format version and a `u64` table-of-contents offset. All integers use
+//! one byte order, little- or big-endian, recognised from the version.
+//!
+//! * **Version 1**: entry data is stored uncompressed. The table of
+//!   contents is a `u32` entry count, then per entry a `u32` name length,
+//!   the UTF-8 name and `u64` offset/size, and finally the `u64`
+//!   offset/size of the scene XML. Old exporters leave the header offset
+//!   at zero and either append a `CEND` footer holding it, or leave the
+//!   table to be found by scanning.
+//! * **Version 2**: the version 1 layout followed by a `u32` CRC-32 (IEEE)
+//!   of every byte before it. A mismatch fails with
+//!   [`ArchiveError::ChecksumMismatch`] before the table of contents is
+//!   read, so truncated or corrupted downloads are reported as such.
+//!
+//! Newer versions are rejected with an error rather than guessed at, so a
+//! future layout (e.g. per-entry compression flags) cannot be misread.
+//!
+//! Little-endian version 2 is the canonical form: [`CGameArchiveBuilder`]
+//! always writes it. [`CGameArchive::open`] still accepts big-endian and
+//! legacy footer or offset-less archives, while
+//! [`CGameArchive::open_strict`] only trusts the header offset and never
+//! scans payload data for something that looks like a table of contents.
+
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use anyhow::{anyhow, Context, Result};
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::telemetry::load_span;
+
+/// Highest archive format version this build can read.
+pub const MAX_SUPPORTED_VERSION: u32 = 2;
+
+/// Size of the CRC-32 trailer closing version 2 archives.
+const CHECKSUM_LEN: usize = 4;
+
+/// Typed archive failures; recover them with
+/// `err.downcast_ref::<ArchiveError>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ArchiveError {
+    /// The stored checksum does not match the archive contents.
+    #[error("archive checksum mismatch: stored {expected:08x}, computed {actual:08x}; the file is truncated or corrupt")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+/// Archives parsed by [`CGameArchive::from_bytes_cached`], by handle.
+static ARCHIVE_CACHE: OnceLock<Mutex<HashMap<String, Arc<CGameArchive>>>> = OnceLock::new();
+
+/// Scene file read from the root of [`CGameArchive::from_directory`].
+const SCENE_FILE_NAME: &str = "scene.xml";
+
+/// Magic closing legacy archives that record their TOC offset in a footer.
+const FOOTER_MAGIC: &[u8; 4] = b"CEND";
+/// Footer size: the magic followed by a u64 TOC offset.
+const FOOTER_LEN: usize = 12;
 
 /// File entry extracted from the archive table of contents.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -26,20 +85,70 @@ pub struct CGameArchive {
 #[derive(Debug, Clone)]
 enum ArchiveBacking {
     File(PathBuf),
-    Memory { _label: String, data: Arc<[u8]> },
+    /// Loose files read from disk by [`CGameArchive::from_directory`].
+    Directory(PathBuf),
+    Memory {
+        _label: String,
+        data: Arc<[u8]>,
+    },
+    #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+    Mapped {
+        _path: PathBuf,
+        map: Arc<memmap2::Mmap>,
+    },
 }
 
 impl CGameArchive {
     /// Opens an archive from disk and eagerly loads the scene XML blob.
+    ///
+    /// With the `mmap` feature the file stays mapped so entries are sliced
+    /// out of memory; if mapping fails each extraction rereads the file.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let path_buf = path.as_ref().to_path_buf();
+        Self::open_with(path.as_ref(), TocSearch::Recover)
+    }
+
+    /// Like [`open`](Self::open), but requires the header to hold a valid
+    /// table-of-contents offset, rejecting legacy archives that need a
+    /// footer or a scan to find it.
+    pub fn open_strict<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path.as_ref(), TocSearch::HeaderOnly)
+    }
+
+    fn open_with(path: &Path, search: TocSearch) -> Result<Self> {
+        let path_buf = path.to_path_buf();
+        let span = load_span("archive_open", &path_buf.display());
         let mut file = File::open(&path_buf)
             .with_context(|| format!("unable to open {}", path_buf.display()))?;
+
+        #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+        // SAFETY: the mapping is read-only; like the reopen path, this
+        // assumes the archive is not truncated while it is open.
+        match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(map) => {
+                let (version, files, scene_xml) = parse_archive_metadata(&map, search)?;
+                span.finish(files.len());
+                return Ok(Self {
+                    backing: ArchiveBacking::Mapped {
+                        _path: path_buf,
+                        map: Arc::new(map),
+                    },
+                    version,
+                    files,
+                    scene_xml,
+                });
+            }
+            Err(err) => warn!(
+                "unable to map {}, reading entries on demand: {err}",
+                path_buf.display()
+            ),
+        }
+
         let mut data = Vec::new();
         file.read_to_end(&mut data)
             .context("unable to read archive into memory")?;
 
-        let (version, files, scene_xml) = parse_archive_metadata(&data)?;
+        let (version, files, scene_xml) = parse_archive_metadata(&data, search)?;
+        span.finish(files.len());
 
         Ok(Self {
             backing: ArchiveBacking::File(path_buf),
@@ -52,7 +161,7 @@ impl CGameArchive {
     /// Creates an archive from bytes already resident in memory.
     pub fn from_bytes(label: impl Into<String>, data: Vec<u8>) -> Result<Self> {
         let storage: Arc<[u8]> = Arc::from(data.into_boxed_slice());
-        let (version, files, scene_xml) = parse_archive_metadata(&storage)?;
+        let (version, files, scene_xml) = parse_archive_metadata(&storage, TocSearch::Recover)?;
         Ok(Self {
             backing: ArchiveBacking::Memory {
                 _label: label.into(),
@@ -64,7 +173,90 @@ impl CGameArchive {
         })
     }
 
-    /// Returns the engine version stored in the archive header.
+    /// Like [`from_bytes`](Self::from_bytes), but keeps the parsed archive
+    /// in a process-wide registry under `key` and returns the same instance
+    /// while later calls pass identical bytes.
+    ///
+    /// Different bytes under a known key replace its entry, so a stale
+    /// handle never serves the wrong scene. Entries live until
+    /// [`remove_cached`](Self::remove_cached) drops them.
+    pub fn from_bytes_cached(key: impl Into<String>, data: Vec<u8>) -> Result<Arc<Self>> {
+        let key = key.into();
+        let cache = ARCHIVE_CACHE.get_or_init(Mutex::default);
+        if let Some(archive) = cache.lock().get(&key) {
+            if matches!(&archive.backing, ArchiveBacking::Memory { data: cached, .. } if **cached == *data)
+            {
+                return Ok(Arc::clone(archive));
+            }
+        }
+        let archive = Arc::new(Self::from_bytes(key.clone(), data)?);
+        cache.lock().insert(key, Arc::clone(&archive));
+        Ok(archive)
+    }
+
+    /// Drops the archive cached under `key`, returning whether there was
+    /// one. Instances already handed out stay valid.
+    pub fn remove_cached(key: &str) -> bool {
+        ARCHIVE_CACHE
+            .get()
+            .is_some_and(|cache| cache.lock().remove(key).is_some())
+    }
+
+    /// An archive without entries or scene, standing in where scripts come
+    /// from elsewhere.
+    pub(crate) fn empty() -> Self {
+        Self {
+            backing: ArchiveBacking::Memory {
+                _label: "empty archive".into(),
+                data: Arc::from(Vec::new().into_boxed_slice()),
+            },
+            version: MAX_SUPPORTED_VERSION,
+            files: Vec::new(),
+            scene_xml: String::new(),
+        }
+    }
+
+    /// Reads an archive from any byte stream, such as a network response.
+    pub fn from_reader<R: Read>(label: impl Into<String>, mut reader: R) -> Result<Self> {
+        let label = label.into();
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("unable to read archive {label}"))?;
+        Self::from_bytes(label, data)
+    }
+
+    /// Presents a directory of loose files as an archive, for iterating
+    /// without repackaging.
+    ///
+    /// `scene.xml` at the root becomes the scene, and every other file
+    /// below the directory becomes an entry named by its `/`-separated
+    /// path relative to it, e.g. `models/cube.obj`. Entries are read from
+    /// disk on extraction, so edits show up without reopening. Directories
+    /// have no header and report [`MAX_SUPPORTED_VERSION`].
+    pub fn from_directory<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let root = path.as_ref().to_path_buf();
+        let span = load_span("archive_open", &root.display());
+        let scene_path = root.join(SCENE_FILE_NAME);
+        let scene_xml = std::fs::read_to_string(&scene_path)
+            .with_context(|| format!("unable to read {}", scene_path.display()))?;
+
+        let mut files = Vec::new();
+        collect_directory_entries(&root, "", &mut files)?;
+        files.retain(|entry| entry.name != SCENE_FILE_NAME);
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        span.finish(files.len());
+
+        Ok(Self {
+            backing: ArchiveBacking::Directory(root),
+            version: MAX_SUPPORTED_VERSION,
+            files,
+            scene_xml,
+        })
+    }
+
+    /// Returns the format version stored in the archive header; see the
+    /// module docs for what each version means.
     pub fn version(&self) -> u32 {
         self.version
     }
@@ -92,6 +284,36 @@ impl CGameArchive {
         self.extract_entry(entry)
     }
 
+    /// Decodes every entry under `prefix` as UTF-8.
+    ///
+    /// Unlike [`extract_file`](Self::extract_file) this keeps going past
+    /// failures: decoded files are returned by name, and entries that could
+    /// not be read or decoded are listed with their error message.
+    pub fn extract_all_text(
+        &self,
+        prefix: &str,
+    ) -> (HashMap<String, String>, Vec<(String, String)>) {
+        let mut texts = HashMap::new();
+        let mut failures = Vec::new();
+        for entry in self
+            .files
+            .iter()
+            .filter(|entry| entry.name.starts_with(prefix))
+        {
+            let text = self.extract_entry(entry).and_then(|bytes| {
+                String::from_utf8(bytes)
+                    .with_context(|| format!("{} is not valid UTF-8", entry.name))
+            });
+            match text {
+                Ok(text) => {
+                    texts.insert(entry.name.clone(), text);
+                }
+                Err(err) => failures.push((entry.name.clone(), format!("{err:#}"))),
+            }
+        }
+        (texts, failures)
+    }
+
     /// Extracts the raw bytes for a previously looked-up entry.
     pub fn extract_entry(&self, entry: &ArchiveFileEntry) -> Result<Vec<u8>> {
         match &self.backing {
@@ -105,24 +327,156 @@ impl CGameArchive {
                     .with_context(|| format!("unable to read {} from archive", entry.name))?;
                 Ok(buffer)
             }
-            ArchiveBacking::Memory { data, .. } => {
-                let start = entry.offset as usize;
-                let end = start + entry.size as usize;
-                if end > data.len() {
-                    return Err(anyhow!(
-                        "entry {} extends past archive bounds ({} > {})",
-                        entry.name,
-                        end,
-                        data.len()
-                    ));
-                }
-                Ok(data[start..end].to_vec())
+            ArchiveBacking::Directory(root) => {
+                let path = root.join(&entry.name);
+                std::fs::read(&path).with_context(|| format!("unable to read {}", path.display()))
             }
+            ArchiveBacking::Memory { data, .. } => slice_entry(data, entry),
+            #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+            ArchiveBacking::Mapped { map, .. } => slice_entry(map, entry),
         }
     }
 }
 
-fn parse_archive_metadata(data: &[u8]) -> Result<(u32, Vec<ArchiveFileEntry>, String)> {
+/// Appends an entry for every file below `dir`, named with `prefix` and
+/// `/` separators so names match packed archives on every platform.
+fn collect_directory_entries(
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<ArchiveFileEntry>,
+) -> Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("unable to list {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("unable to list {}", dir.display()))?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            warn!("skipping non-UTF-8 path {}", entry.path().display());
+            continue;
+        };
+        let name = format!("{prefix}{file_name}");
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("unable to inspect {}", entry.path().display()))?;
+        if metadata.is_dir() {
+            collect_directory_entries(&entry.path(), &format!("{name}/"), files)?;
+        } else if metadata.is_file() {
+            files.push(ArchiveFileEntry {
+                name,
+                offset: 0,
+                size: metadata.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn slice_entry(data: &[u8], entry: &ArchiveFileEntry) -> Result<Vec<u8>> {
+    let start = entry.offset as usize;
+    let end = start + entry.size as usize;
+    if end > data.len() {
+        return Err(anyhow!(
+            "entry {} extends past archive bounds ({} > {})",
+            entry.name,
+            end,
+            data.len()
+        ));
+    }
+    Ok(data[start..end].to_vec())
+}
+
+/// Builds checksummed version 2 archives in the canonical little-endian
+/// byte order.
+///
+/// Entries are written in the order they were added, followed by the scene
+/// and the table of contents, whose offset goes in the header.
+#[derive(Debug, Clone, Default)]
+pub struct CGameArchiveBuilder {
+    scene_xml: String,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl CGameArchiveBuilder {
+    pub fn new(scene_xml: impl Into<String>) -> Self {
+        Self {
+            scene_xml: scene_xml.into(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Adds an entry, replacing any earlier entry with the same name.
+    pub fn file(mut self, name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        let name = name.into();
+        let data = data.into();
+        match self
+            .files
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some(entry) => entry.1 = data,
+            None => self.files.push((name, data)),
+        }
+        self
+    }
+
+    /// Serializes the archive.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let endian = ArchiveEndian::Little;
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(b"CGME");
+        buffer.extend_from_slice(&endian.encode_u32(2));
+        buffer.extend_from_slice(&endian.encode_u64(0));
+
+        let mut entries = Vec::with_capacity(self.files.len());
+        for (name, data) in &self.files {
+            entries.push((name, buffer.len() as u64, data.len() as u64));
+            buffer.extend_from_slice(data);
+        }
+        let scene_offset = buffer.len() as u64;
+        buffer.extend_from_slice(self.scene_xml.as_bytes());
+
+        let toc_offset = buffer.len() as u64;
+        buffer.extend_from_slice(&endian.encode_u32(entries.len() as u32));
+        for (name, offset, size) in entries {
+            buffer.extend_from_slice(&endian.encode_u32(name.len() as u32));
+            buffer.extend_from_slice(name.as_bytes());
+            buffer.extend_from_slice(&endian.encode_u64(offset));
+            buffer.extend_from_slice(&endian.encode_u64(size));
+        }
+        buffer.extend_from_slice(&endian.encode_u64(scene_offset));
+        buffer.extend_from_slice(&endian.encode_u64(self.scene_xml.len() as u64));
+        buffer[8..16].copy_from_slice(&endian.encode_u64(toc_offset));
+        let checksum = crc32(&buffer);
+        buffer.extend_from_slice(&endian.encode_u32(checksum));
+        buffer
+    }
+
+    /// Writes the serialized archive to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer
+            .write_all(&self.to_bytes())
+            .context("unable to write archive")
+    }
+
+    /// Serializes the archive and opens the result from memory.
+    pub fn build(&self) -> Result<CGameArchive> {
+        CGameArchive::from_bytes("built archive", self.to_bytes())
+    }
+}
+
+/// Where the parser may look for the table of contents.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum TocSearch {
+    /// Only at the offset recorded in the header.
+    HeaderOnly,
+    /// At the header offset, then a `CEND` footer, then by scanning.
+    Recover,
+}
+
+fn parse_archive_metadata(
+    data: &[u8],
+    search: TocSearch,
+) -> Result<(u32, Vec<ArchiveFileEntry>, String)> {
     if data.len() < 16 {
         return Err(anyhow!(
             "archive too small to contain header (len={})",
@@ -141,11 +495,85 @@ fn parse_archive_metadata(data: &[u8]) -> Result<(u32, Vec<ArchiveFileEntry>, St
     let version_bytes: [u8; 4] = data[4..8].try_into().expect("slice length verified above");
     let toc_bytes: [u8; 8] = data[8..16].try_into().expect("slice length verified above");
 
-    let (_endian, version, _toc_offset, files, scene_xml) =
-        parse_archive_bytes(data, version_bytes, toc_bytes)?;
+    let (endian, version) = detect_version(version_bytes)?;
+    let (_toc_offset, files, scene_xml) = match version {
+        1 => parse_archive_bytes(data, endian, toc_bytes, search)?,
+        2 => {
+            let body = verify_checksum(data, endian)?;
+            parse_archive_bytes(body, endian, toc_bytes, search)?
+        }
+        _ => unreachable!("detect_version only returns supported versions"),
+    };
     Ok((version, files, scene_xml))
 }
 
+/// Checks the CRC-32 trailer of a version 2 archive and returns the bytes
+/// it covers.
+fn verify_checksum(data: &[u8], endian: ArchiveEndian) -> Result<&[u8]> {
+    let body_len = data
+        .len()
+        .checked_sub(CHECKSUM_LEN)
+        .filter(|len| *len >= 16)
+        .ok_or_else(|| anyhow!("archive is too small to contain its checksum"))?;
+    let (body, trailer) = data.split_at(body_len);
+    let expected = endian.decode_u32(trailer.try_into().expect("checksum length"));
+    let actual = crc32(body);
+    if expected != actual {
+        return Err(ArchiveError::ChecksumMismatch { expected, actual }.into());
+    }
+    Ok(body)
+}
+
+/// Lookup table for the reflected IEEE CRC-32 polynomial.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                0xEDB8_8320 ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[index] = value;
+        index += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE), as used by zip and PNG.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Reads the header version in whichever byte order yields a supported
+/// version, which also fixes the byte order of the rest of the archive.
+fn detect_version(version_bytes: [u8; 4]) -> Result<(ArchiveEndian, u32)> {
+    let candidates = [ArchiveEndian::Little, ArchiveEndian::Big]
+        .map(|endian| (endian, endian.decode_u32(version_bytes)));
+    if let Some(found) = candidates
+        .into_iter()
+        .find(|(_, version)| (1..=MAX_SUPPORTED_VERSION).contains(version))
+    {
+        return Ok(found);
+    }
+    // The smaller reading is the plausible one whichever order was used.
+    let version = candidates[0].1.min(candidates[1].1);
+    if version == 0 {
+        Err(anyhow!("archive header has invalid format version 0"))
+    } else {
+        Err(anyhow!(
+            "archive format version {version} is newer than this runtime supports (up to {MAX_SUPPORTED_VERSION})"
+        ))
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum ArchiveEndian {
     Little,
@@ -167,7 +595,6 @@ impl ArchiveEndian {
         }
     }
 
-    #[cfg(test)]
     fn encode_u32(self, value: u32) -> [u8; 4] {
         match self {
             ArchiveEndian::Little => value.to_le_bytes(),
@@ -175,7 +602,6 @@ impl ArchiveEndian {
         }
     }
 
-    #[cfg(test)]
     fn encode_u64(self, value: u64) -> [u8; 8] {
         match self {
             ArchiveEndian::Little => value.to_le_bytes(),
@@ -184,46 +610,66 @@ impl ArchiveEndian {
     }
 }
 
+/// Parses a version 1 archive, or the checksummed body of a version 2 one,
+/// stored in `endian` byte order.
 fn parse_archive_bytes(
     data: &[u8],
-    version_bytes: [u8; 4],
+    endian: ArchiveEndian,
     toc_bytes: [u8; 8],
-) -> Result<(ArchiveEndian, u32, u64, Vec<ArchiveFileEntry>, String)> {
+    search: TocSearch,
+) -> Result<(u64, Vec<ArchiveFileEntry>, String)> {
     let mut last_error = None;
     let file_len = data.len() as u64;
 
-    for endian in [ArchiveEndian::Little, ArchiveEndian::Big] {
-        let version = endian.decode_u32(version_bytes);
-        let toc_offset = endian.decode_u64(toc_bytes);
-        if (16..=file_len.saturating_sub(16)).contains(&toc_offset) {
-            match parse_toc_block(data, endian, toc_offset) {
+    let toc_offset = endian.decode_u64(toc_bytes);
+    if search == TocSearch::HeaderOnly {
+        let (files, scene_offset, scene_size) = parse_toc_block(data, endian, toc_offset)
+            .context("archive header does not point at a valid table of contents")?;
+        let scene_xml = extract_scene(data, scene_offset, scene_size)?;
+        return Ok((toc_offset, files, scene_xml));
+    }
+    if (16..=file_len.saturating_sub(16)).contains(&toc_offset) {
+        match parse_toc_block(data, endian, toc_offset) {
+            Ok((files, scene_offset, scene_size)) => {
+                let scene_xml = extract_scene(data, scene_offset, scene_size)?;
+                return Ok((toc_offset, files, scene_xml));
+            }
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    // Legacy archives leave the header offset at zero and append a
+    // `CEND` footer instead; everything before it is a regular archive.
+    let footer = data
+        .len()
+        .checked_sub(FOOTER_LEN)
+        .filter(|start| *start >= 16 && &data[*start..*start + 4] == FOOTER_MAGIC);
+    let body = match footer {
+        Some(start) => {
+            let body = &data[..start];
+            let offset_bytes: [u8; 8] = data[start + 4..].try_into().expect("footer length");
+            let toc_offset = endian.decode_u64(offset_bytes);
+            match parse_toc_block(body, endian, toc_offset) {
                 Ok((files, scene_offset, scene_size)) => {
-                    let scene_xml = extract_scene(data, scene_offset, scene_size)?;
-                    return Ok((endian, version, toc_offset, files, scene_xml));
+                    let scene_xml = extract_scene(body, scene_offset, scene_size)?;
+                    return Ok((toc_offset, files, scene_xml));
                 }
                 Err(err) => last_error = Some(err),
             }
+            body
         }
-    }
+        None => data,
+    };
 
-    for endian in [ArchiveEndian::Little, ArchiveEndian::Big] {
-        let version = endian.decode_u32(version_bytes);
-        match locate_toc_by_scanning(data, endian) {
-            Ok((toc_offset, files, scene_offset, scene_size)) => {
-                let scene_xml = extract_scene(data, scene_offset, scene_size)?;
-                return Ok((endian, version, toc_offset, files, scene_xml));
-            }
-            Err(err) => last_error = Some(err),
+    match locate_toc_by_scanning(body, endian) {
+        Ok((toc_offset, files, scene_offset, scene_size)) => {
+            let scene_xml = extract_scene(body, scene_offset, scene_size)?;
+            Ok((toc_offset, files, scene_xml))
         }
+        // A table that was found but failed to parse explains more than a
+        // failed scan does.
+        Err(err) => Err(last_error.unwrap_or(err)),
     }
-
-    Err(last_error.unwrap_or_else(|| {
-        anyhow!(
-            "unable to locate archive table of contents (file length {file_len}, little {}, big {})",
-            u64::from_le_bytes(toc_bytes),
-            u64::from_be_bytes(toc_bytes)
-        )
-    }))
 }
 
 fn parse_toc_block(
@@ -490,6 +936,22 @@ mod tests {
         assert_eq!(archive.files()[0].name, "scripts/test.lua");
     }
 
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        for endian in [ArchiveEndian::Little, ArchiveEndian::Big] {
+            let mut buffer = build_archive_buffer(endian, &[("scripts/test.lua", b"return 1")]);
+            buffer[4..8].copy_from_slice(&endian.encode_u32(MAX_SUPPORTED_VERSION + 1));
+            let err = CGameArchive::from_bytes("future", buffer).unwrap_err();
+            assert!(
+                err.to_string().contains(&format!(
+                    "format version {} is newer",
+                    MAX_SUPPORTED_VERSION + 1
+                )),
+                "{err}"
+            );
+        }
+    }
+
     #[test]
     fn extract_file_returns_bytes() {
         let (_tmp, archive) =
@@ -498,6 +960,145 @@ mod tests {
         assert_eq!(bytes, b"return 42");
     }
 
+    #[test]
+    fn extract_all_text_collects_failures() {
+        let (_tmp, archive) = create_archive(
+            ArchiveEndian::Little,
+            &[
+                ("scripts/good.lua", b"print('ok')"),
+                ("scripts/bad.lua", b"\xff\xfe"),
+                ("models/cube.obj", b"v 0 0 0"),
+            ],
+        );
+        let (texts, failures) = archive.extract_all_text("scripts/");
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts["scripts/good.lua"], "print('ok')");
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "scripts/bad.lua");
+        assert!(failures[0].1.contains("UTF-8"));
+    }
+
+    #[test]
+    fn from_reader_reads_stream() {
+        let buffer =
+            build_archive_buffer(ArchiveEndian::Little, &[("scripts/test.lua", b"return 1")]);
+        let archive = CGameArchive::from_reader("stream", std::io::Cursor::new(buffer)).unwrap();
+        assert_eq!(
+            archive.extract_file("scripts/test.lua").unwrap(),
+            b"return 1"
+        );
+    }
+
+    #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+    #[test]
+    fn open_maps_file_when_enabled() {
+        let (_tmp, archive) =
+            create_archive(ArchiveEndian::Little, &[("scripts/test.lua", b"return 7")]);
+        assert!(matches!(archive.backing, ArchiveBacking::Mapped { .. }));
+        assert_eq!(
+            archive.extract_file("scripts/test.lua").unwrap(),
+            b"return 7"
+        );
+    }
+
+    #[test]
+    fn from_directory_serves_loose_files() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        std::fs::write(dir.path().join("scene.xml"), SCENE_XML.as_str()).unwrap();
+        std::fs::create_dir(dir.path().join("models")).unwrap();
+        std::fs::write(dir.path().join("models/cube.obj"), b"v 0 0 0\n").unwrap();
+
+        let archive = CGameArchive::from_directory(dir.path()).expect("directory archive");
+        assert_eq!(archive.scene_xml(), SCENE_XML.as_str());
+        let names: Vec<_> = archive.files().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["models/cube.obj"]);
+        assert_eq!(
+            archive.extract_file("models/cube.obj").unwrap(),
+            b"v 0 0 0\n"
+        );
+
+        let empty = tempfile::tempdir().expect("temp dir");
+        assert!(CGameArchive::from_directory(empty.path()).is_err());
+    }
+
+    #[test]
+    fn builder_writes_little_endian_archives() {
+        let builder = CGameArchiveBuilder::new(SCENE_XML.as_str())
+            .file("models/cube.obj", b"v 0 0 0".to_vec())
+            .file("scripts/main.lua", "print('old')")
+            .file("scripts/main.lua", "print('new')");
+        let bytes = builder.to_bytes();
+        assert_eq!(&bytes[4..8], &2u32.to_le_bytes());
+
+        let tmp = write_archive(&bytes);
+        let archive = CGameArchive::open_strict(tmp.path()).expect("strict open");
+        assert_eq!(archive.scene_xml(), SCENE_XML.as_str());
+        assert_eq!(archive.files().len(), 2);
+        assert_eq!(
+            archive.extract_file("scripts/main.lua").unwrap(),
+            b"print('new')"
+        );
+    }
+
+    #[test]
+    fn cached_archives_are_shared_until_bytes_change() {
+        let bytes = CGameArchiveBuilder::new(SCENE_XML.as_str()).to_bytes();
+        let first = CGameArchive::from_bytes_cached("cache-test", bytes.clone()).unwrap();
+        let second = CGameArchive::from_bytes_cached("cache-test", bytes.clone()).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let changed = CGameArchiveBuilder::new(SCENE_XML.as_str())
+            .file("scripts/main.lua", "print('hi')")
+            .to_bytes();
+        let third = CGameArchive::from_bytes_cached("cache-test", changed).unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert_eq!(third.files().len(), 1);
+
+        assert!(CGameArchive::remove_cached("cache-test"));
+        assert!(!CGameArchive::remove_cached("cache-test"));
+        let fresh = CGameArchive::from_bytes_cached("cache-test", bytes).unwrap();
+        assert!(!Arc::ptr_eq(&first, &fresh));
+    }
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn corrupted_v2_archive_reports_checksum_mismatch() {
+        let mut bytes = CGameArchiveBuilder::new(SCENE_XML.as_str())
+            .file("scripts/main.lua", "print('hi')")
+            .to_bytes();
+        assert_eq!(
+            CGameArchive::from_bytes("intact", bytes.clone())
+                .expect("intact archive")
+                .version(),
+            2
+        );
+
+        bytes[20] ^= 0x01;
+        let err = CGameArchive::from_bytes("corrupt", bytes.clone()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ArchiveError>(),
+            Some(ArchiveError::ChecksumMismatch { .. })
+        ));
+
+        bytes.truncate(bytes.len() - 10);
+        let err = CGameArchive::from_bytes("truncated", bytes).unwrap_err();
+        assert!(err.downcast_ref::<ArchiveError>().is_some(), "{err}");
+    }
+
+    #[test]
+    fn open_strict_rejects_archives_needing_recovery() {
+        let mut buffer = build_archive_buffer(ArchiveEndian::Little, &[("a.txt", b"a")]);
+        buffer[8..16].copy_from_slice(&0u64.to_le_bytes());
+        let tmp = write_archive(&buffer);
+        assert!(CGameArchive::open(tmp.path()).is_ok());
+        assert!(CGameArchive::open_strict(tmp.path()).is_err());
+    }
+
     #[test]
     fn extract_missing_file_is_error() {
         let (_tmp, archive) = create_archive(ArchiveEndian::Little, &[]);
@@ -525,4 +1126,23 @@ mod tests {
         assert_eq!(archive.files().len(), 1);
         assert_eq!(archive.files()[0].name, "scripts/test.lua");
     }
+
+    #[test]
+    fn locate_toc_from_footer() {
+        for endian in [ArchiveEndian::Little, ArchiveEndian::Big] {
+            let mut buffer = build_archive_buffer(endian, &[("scripts/test.lua", b"print('hi')")]);
+            let toc_offset: [u8; 8] = buffer[8..16].try_into().unwrap();
+            buffer[8..16].copy_from_slice(&[0; 8]);
+            buffer.extend_from_slice(FOOTER_MAGIC);
+            buffer.extend_from_slice(&toc_offset);
+
+            let archive = CGameArchive::from_bytes("footer", buffer).expect("open archive");
+            assert_eq!(archive.scene_xml(), SCENE_XML.as_str());
+            assert_eq!(archive.files().len(), 1);
+            assert_eq!(
+                archive.extract_file("scripts/test.lua").unwrap(),
+                b"print('hi')"
+            );
+        }
+    }
 }
@@ -0,0 +1,146 @@
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::input::{InputState, MouseButton};
+use crate::render::CameraParams;
+
+const MIN_DISTANCE: f32 = 0.5;
+const MAX_DISTANCE: f32 = 90.0;
+const MAX_PITCH: f32 = 89.0;
+
+/// Mouse-driven camera that orbits a target point.
+///
+/// Dragging with the left mouse button rotates around the target and the
+/// scroll wheel zooms. The controller only reads [`InputState`] plus the
+/// scroll delta reported by the host, so it works with any windowing layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrbitController {
+    pub target: Vec3,
+    pub distance: f32,
+    /// Horizontal angle around the target, in degrees.
+    pub yaw: f32,
+    /// Vertical angle above the target, in degrees.
+    pub pitch: f32,
+    pub fov: f32,
+    /// Degrees of rotation per pixel of mouse movement.
+    pub rotate_speed: f32,
+    /// Fraction of the current distance covered per scroll step.
+    pub zoom_speed: f32,
+    last_mouse: Option<Vec2>,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            distance: 6.0,
+            yaw: 0.0,
+            pitch: 20.0,
+            fov: 60.0,
+            rotate_speed: 0.3,
+            zoom_speed: 0.1,
+            last_mouse: None,
+        }
+    }
+}
+
+impl OrbitController {
+    pub fn new(target: Vec3, distance: f32) -> Self {
+        Self {
+            target,
+            distance: distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+            ..Self::default()
+        }
+    }
+
+    /// Applies one frame of input: left-drag orbits, scrolling zooms.
+    pub fn update(&mut self, input: &InputState, scroll_delta: f32) {
+        let mouse = input.mouse_position();
+        if input.is_mouse_button_down(MouseButton::LEFT) {
+            if let Some(last) = self.last_mouse {
+                let delta = mouse - last;
+                self.yaw -= delta.x * self.rotate_speed;
+                self.pitch =
+                    (self.pitch + delta.y * self.rotate_speed).clamp(-MAX_PITCH, MAX_PITCH);
+            }
+            self.last_mouse = Some(mouse);
+        } else {
+            self.last_mouse = None;
+        }
+
+        if scroll_delta != 0.0 {
+            let factor = (1.0 - scroll_delta * self.zoom_speed).max(0.1);
+            self.distance = (self.distance * factor).clamp(MIN_DISTANCE, MAX_DISTANCE);
+        }
+    }
+
+    /// Returns the world-space position of the orbiting eye.
+    pub fn eye(&self) -> Vec3 {
+        let yaw = self.yaw.to_radians();
+        let pitch = self.pitch.to_radians();
+        let offset = Vec3::new(
+            pitch.cos() * yaw.sin(),
+            pitch.sin(),
+            pitch.cos() * yaw.cos(),
+        );
+        self.target + offset * self.distance
+    }
+
+    /// Builds the camera uniforms for the current orbit state.
+    pub fn camera(&self, aspect: f32) -> CameraParams {
+        let position = self.eye();
+        let view = Mat4::look_at_rh(position, self.target, Vec3::Y);
+        let projection =
+            Mat4::perspective_rh_gl(self.fov.to_radians(), aspect.max(0.01), 0.1, 100.0);
+        CameraParams {
+            view_proj: projection * view,
+            position,
+            view,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_eye_sits_in_front_of_target() {
+        let controller = OrbitController {
+            pitch: 0.0,
+            ..OrbitController::default()
+        };
+        assert!((controller.eye() - Vec3::new(0.0, 0.0, 6.0)).length() < 1e-5);
+        assert_eq!(controller.camera(1.0).position, controller.eye());
+    }
+
+    #[test]
+    fn dragging_rotates_and_release_resets_anchor() {
+        let input = InputState::new();
+        let mut controller = OrbitController::default();
+        input.set_mouse_button_down(MouseButton::LEFT);
+        input.set_mouse_position(Vec2::new(100.0, 100.0));
+        controller.update(&input, 0.0);
+        input.set_mouse_position(Vec2::new(110.0, 100.0));
+        controller.update(&input, 0.0);
+        assert!((controller.yaw - -3.0).abs() < 1e-5);
+
+        input.set_mouse_button_up(MouseButton::LEFT);
+        controller.update(&input, 0.0);
+        input.set_mouse_button_down(MouseButton::LEFT);
+        input.set_mouse_position(Vec2::new(500.0, 100.0));
+        controller.update(&input, 0.0);
+        assert!((controller.yaw - -3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn scrolling_zooms_within_limits() {
+        let input = InputState::new();
+        let mut controller = OrbitController::default();
+        controller.update(&input, 1.0);
+        assert!(controller.distance < 6.0);
+        for _ in 0..100 {
+            controller.update(&input, 5.0);
+        }
+        assert_eq!(controller.distance, MIN_DISTANCE);
+    }
+}
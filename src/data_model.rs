@@ -1,20 +1,64 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 
-use glam::Vec3;
-use parking_lot::RwLock;
+use glam::{EulerRot, Mat4, Vec3};
+use parking_lot::{RwLock, RwLockReadGuard};
 
-use crate::scene::SceneObject;
+use crate::scene::{self, AttributeValue, Light, SceneObject, Shading, UpAxis};
+
+/// Reports whether [`DataModel::upsert`] added or replaced an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    Created,
+    Updated,
+}
+
+/// Callback run after objects are added to or removed from a [`DataModel`].
+pub type StructureCallback = Arc<dyn Fn() + Send + Sync>;
+
+/// Subscribers registered with [`DataModel::on_structure_change`].
+#[derive(Default)]
+struct StructureListeners(RwLock<Vec<StructureCallback>>);
+
+impl std::fmt::Debug for StructureListeners {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} listener(s)", self.0.read().len())
+    }
+}
 
 /// Thread-safe container mirroring the mutable state of the scene graph.
+///
+/// Objects are kept in insertion order unless
+/// [`set_keep_sorted`](Self::set_keep_sorted) is enabled, in which case they
+/// stay sorted by name however the model is mutated.
 #[derive(Debug, Default)]
 pub struct DataModel {
     objects: Arc<RwLock<Vec<SceneObject>>>,
+    active_camera: Arc<RwLock<Option<String>>>,
+    keep_sorted: Arc<AtomicBool>,
+    /// Index of the first object of each type, rebuilt lazily after any
+    /// change that can add, remove, reorder or retype objects.
+    first_by_type: Arc<RwLock<Option<HashMap<String, usize>>>>,
+    /// Meters per scene unit; `None` until set, meaning `1.0`.
+    scene_scale: Arc<RwLock<Option<f32>>>,
+    up_axis: Arc<RwLock<UpAxis>>,
+    /// Bumped by every mutation, see [`DataModel::revision`].
+    revision: Arc<AtomicU64>,
+    structure_listeners: Arc<StructureListeners>,
 }
 
 impl Clone for DataModel {
     fn clone(&self) -> Self {
         Self {
             objects: Arc::clone(&self.objects),
+            active_camera: Arc::clone(&self.active_camera),
+            keep_sorted: Arc::clone(&self.keep_sorted),
+            first_by_type: Arc::clone(&self.first_by_type),
+            scene_scale: Arc::clone(&self.scene_scale),
+            up_axis: Arc::clone(&self.up_axis),
+            revision: Arc::clone(&self.revision),
+            structure_listeners: Arc::clone(&self.structure_listeners),
         }
     }
 }
@@ -29,12 +73,191 @@ impl DataModel {
     pub fn from_objects(objects: Vec<SceneObject>) -> Self {
         Self {
             objects: Arc::new(RwLock::new(objects)),
+            active_camera: Arc::default(),
+            keep_sorted: Arc::default(),
+            first_by_type: Arc::default(),
+            scene_scale: Arc::default(),
+            up_axis: Arc::default(),
+            revision: Arc::default(),
+            structure_listeners: Arc::default(),
         }
     }
 
+    /// Sets the meters per scene unit used when building the camera and
+    /// light, usually [`Scene::scale`](crate::Scene::scale).
+    pub fn set_scene_scale(&self, scale: f32) {
+        *self.scene_scale.write() = Some(scale);
+        self.mark_changed();
+    }
+
+    pub fn scene_scale(&self) -> f32 {
+        self.scene_scale.read().unwrap_or(1.0)
+    }
+
+    /// Sets the up axis the camera and light positions were authored
+    /// with, usually [`Scene::up_axis`](crate::Scene::up_axis).
+    pub fn set_up_axis(&self, up_axis: UpAxis) {
+        *self.up_axis.write() = up_axis;
+        self.mark_changed();
+    }
+
+    pub fn up_axis(&self) -> UpAxis {
+        *self.up_axis.read()
+    }
+
+    /// Counter that changes whenever objects, the active camera or scene
+    /// settings do, shared by every clone.
+    ///
+    /// Hosts compare it with the value seen at their last frame to skip
+    /// redrawing a scene nothing touched. Writes through
+    /// [`update`](Self::update) count even when they store equal values.
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Acquire)
+    }
+
+    fn mark_changed(&self) {
+        self.revision.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Encodes every object as a compact bincode snapshot, for editor
+    /// checkpoints and undo history. Use XML for interchange; the snapshot
+    /// layout follows [`SceneObject`] and is only readable by the same build.
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(&self) -> Vec<u8> {
+        bincode::serialize(&*self.objects.read()).expect("scene objects always encode")
+    }
+
+    /// Creates a data model from a snapshot written by [`Self::to_bincode`].
+    #[cfg(feature = "bincode")]
+    pub fn from_bincode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let objects = bincode::deserialize(bytes)
+            .map_err(|err| anyhow::anyhow!("invalid data model snapshot: {err}"))?;
+        Ok(Self::from_objects(objects))
+    }
+
     /// Replaces the stored objects with a new snapshot.
-    pub fn replace_objects(&self, objects: Vec<SceneObject>) {
-        *self.objects.write() = objects;
+    pub fn replace_objects(&self, mut objects: Vec<SceneObject>) {
+        if self.keeps_sorted() {
+            sort_objects(&mut objects);
+        }
+        let mut guard = self.objects.write();
+        *guard = objects;
+        self.invalidate_type_index();
+        drop(guard);
+        self.mark_changed();
+        self.notify_structure_change();
+    }
+
+    /// Registers `callback` to run whenever objects are added or removed:
+    /// by [`upsert`](Self::upsert) creating one, [`remove`](Self::remove),
+    /// [`clear`](Self::clear) or [`replace_objects`](Self::replace_objects).
+    /// Property edits do not trigger it. Callbacks run on the mutating
+    /// thread after the model is unlocked, so they may read it.
+    pub fn on_structure_change(&self, callback: StructureCallback) {
+        self.structure_listeners.0.write().push(callback);
+    }
+
+    fn notify_structure_change(&self) {
+        let listeners = self.structure_listeners.0.read().clone();
+        for listener in listeners {
+            listener();
+        }
+    }
+
+    /// Sorts the stored objects by name once; later insertions keep
+    /// insertion order unless [`set_keep_sorted`](Self::set_keep_sorted) is on.
+    pub fn sort_by_name(&self) {
+        let mut guard = self.objects.write();
+        sort_objects(&mut guard);
+        self.invalidate_type_index();
+        drop(guard);
+        self.mark_changed();
+    }
+
+    /// Keeps objects sorted by name on every insertion and replacement, so
+    /// enumeration order does not depend on mutation history. Enabling it
+    /// sorts the current objects. Shared by every clone of this model.
+    pub fn set_keep_sorted(&self, enabled: bool) {
+        self.keep_sorted.store(enabled, Ordering::Release);
+        if enabled {
+            self.sort_by_name();
+        }
+    }
+
+    fn keeps_sorted(&self) -> bool {
+        self.keep_sorted.load(Ordering::Acquire)
+    }
+
+    /// Removes every stored object.
+    pub fn clear(&self) {
+        let removed = {
+            let mut guard = self.objects.write();
+            let removed = !guard.is_empty();
+            guard.clear();
+            self.invalidate_type_index();
+            removed
+        };
+        if removed {
+            self.mark_changed();
+            self.notify_structure_change();
+        }
+    }
+
+    /// Removes the object named `name`, returning it if it existed.
+    pub fn remove(&self, name: &str) -> Option<SceneObject> {
+        let removed = {
+            let mut guard = self.objects.write();
+            let index = guard.iter().position(|object| object.name == name)?;
+            self.invalidate_type_index();
+            guard.remove(index)
+        };
+        self.mark_changed();
+        self.notify_structure_change();
+        Some(removed)
+    }
+
+    /// Returns the number of stored objects without cloning them.
+    pub fn len(&self) -> usize {
+        self.objects.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.read().is_empty()
+    }
+
+    /// Selects the camera to render from by name; `None` reverts to the
+    /// first camera in the scene. Unknown names also fall back.
+    pub fn set_active_camera(&self, name: Option<&str>) {
+        *self.active_camera.write() = name.map(str::to_string);
+        self.mark_changed();
+    }
+
+    /// Returns the camera selected with [`set_active_camera`](Self::set_active_camera).
+    pub fn active_camera(&self) -> Option<String> {
+        self.active_camera.read().clone()
+    }
+
+    /// Borrows the stored objects in place, avoiding the clone made by
+    /// [`all_objects`](Self::all_objects).
+    ///
+    /// Writers (scripts, setters) block until the guard is dropped, so keep
+    /// it for as short a time as possible.
+    pub fn read(&self) -> RwLockReadGuard<'_, Vec<SceneObject>> {
+        self.objects.read()
+    }
+
+    /// Returns a copy of the object at `index` in storage order, the order
+    /// [`all_objects`](Self::all_objects) and scripts enumerate.
+    pub fn object_at(&self, index: usize) -> Option<SceneObject> {
+        self.objects.read().get(index).cloned()
+    }
+
+    /// Returns the storage-order position of the object named `name`.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.objects
+            .read()
+            .iter()
+            .position(|object| object.name == name)
     }
 
     /// Returns a snapshot of all stored objects.
@@ -42,13 +265,103 @@ impl DataModel {
         self.objects.read().clone()
     }
 
+    /// Returns the names of all stored objects, in storage order.
+    pub fn object_names(&self) -> Vec<String> {
+        self.objects
+            .read()
+            .iter()
+            .map(|object| object.name.clone())
+            .collect()
+    }
+
     /// Returns a clone of the requested object.
     pub fn get(&self, name: &str) -> Option<SceneObject> {
+        self.find(|object| object.name == name)
+    }
+
+    /// Returns a clone of the first object whose type is `object_type`.
+    ///
+    /// Backed by a per-type index, so per-frame camera and light lookups
+    /// avoid scanning the whole scene.
+    pub fn first_of_type(&self, object_type: &str) -> Option<SceneObject> {
+        let objects = self.objects.read();
+        if let Some(index) = self.first_by_type.read().as_ref() {
+            return index
+                .get(object_type)
+                .and_then(|&position| objects.get(position).cloned());
+        }
+        let mut index = HashMap::new();
+        for (position, object) in objects.iter().enumerate() {
+            index.entry(object.object_type.clone()).or_insert(position);
+        }
+        let first = index
+            .get(object_type)
+            .and_then(|&position| objects.get(position).cloned());
+        *self.first_by_type.write() = Some(index);
+        first
+    }
+
+    /// Drops the per-type index. Call it while still holding the `objects`
+    /// write guard, so no reader can pair the old index with new objects.
+    fn invalidate_type_index(&self) {
+        *self.first_by_type.write() = None;
+    }
+
+    /// Returns a clone of the first object matching the predicate.
+    pub fn find<F>(&self, predicate: F) -> Option<SceneObject>
+    where
+        F: Fn(&SceneObject) -> bool,
+    {
         self.objects
             .read()
             .iter()
-            .find(|object| object.name == name)
+            .find(|object| predicate(object))
+            .cloned()
+    }
+
+    /// Returns clones of every object matching the predicate, in storage order.
+    pub fn filter<F>(&self, predicate: F) -> Vec<SceneObject>
+    where
+        F: Fn(&SceneObject) -> bool,
+    {
+        self.objects
+            .read()
+            .iter()
+            .filter(|object| predicate(object))
             .cloned()
+            .collect()
+    }
+
+    /// Returns the current parameters of every light, in storage order.
+    pub fn lights(&self) -> Vec<Light> {
+        let objects = self.objects.read();
+        objects
+            .iter()
+            .zip(scene::world_matrices(&objects))
+            .filter_map(|(object, world)| Light::from_object(object, world))
+            .collect()
+    }
+
+    /// Returns the object's transform with its parent chain applied.
+    pub fn world_matrix(&self, name: &str) -> Option<Mat4> {
+        let objects = self.objects.read();
+        let object = objects.iter().find(|object| object.name == name)?;
+        Some(scene::world_matrix(&objects, object))
+    }
+
+    /// Returns the names of objects whose world position lies within
+    /// `radius` of `center`, in storage order.
+    ///
+    /// This is a linear scan; large scenes may later want a spatial index.
+    pub fn within_radius(&self, center: Vec3, radius: f32) -> Vec<String> {
+        let objects = self.objects.read();
+        let radius_squared = radius * radius;
+        objects
+            .iter()
+            .zip(scene::world_matrices(&objects))
+            .filter(|(_, world)| world.w_axis.truncate().distance_squared(center) <= radius_squared)
+            .map(|(object, _)| object.name.clone())
+            .collect()
     }
 
     /// Applies a mutation to the requested object.
@@ -58,19 +371,101 @@ impl DataModel {
     {
         let mut guard = self.objects.write();
         let object = guard.iter_mut().find(|object| object.name == name)?;
-        Some(updater(object))
+        let object_type = object.object_type.clone();
+        let result = updater(object);
+        if object.object_type != object_type {
+            self.invalidate_type_index();
+        }
+        self.mark_changed();
+        Some(result)
+    }
+
+    /// Replaces the object with the same name, or appends it if none exists.
+    /// New objects are inserted at their sorted position when the model
+    /// keeps objects sorted.
+    pub fn upsert(&self, object: SceneObject) -> UpsertOutcome {
+        let keep_sorted = self.keeps_sorted();
+        let mut guard = self.objects.write();
+        self.invalidate_type_index();
+        let outcome = match guard
+            .iter_mut()
+            .find(|existing| existing.name == object.name)
+        {
+            Some(existing) => {
+                *existing = object;
+                UpsertOutcome::Updated
+            }
+            None if keep_sorted => {
+                let index = guard.partition_point(|existing| existing.name < object.name);
+                guard.insert(index, object);
+                UpsertOutcome::Created
+            }
+            None => {
+                guard.push(object);
+                UpsertOutcome::Created
+            }
+        };
+        drop(guard);
+        self.mark_changed();
+        if outcome == UpsertOutcome::Created {
+            self.notify_structure_change();
+        }
+        outcome
+    }
+
+    /// Sets the local transform of many objects under a single write lock,
+    /// for hosts driving the scene from their own simulation.
+    ///
+    /// Each matrix is decomposed into position, rotation and scale (any
+    /// baked `matrix` is cleared). Returns how many names were found.
+    pub fn apply_transforms(&self, transforms: &[(String, Mat4)]) -> usize {
+        let mut guard = self.objects.write();
+        let positions: HashMap<&str, usize> = guard
+            .iter()
+            .enumerate()
+            .map(|(index, object)| (object.name.as_str(), index))
+            .collect();
+        let targets: Vec<(usize, &Mat4)> = transforms
+            .iter()
+            .filter_map(|(name, matrix)| Some((*positions.get(name.as_str())?, matrix)))
+            .collect();
+        for &(index, matrix) in &targets {
+            set_local_matrix(&mut guard[index], matrix);
+        }
+        if !targets.is_empty() {
+            self.mark_changed();
+        }
+        targets.len()
     }
 
+    /// Sets the local position. A baked `matrix` is first decomposed into
+    /// position, rotation and scale, so the other two are kept.
     pub fn set_position(&self, name: &str, position: Vec3) -> bool {
-        self.update(name, |obj| obj.position = position).is_some()
+        self.update(name, |obj| {
+            unbake_matrix(obj);
+            obj.position = position;
+        })
+        .is_some()
     }
 
+    /// Sets the local rotation in degrees, decomposing a baked `matrix`
+    /// like [`set_position`](Self::set_position).
     pub fn set_rotation(&self, name: &str, rotation: Vec3) -> bool {
-        self.update(name, |obj| obj.rotation = rotation).is_some()
+        self.update(name, |obj| {
+            unbake_matrix(obj);
+            obj.rotation = rotation;
+        })
+        .is_some()
     }
 
+    /// Sets the local scale, decomposing a baked `matrix` like
+    /// [`set_position`](Self::set_position).
     pub fn set_scale(&self, name: &str, scale: Vec3) -> bool {
-        self.update(name, |obj| obj.scale = scale).is_some()
+        self.update(name, |obj| {
+            unbake_matrix(obj);
+            obj.scale = scale;
+        })
+        .is_some()
     }
 
     pub fn set_color(&self, name: &str, color: Vec3) -> bool {
@@ -84,6 +479,64 @@ impl DataModel {
     pub fn set_intensity(&self, name: &str, intensity: f32) -> bool {
         self.update(name, |obj| obj.intensity = intensity).is_some()
     }
+
+    pub fn set_shading(&self, name: &str, shading: Shading) -> bool {
+        self.update(name, |obj| obj.shading = shading).is_some()
+    }
+
+    pub fn set_emissive(&self, name: &str, emissive: Vec3) -> bool {
+        self.update(name, |obj| obj.emissive = emissive).is_some()
+    }
+
+    pub fn set_wireframe(&self, name: &str, wireframe: bool) -> bool {
+        self.update(name, |obj| obj.wireframe = wireframe).is_some()
+    }
+
+    /// Sets a custom attribute, returning `false` if no object is named `name`.
+    pub fn set_attribute(&self, name: &str, key: &str, value: impl Into<AttributeValue>) -> bool {
+        let value = value.into();
+        self.update(name, |obj| {
+            obj.attributes.insert(key.to_string(), value.clone());
+        })
+        .is_some()
+    }
+
+    /// Removes a custom attribute, returning its previous value.
+    pub fn remove_attribute(&self, name: &str, key: &str) -> Option<AttributeValue> {
+        self.update(name, |obj| obj.attributes.remove(key))
+            .flatten()
+    }
+
+    pub fn get_attribute(&self, name: &str, key: &str) -> Option<AttributeValue> {
+        self.read()
+            .iter()
+            .find(|object| object.name == name)
+            .and_then(|object| object.attributes.get(key).cloned())
+    }
+}
+
+/// Stores `matrix` as the object's position, rotation and scale, clearing
+/// any baked `matrix`.
+fn set_local_matrix(object: &mut SceneObject, matrix: &Mat4) {
+    let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+    let (z, y, x) = rotation.to_euler(EulerRot::ZYX);
+    object.position = translation;
+    object.rotation = Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees());
+    object.scale = scale;
+    object.matrix = None;
+}
+
+/// Replaces a baked `matrix` with the equivalent position, rotation and
+/// scale, so editing one of them takes effect.
+fn unbake_matrix(object: &mut SceneObject) {
+    if let Some(matrix) = object.matrix {
+        set_local_matrix(object, &matrix);
+    }
+}
+
+/// Stable sort, so objects sharing a name keep their relative order.
+fn sort_objects(objects: &mut [SceneObject]) {
+    objects.sort_by(|a, b| a.name.cmp(&b.name));
 }
 
 #[cfg(test)]
@@ -98,6 +551,102 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_snapshot_round_trips() {
+        let mut child = make_object("Child");
+        child.parent = Some("Root".into());
+        child.mesh = Some("meshes/rock.obj".into());
+        child.shading = Shading::Cutout { threshold: 0.3 };
+        child.matrix = Some(Mat4::from_translation(Vec3::X));
+        let model = DataModel::from_objects(vec![make_object("Root"), child]);
+
+        let bytes = model.to_bincode();
+        let restored = DataModel::from_bincode(&bytes).unwrap();
+        assert_eq!(restored.all_objects(), model.all_objects());
+        assert!(DataModel::from_bincode(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn transform_setters_replace_a_baked_matrix() {
+        let mut object = make_object("Crate");
+        object.matrix = Some(Mat4::from_scale_rotation_translation(
+            Vec3::splat(2.0),
+            glam::Quat::from_rotation_y(30f32.to_radians()),
+            Vec3::new(1.0, 2.0, 3.0),
+        ));
+        let model = DataModel::from_objects(vec![object]);
+
+        assert!(model.set_position("Crate", Vec3::new(5.0, 0.0, 0.0)));
+        let moved = model.get("Crate").unwrap();
+        assert!(moved.matrix.is_none());
+        assert_eq!(moved.position, Vec3::new(5.0, 0.0, 0.0));
+        assert!(moved.scale.abs_diff_eq(Vec3::splat(2.0), 1e-5));
+        assert!((moved.rotation.y - 30.0).abs() < 1e-3);
+        assert_eq!(
+            moved.local_matrix().w_axis.truncate(),
+            Vec3::new(5.0, 0.0, 0.0)
+        );
+
+        assert!(model.set_scale("Crate", Vec3::ONE));
+        assert_eq!(model.get("Crate").unwrap().scale, Vec3::ONE);
+    }
+
+    #[test]
+    fn objects_are_addressable_by_index() {
+        let model = DataModel::new();
+        model.upsert(make_object("Cube"));
+        model.upsert(make_object("Sphere"));
+        assert_eq!(model.object_at(1).unwrap().name, "Sphere");
+        assert!(model.object_at(2).is_none());
+        assert_eq!(model.index_of("Cube"), Some(0));
+        assert_eq!(model.index_of("Missing"), None);
+    }
+
+    #[test]
+    fn revision_changes_on_writes_only() {
+        let model = DataModel::from_objects(vec![make_object("Cube")]);
+        let start = model.revision();
+        assert!(model.get("Cube").is_some());
+        assert!(!model.set_position("Missing", Vec3::ONE));
+        assert_eq!(model.revision(), start);
+
+        assert!(model.set_position("Cube", Vec3::ONE));
+        let moved = model.revision();
+        assert_ne!(moved, start);
+        model.clone().upsert(make_object("Sphere"));
+        assert_ne!(model.revision(), moved);
+    }
+
+    #[test]
+    fn structure_callbacks_skip_property_edits() {
+        use std::sync::atomic::AtomicUsize;
+
+        let model = DataModel::from_objects(vec![make_object("Cube")]);
+        let calls = Arc::new(AtomicUsize::new(0));
+        for _ in 0..2 {
+            let counter = Arc::clone(&calls);
+            let reader = model.clone();
+            model.on_structure_change(Arc::new(move || {
+                // Listeners run unlocked, so reading the model is fine.
+                let _ = reader.len();
+                counter.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+
+        model.set_position("Cube", Vec3::ONE);
+        model.upsert(make_object("Cube"));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        model.upsert(make_object("Sphere"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(model.remove("Sphere").is_some());
+        assert!(model.remove("Sphere").is_none());
+        model.clear();
+        model.clear();
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+
     #[test]
     fn replace_and_get_object() {
         let model = DataModel::from_objects(vec![make_object("Cube")]);
@@ -107,6 +656,93 @@ mod tests {
         assert!(model.get("Sphere").is_some());
     }
 
+    #[test]
+    fn sorting_orders_names_alphabetically() {
+        let model = DataModel::from_objects(vec![
+            make_object("Sphere"),
+            make_object("Camera"),
+            make_object("Light"),
+        ]);
+        assert_eq!(model.object_names(), vec!["Sphere", "Camera", "Light"]);
+        model.sort_by_name();
+        assert_eq!(model.object_names(), vec!["Camera", "Light", "Sphere"]);
+
+        model.upsert(make_object("Arrow"));
+        assert_eq!(model.object_names().last().unwrap(), "Arrow");
+
+        model.set_keep_sorted(true);
+        model.upsert(make_object("Box"));
+        assert_eq!(
+            model.object_names(),
+            vec!["Arrow", "Box", "Camera", "Light", "Sphere"]
+        );
+    }
+
+    #[test]
+    fn first_of_type_tracks_mutations() {
+        let mut sun = make_object("Sun");
+        sun.object_type = "light".into();
+        let model = DataModel::from_objects(vec![make_object("Cube"), sun]);
+        assert_eq!(model.first_of_type("light").unwrap().name, "Sun");
+        assert!(model.first_of_type("camera").is_none());
+
+        let mut lamp = make_object("Lamp");
+        lamp.object_type = "light".into();
+        model.replace_objects(vec![lamp, make_object("Cube")]);
+        assert_eq!(model.first_of_type("light").unwrap().name, "Lamp");
+
+        model.update("Lamp", |object| object.object_type = "mesh".into());
+        assert!(model.first_of_type("light").is_none());
+
+        model.clear();
+        assert!(model.first_of_type("mesh").is_none());
+    }
+
+    #[test]
+    fn apply_transforms_updates_several_objects() {
+        let model = DataModel::from_objects(vec![
+            make_object("Ball"),
+            make_object("Crate"),
+            make_object("Floor"),
+        ]);
+        let crate_object = SceneObject {
+            position: Vec3::new(0.0, 2.0, 0.0),
+            rotation: Vec3::new(10.0, 20.0, 30.0),
+            scale: Vec3::splat(2.0),
+            ..make_object("Crate")
+        };
+        let applied = model.apply_transforms(&[
+            ("Ball".to_string(), Mat4::from_translation(Vec3::X)),
+            ("Crate".to_string(), crate_object.local_matrix()),
+            ("Ghost".to_string(), Mat4::IDENTITY),
+        ]);
+
+        assert_eq!(applied, 2);
+        assert_eq!(model.get("Ball").unwrap().position, Vec3::X);
+        let updated = model.get("Crate").unwrap();
+        assert!(updated.position.abs_diff_eq(crate_object.position, 1e-4));
+        assert!(updated.rotation.abs_diff_eq(crate_object.rotation, 1e-3));
+        assert!(updated.scale.abs_diff_eq(crate_object.scale, 1e-4));
+        assert_eq!(model.get("Floor").unwrap().position, Vec3::ZERO);
+    }
+
+    #[test]
+    fn clear_empties_the_model() {
+        let model = DataModel::from_objects(vec![make_object("Cube"), make_object("Sphere")]);
+        assert_eq!(model.len(), 2);
+        model.clear();
+        assert_eq!(model.len(), 0);
+        assert!(model.is_empty());
+    }
+
+    #[test]
+    fn read_borrows_without_cloning() {
+        let model = DataModel::from_objects(vec![make_object("Cube"), make_object("Sphere")]);
+        let objects = model.read();
+        let names: Vec<&str> = objects.iter().map(|object| object.name.as_str()).collect();
+        assert_eq!(names, vec!["Cube", "Sphere"]);
+    }
+
     #[test]
     fn update_modifies_object() {
         let model = DataModel::from_objects(vec![make_object("Camera")]);
@@ -115,6 +751,73 @@ mod tests {
         assert_eq!(cam.fov, 60.0);
     }
 
+    #[test]
+    fn find_and_filter_by_type() {
+        let mut light = make_object("Sun");
+        light.object_type = "light".into();
+        let mut lamp = make_object("Lamp");
+        lamp.object_type = "light".into();
+        let model = DataModel::from_objects(vec![make_object("Cube"), light, lamp]);
+
+        let first = model.find(|object| object.object_type == "light").unwrap();
+        assert_eq!(first.name, "Sun");
+        let names: Vec<String> = model
+            .filter(|object| object.object_type == "light")
+            .into_iter()
+            .map(|object| object.name)
+            .collect();
+        assert_eq!(names, vec!["Sun".to_string(), "Lamp".to_string()]);
+        assert!(model
+            .find(|object| object.object_type == "camera")
+            .is_none());
+    }
+
+    #[test]
+    fn world_matrix_follows_parent() {
+        let mut parent = make_object("Parent");
+        parent.position = Vec3::new(0.0, 5.0, 0.0);
+        let mut child = make_object("Child");
+        child.parent = Some("Parent".into());
+        child.position = Vec3::new(1.0, 0.0, 0.0);
+        let model = DataModel::from_objects(vec![parent, child]);
+
+        let world = model.world_matrix("Child").unwrap();
+        assert_eq!(world.transform_point3(Vec3::ZERO), Vec3::new(1.0, 5.0, 0.0));
+        assert!(model.world_matrix("Missing").is_none());
+    }
+
+    #[test]
+    fn within_radius_uses_world_positions() {
+        let mut near = make_object("Near");
+        near.position = Vec3::new(1.0, 0.0, 0.0);
+        let mut far = make_object("Far");
+        far.position = Vec3::new(10.0, 0.0, 0.0);
+        let mut child = make_object("Child");
+        child.parent = Some("Far".into());
+        child.position = Vec3::new(-9.0, 0.0, 0.0);
+        let model = DataModel::from_objects(vec![near, far, child]);
+
+        let names = model.within_radius(Vec3::ZERO, 2.0);
+        assert_eq!(names, vec!["Near".to_string(), "Child".to_string()]);
+    }
+
+    #[test]
+    fn upsert_creates_then_updates() {
+        let model = DataModel::from_objects(vec![make_object("Cube")]);
+        assert_eq!(model.upsert(make_object("Sphere")), UpsertOutcome::Created);
+        assert_eq!(model.len(), 2);
+
+        let mut moved = make_object("Cube");
+        moved.position = Vec3::new(0.0, 3.0, 0.0);
+        assert_eq!(model.upsert(moved), UpsertOutcome::Updated);
+        assert_eq!(model.len(), 2);
+        assert_eq!(
+            model.get("Cube").unwrap().position,
+            Vec3::new(0.0, 3.0, 0.0)
+        );
+        assert_eq!(model.all_objects()[0].name, "Cube");
+    }
+
     #[test]
     fn update_returns_false_for_missing_object() {
         let model = DataModel::new();
@@ -0,0 +1,182 @@
+//! Static glTF 2.0 geometry import.
+//!
+//! Primitives are converted into the same interleaved layout as
+//! [`load_obj_from_str`](crate::load_obj_from_str) so the renderer can treat
+//! both formats alike. Materials, skins and animations are ignored.
+
+use anyhow::{anyhow, Context, Result};
+use glam::{Mat3, Mat4, Vec3};
+
+use crate::obj::{compute_normals, needs_normals, ObjMesh, VERTEX_STRIDE};
+
+/// Parses a `.glb` or `.gltf` file and returns one mesh per triangle
+/// primitive, with node transforms baked into the vertices.
+///
+/// Only the binary chunk of a `.glb` can back buffers; `.gltf` files that
+/// reference external or data URIs are rejected.
+pub fn load_gltf_from_bytes(data: &[u8]) -> Result<Vec<ObjMesh>> {
+    let gltf = ::gltf::Gltf::from_slice(data).context("invalid glTF document")?;
+    let blob = gltf.blob.as_deref();
+    let document = &gltf.document;
+
+    let mut meshes = Vec::new();
+    match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => {
+            for node in scene.nodes() {
+                load_node(&node, Mat4::IDENTITY, blob, &mut meshes)?;
+            }
+        }
+        None => {
+            for mesh in document.meshes() {
+                load_mesh(&mesh, Mat4::IDENTITY, blob, &mut meshes)?;
+            }
+        }
+    }
+
+    if meshes.is_empty() {
+        return Err(anyhow!("glTF file does not contain any triangle meshes"));
+    }
+    Ok(meshes)
+}
+
+fn load_node(
+    node: &::gltf::Node,
+    parent: Mat4,
+    blob: Option<&[u8]>,
+    meshes: &mut Vec<ObjMesh>,
+) -> Result<()> {
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        load_mesh(&mesh, transform, blob, meshes)?;
+    }
+    for child in node.children() {
+        load_node(&child, transform, blob, meshes)?;
+    }
+    Ok(())
+}
+
+fn load_mesh(
+    mesh: &::gltf::Mesh,
+    transform: Mat4,
+    blob: Option<&[u8]>,
+    meshes: &mut Vec<ObjMesh>,
+) -> Result<()> {
+    let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+    for primitive in mesh.primitives() {
+        if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+            continue;
+        }
+        let reader = primitive.reader(|buffer| match buffer.source() {
+            ::gltf::buffer::Source::Bin => blob,
+            ::gltf::buffer::Source::Uri(_) => None,
+        });
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .ok_or_else(|| {
+                anyhow!(
+                    "mesh {} has a primitive without readable positions",
+                    mesh.index()
+                )
+            })?
+            .collect();
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+        let colors: Option<Vec<[f32; 3]>> = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgb_f32().collect());
+
+        let mut vertices = Vec::with_capacity(positions.len() * VERTEX_STRIDE);
+        for (i, position) in positions.iter().enumerate() {
+            let position = transform.transform_point3(Vec3::from(*position));
+            let normal = normals
+                .as_ref()
+                .and_then(|normals| normals.get(i))
+                .map(|normal| (normal_matrix * Vec3::from(*normal)).normalize_or_zero())
+                .unwrap_or(Vec3::ZERO);
+            let color = colors
+                .as_ref()
+                .and_then(|colors| colors.get(i))
+                .map(|color| Vec3::from(*color))
+                .unwrap_or(Vec3::ONE);
+            vertices.extend_from_slice(&position.to_array());
+            vertices.extend_from_slice(&normal.to_array());
+            vertices.extend_from_slice(&color.to_array());
+        }
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let mut mesh = ObjMesh { vertices, indices };
+        if needs_normals(&mesh.vertices) {
+            compute_normals(&mut mesh);
+        }
+        meshes.push(mesh);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs a JSON chunk and a binary chunk into a `.glb` container.
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+        let mut bin = bin.to_vec();
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+        let mut out = Vec::with_capacity(total);
+        out.extend_from_slice(b"glTF");
+        out.extend_from_slice(&2u32.to_le_bytes());
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+        out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"JSON");
+        out.extend_from_slice(&json);
+        out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"BIN\0");
+        out.extend_from_slice(&bin);
+        out
+    }
+
+    #[test]
+    fn loads_triangle_with_node_transform() {
+        let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let bin: Vec<u8> = positions.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "buffers": [{"byteLength": 36}],
+            "bufferViews": [{"buffer": 0, "byteLength": 36}],
+            "accessors": [{
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+            "nodes": [{"mesh": 0, "translation": [0, 2, 0]}],
+            "scenes": [{"nodes": [0]}],
+            "scene": 0
+        }"#;
+
+        let meshes = load_gltf_from_bytes(&glb(json, &bin)).unwrap();
+        assert_eq!(meshes.len(), 1);
+        let mesh = &meshes[0];
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        let first = &mesh.vertices[..VERTEX_STRIDE];
+        assert_eq!(Vec3::from_slice(&first[0..3]), Vec3::new(0.0, 2.0, 0.0));
+        assert!((Vec3::from_slice(&first[3..6]) - Vec3::Z).length() < 1e-5);
+        assert_eq!(Vec3::from_slice(&first[6..9]), Vec3::ONE);
+    }
+
+    #[test]
+    fn rejects_documents_without_meshes() {
+        let json = r#"{"asset": {"version": "2.0"}}"#;
+        assert!(load_gltf_from_bytes(&glb(json, &[])).is_err());
+    }
+}
@@ -4,113 +4,39 @@ use glam::Vec2;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 
-/// Identifier for a physical keyboard key.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum KeyCode {
-    Named(NamedKey),
-    Character(char),
-    Digit(u8),
-    Function(u8),
-}
-
-impl KeyCode {
-    pub fn from_name(name: &str) -> Option<Self> {
-        if let Some(button) = parse_named_key(name) {
-            return Some(button);
-        }
-        if name.len() == 1 {
-            let ch = name.chars().next().unwrap();
-            if ch.is_ascii_alphabetic() {
-                return Some(Self::Character(ch.to_ascii_uppercase()));
-            }
-            if ch.is_ascii_digit() {
-                return Some(Self::Digit(ch as u8 - b'0'));
-            }
-        }
-        if let Some(function) = name.strip_prefix('F').or_else(|| name.strip_prefix('f')) {
-            if let Ok(index) = function.parse::<u8>() {
-                if index >= 1 && index <= 25 {
-                    return Some(Self::Function(index));
-                }
-            }
-        }
-        None
-    }
-}
-
-fn parse_named_key(name: &str) -> Option<KeyCode> {
-    use NamedKey::*;
-    let key = match name {
-        "Space" => Space,
-        "Enter" | "Return" => Enter,
-        "Tab" => Tab,
-        "Left" => Left,
-        "Right" => Right,
-        "Up" => Up,
-        "Down" => Down,
-        "Escape" | "Esc" => Escape,
-        "Backspace" => Backspace,
-        "Home" => Home,
-        "End" => End,
-        "PageUp" => PageUp,
-        "PageDown" => PageDown,
-        "LeftShift" | "LShift" => LeftShift,
-        "RightShift" | "RShift" => RightShift,
-        "LeftCtrl" | "LControl" => LeftCtrl,
-        "RightCtrl" | "RControl" => RightCtrl,
-        "LeftAlt" | "LAlt" => LeftAlt,
-        "RightAlt" | "RAlt" => RightAlt,
-        _ => return None,
-    };
-    Some(KeyCode::Named(key))
-}
-
-/// Friendly names for a subset of keyboard keys used by existing scripts.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum NamedKey {
-    Space,
-    Enter,
-    Tab,
-    Left,
-    Right,
-    Up,
-    Down,
-    Escape,
-    Backspace,
-    Home,
-    End,
-    PageUp,
-    PageDown,
-    LeftShift,
-    RightShift,
-    LeftCtrl,
-    RightCtrl,
-    LeftAlt,
-    RightAlt,
-}
-
-/// Identifier for a mouse button (left button is zero).
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct MouseButton(u8);
-
-impl MouseButton {
-    pub const LEFT: Self = Self(0);
-
-    pub fn new(index: u8) -> Self {
-        Self(index)
-    }
-
-    pub fn index(self) -> u8 {
-        self.0
-    }
-}
+pub use crate::types::{KeyCode, MouseButton, NamedKey};
 
 /// Thread-safe input snapshot shared with Lua scripts.
 #[derive(Debug, Default)]
 pub struct InputState {
     keys: RwLock<HashSet<KeyCode>>,
     mouse_buttons: RwLock<HashSet<MouseButton>>,
-    mouse_position: RwLock<Vec2>,
+    mouse_position: RwLock<Option<Vec2>>,
+    mouse_frame: RwLock<MouseFrame>,
+    /// Latest cursor lock change asked for by scripts, until the host takes it.
+    cursor_lock_request: RwLock<Option<bool>>,
+}
+
+/// Serializable copy of the held keys, held mouse buttons and cursor
+/// position, for recording input and replaying it into another run.
+///
+/// Keys and buttons are sorted so equal states serialize identically.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputSnapshot {
+    pub keys: Vec<KeyCode>,
+    pub mouse_buttons: Vec<MouseButton>,
+    /// `None` until the cursor has been seen.
+    pub mouse_position: Option<Vec2>,
+}
+
+/// Mouse movement bookkeeping advanced by [`InputState::begin_frame`].
+#[derive(Debug, Default)]
+struct MouseFrame {
+    /// Position at the previous `begin_frame`, once the cursor has been seen.
+    previous: Option<Vec2>,
+    /// Raw motion added since the previous `begin_frame`, if any.
+    motion: Option<Vec2>,
+    delta: Vec2,
 }
 
 impl InputState {
@@ -135,13 +61,61 @@ impl InputState {
     }
 
     pub fn set_mouse_position(&self, position: Vec2) {
-        *self.mouse_position.write() = position;
+        *self.mouse_position.write() = Some(position);
+    }
+
+    /// Adds raw device motion to the current frame's mouse delta.
+    ///
+    /// A locked cursor stops moving, so hosts feed the device's own motion
+    /// while it is locked. Frames that receive any use it in place of the
+    /// cursor movement.
+    pub fn add_mouse_motion(&self, motion: Vec2) {
+        let mut frame = self.mouse_frame.write();
+        *frame.motion.get_or_insert(Vec2::ZERO) += motion;
+    }
+
+    /// Starts a new frame, fixing the mouse movement reported by
+    /// [`InputState::mouse_delta`] until the next call.
+    ///
+    /// Hosts call this once per frame. Without raw motion from
+    /// [`InputState::add_mouse_motion`], the delta is zero until the cursor
+    /// position is known on two consecutive frames.
+    pub fn begin_frame(&self) {
+        let current = *self.mouse_position.read();
+        let mut frame = self.mouse_frame.write();
+        frame.delta = match (frame.motion.take(), frame.previous, current) {
+            (Some(motion), _, _) => motion,
+            (None, Some(previous), Some(current)) => current - previous,
+            _ => Vec2::ZERO,
+        };
+        frame.previous = current;
+    }
+
+    /// Releases every held key and mouse button.
+    ///
+    /// Call this when the window loses focus: the key-up events for keys
+    /// held at that moment are delivered elsewhere.
+    pub fn reset(&self) {
+        self.keys.write().clear();
+        self.mouse_buttons.write().clear();
     }
 
     pub fn is_key_down(&self, key: KeyCode) -> bool {
         self.keys.read().contains(&key)
     }
 
+    /// Returns the canonical names of every held key, sorted.
+    pub fn keys_down(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.keys.read().iter().map(KeyCode::name).collect();
+        names.sort();
+        names
+    }
+
+    /// Whether any key or mouse button is held.
+    pub fn any_held(&self) -> bool {
+        !self.keys.read().is_empty() || !self.mouse_buttons.read().is_empty()
+    }
+
     pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
         self.mouse_buttons.read().contains(&button)
     }
@@ -155,7 +129,51 @@ impl InputState {
     }
 
     pub fn mouse_position(&self) -> Vec2 {
-        *self.mouse_position.read()
+        self.mouse_position.read().unwrap_or_default()
+    }
+
+    /// Asks the host to lock and hide the cursor, or to release it.
+    ///
+    /// The core cannot reach the OS cursor; hosts apply the request after
+    /// collecting it with [`InputState::take_cursor_lock_request`]. Only the
+    /// latest request is kept.
+    pub fn request_cursor_lock(&self, locked: bool) {
+        *self.cursor_lock_request.write() = Some(locked);
+    }
+
+    /// Returns and clears the pending cursor lock request, if any.
+    pub fn take_cursor_lock_request(&self) -> Option<bool> {
+        self.cursor_lock_request.write().take()
+    }
+
+    /// Mouse movement between the two most recent frames.
+    pub fn mouse_delta(&self) -> Vec2 {
+        self.mouse_frame.read().delta
+    }
+
+    /// Captures the held keys, held mouse buttons and cursor position.
+    pub fn snapshot(&self) -> InputSnapshot {
+        let mut keys: Vec<KeyCode> = self.keys.read().iter().copied().collect();
+        keys.sort_by_cached_key(KeyCode::name);
+        let mut mouse_buttons: Vec<MouseButton> =
+            self.mouse_buttons.read().iter().copied().collect();
+        mouse_buttons.sort_by_key(|button| button.index());
+        InputSnapshot {
+            keys,
+            mouse_buttons,
+            mouse_position: *self.mouse_position.read(),
+        }
+    }
+
+    /// Replaces the held keys, held mouse buttons and cursor position with
+    /// those in `snapshot`.
+    ///
+    /// The mouse delta still comes from [`InputState::begin_frame`], so
+    /// replaying one snapshot per frame reproduces it too.
+    pub fn apply_snapshot(&self, snapshot: &InputSnapshot) {
+        *self.keys.write() = snapshot.keys.iter().copied().collect();
+        *self.mouse_buttons.write() = snapshot.mouse_buttons.iter().copied().collect();
+        *self.mouse_position.write() = snapshot.mouse_position;
     }
 }
 
@@ -201,6 +219,18 @@ mod tests {
         assert_eq!(KeyCode::from_name("F12"), Some(KeyCode::Function(12)));
     }
 
+    #[test]
+    fn key_names_round_trip() {
+        for key in [
+            KeyCode::Named(NamedKey::LeftShift),
+            KeyCode::Character('Q'),
+            KeyCode::Digit(7),
+            KeyCode::Function(4),
+        ] {
+            assert_eq!(KeyCode::from_name(&key.name()), Some(key));
+        }
+    }
+
     #[test]
     fn mouse_names_are_supported() {
         assert_eq!(mouse_index("Mouse1"), 0);
@@ -216,6 +246,89 @@ mod tests {
         assert!(!state.is_key_down_by_name("Space"));
     }
 
+    #[test]
+    fn reset_releases_everything() {
+        let state = InputState::new();
+        state.set_key_down(KeyCode::Character('W'));
+        state.set_key_down(KeyCode::Named(NamedKey::LeftShift));
+        state.set_mouse_button_down(MouseButton::LEFT);
+        state.reset();
+        assert!(!state.is_key_down(KeyCode::Character('W')));
+        assert!(!state.is_key_down(KeyCode::Named(NamedKey::LeftShift)));
+        assert!(!state.is_mouse_button_down(MouseButton::LEFT));
+    }
+
+    #[test]
+    fn mouse_delta_spans_one_frame() {
+        let state = InputState::new();
+        state.begin_frame();
+        assert_eq!(state.mouse_delta(), Vec2::ZERO);
+
+        state.set_mouse_position(Vec2::new(100.0, 50.0));
+        state.begin_frame();
+        assert_eq!(state.mouse_delta(), Vec2::ZERO);
+
+        state.set_mouse_position(Vec2::new(110.0, 45.0));
+        state.begin_frame();
+        assert_eq!(state.mouse_delta(), Vec2::new(10.0, -5.0));
+
+        state.begin_frame();
+        assert_eq!(state.mouse_delta(), Vec2::ZERO);
+    }
+
+    #[test]
+    fn raw_motion_drives_the_delta_while_the_cursor_is_still() {
+        let state = InputState::new();
+        state.set_mouse_position(Vec2::new(100.0, 50.0));
+        state.begin_frame();
+
+        state.add_mouse_motion(Vec2::new(3.0, 1.0));
+        state.add_mouse_motion(Vec2::new(2.0, -4.0));
+        state.begin_frame();
+        assert_eq!(state.mouse_delta(), Vec2::new(5.0, -3.0));
+
+        state.begin_frame();
+        assert_eq!(state.mouse_delta(), Vec2::ZERO);
+    }
+
+    #[test]
+    fn cursor_lock_request_is_taken_once() {
+        let state = InputState::new();
+        assert_eq!(state.take_cursor_lock_request(), None);
+        state.request_cursor_lock(true);
+        assert_eq!(state.take_cursor_lock_request(), Some(true));
+        assert_eq!(state.take_cursor_lock_request(), None);
+
+        state.request_cursor_lock(true);
+        state.request_cursor_lock(false);
+        assert_eq!(state.take_cursor_lock_request(), Some(false));
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_serde() {
+        let state = InputState::new();
+        state.set_key_down(KeyCode::Character('W'));
+        state.set_key_down(KeyCode::Named(NamedKey::LeftShift));
+        state.set_key_down(KeyCode::Digit(3));
+        state.set_mouse_button_down(MouseButton::LEFT);
+        state.set_mouse_position(Vec2::new(12.0, 34.0));
+
+        let json = serde_json::to_string(&state.snapshot()).unwrap();
+        let snapshot: InputSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot, state.snapshot());
+
+        let replay = InputState::new();
+        replay.set_key_down(KeyCode::Character('S'));
+        replay.apply_snapshot(&snapshot);
+        assert!(replay.is_key_down(KeyCode::Character('W')));
+        assert!(replay.is_key_down(KeyCode::Named(NamedKey::LeftShift)));
+        assert!(replay.is_key_down(KeyCode::Digit(3)));
+        assert!(!replay.is_key_down(KeyCode::Character('S')));
+        assert!(replay.is_mouse_button_down(MouseButton::LEFT));
+        assert_eq!(replay.mouse_position(), Vec2::new(12.0, 34.0));
+        assert_eq!(replay.snapshot(), snapshot);
+    }
+
     fn mouse_index(name: &str) -> u8 {
         match parse_input_name(name).unwrap() {
             InputName::Mouse(button) => button.index(),
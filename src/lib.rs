@@ -4,23 +4,77 @@
 //! build bespoke runtimes or tooling around the Crystal authoring
 //! environment.  Rendering and platform integration are intentionally kept
 //! outside of the crate so that the code remains testable and easy to
-//! embed in headless tools.
+//! embed in headless tools. Building with `default-features = false` drops
+//! the `graphics` feature, and with it every windowing and GPU dependency.
+//!
+//! Vectors and matrices in the public API are glam types. Constructors such
+//! as [`SceneObjectBuilder::position`] and `LightParams::new` accept anything
+//! convertible into them, including `[f32; 3]` arrays; the `mint` feature adds
+//! glam's conversions to and from [`mint`](https://docs.rs/mint) types, so
+//! `object.position.into()` also yields a `mint::Vector3<f32>`.
+//!
+//! Dropping the `std` feature as well leaves only the plain data types in
+//! [`types`], and the crate becomes `no_std` (it still needs `alloc`).
+//! Only the `rlib` output links without `std`, so build it with
+//! `cargo rustc --lib --crate-type rlib --no-default-features --features libm`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 
+#[cfg(feature = "std")]
 pub mod app;
+#[cfg(feature = "std")]
 pub mod archive;
+#[cfg(feature = "std")]
+pub mod controls;
+#[cfg(feature = "std")]
 pub mod data_model;
+#[cfg(feature = "gltf")]
+pub mod gltf;
+#[cfg(feature = "std")]
 pub mod input;
+#[cfg(feature = "std")]
 pub mod obj;
+#[cfg(feature = "std")]
 pub mod render;
+#[cfg(feature = "std")]
+pub mod runtime;
+#[cfg(feature = "std")]
 pub mod scene;
+#[cfg(feature = "std")]
 pub mod scripting;
-#[cfg(target_arch = "wasm32")]
+#[cfg(feature = "std")]
+mod telemetry;
+pub mod types;
+#[cfg(all(feature = "graphics", target_arch = "wasm32"))]
 pub mod web;
 
-pub use archive::{ArchiveFileEntry, CGameArchive};
+#[cfg(feature = "std")]
+pub use archive::{ArchiveError, ArchiveFileEntry, CGameArchive, CGameArchiveBuilder};
+#[cfg(feature = "std")]
 pub use data_model::DataModel;
-pub use input::{InputState, KeyCode, MouseButton, NamedKey};
-pub use obj::{load_obj_from_str, ObjMesh};
-pub use render::{CameraParams, LightParams, Renderer};
-pub use scene::{Light, Scene, SceneObject};
-pub use scripting::{LuaScriptManager, StaticViewport, ViewportProvider};
+#[cfg(feature = "gltf")]
+pub use gltf::load_gltf_from_bytes;
+#[cfg(feature = "std")]
+pub use input::{InputSnapshot, InputState};
+#[cfg(feature = "std")]
+pub use obj::{load_obj_from_str, load_obj_from_str_with_cancel, load_obj_groups_from_str};
+#[cfg(feature = "std")]
+pub use render::{
+    CameraParams, DebugDraw, DebugLine, FogMode, FogParams, LightParams, PreloadStats, SceneStats,
+};
+#[cfg(feature = "graphics")]
+pub use render::{MeshErrorHandler, PreparedFrame, Renderer, OFFSCREEN_FORMAT};
+#[cfg(feature = "std")]
+pub use runtime::Runtime;
+#[cfg(feature = "std")]
+pub use scene::{ColorSpace, Scene, UpAxis};
+#[cfg(feature = "std")]
+pub use scripting::{
+    HostEvent, LuaScriptManager, ScriptLogHandler, StaticViewport, ViewportProvider,
+};
+pub use types::{
+    AttributeValue, InlineScript, KeyCode, Light, LightType, MouseButton, NamedKey, ObjMesh,
+    SceneObject, SceneObjectBuilder, Shading,
+};
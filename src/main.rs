@@ -12,16 +12,24 @@ use std::fmt;
 #[cfg(not(target_arch = "wasm32"))]
 use std::panic::{self, AssertUnwindSafe};
 #[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
 use std::rc::Rc;
 #[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{self, Receiver};
+#[cfg(not(target_arch = "wasm32"))]
 use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
 
 #[cfg(not(target_arch = "wasm32"))]
 use anyhow::{anyhow, Context, Result};
 #[cfg(not(target_arch = "wasm32"))]
 use glam::Vec2;
 #[cfg(not(target_arch = "wasm32"))]
-use log::info;
+use log::{info, warn};
+#[cfg(not(target_arch = "wasm32"))]
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 #[cfg(not(target_arch = "wasm32"))]
 use parking_lot::RwLock;
 #[cfg(not(target_arch = "wasm32"))]
@@ -29,19 +37,24 @@ use pollster::block_on;
 #[cfg(not(target_arch = "wasm32"))]
 use winit::dpi::LogicalSize;
 #[cfg(not(target_arch = "wasm32"))]
-use winit::event::{ElementState, Event, KeyEvent, MouseButton as WinitMouseButton, WindowEvent};
+use winit::event::{
+    DeviceEvent, ElementState, Event, KeyEvent, MouseButton as WinitMouseButton, MouseScrollDelta,
+    WindowEvent,
+};
 #[cfg(not(target_arch = "wasm32"))]
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 #[cfg(not(target_arch = "wasm32"))]
-use winit::window::Window;
+use winit::window::{CursorGrabMode, Window};
 
 #[cfg(not(target_arch = "wasm32"))]
 use crystal_runtime::{
     app::{
-        camera_from_objects, light_from_objects, map_keycode, map_mouse_button, print_final_state,
+        camera_from_model, light_from_model, map_keycode, map_mouse_button, print_final_state,
+        FramePacer, RedrawTracker,
     },
-    CGameArchive, DataModel, InputState, LuaScriptManager, Renderer, Scene, StaticViewport,
-    ViewportProvider,
+    controls::OrbitController,
+    CGameArchive, CameraParams, DataModel, DebugDraw, InputState, LuaScriptManager, Renderer,
+    Runtime, Scene, StaticViewport, ViewportProvider,
 };
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -56,11 +69,12 @@ fn main() {
 #[cfg(not(target_arch = "wasm32"))]
 fn run() -> Result<()> {
     let options = CliOptions::parse()?;
-    let archive = Arc::new(
-        CGameArchive::open(&options.path)
-            .with_context(|| format!("failed to open archive {}", options.path))?,
-    );
-    let scene = Scene::from_xml(archive.scene_xml()).context("failed to parse scene XML")?;
+    let runtime = if options.watch {
+        Runtime::load(read_archive(Path::new(&options.path))?)?
+    } else {
+        Runtime::open(&options.path)?
+    };
+    let scene = runtime.scene();
 
     println!(
         "Loaded scene with {} objects ({} lights)",
@@ -71,28 +85,17 @@ fn run() -> Result<()> {
         println!(" - {} ({})", object.name, object.object_type);
     }
 
-    let model = DataModel::from_objects(scene.objects.clone());
-    let input = Arc::new(InputState::new());
-
     if options.summary_only {
-        run_headless(archive, model, input, options.run_scripts)
+        run_headless(&runtime, options.run_scripts)
     } else {
-        let headless_archive = Arc::clone(&archive);
-        let headless_model = model.clone();
-        let headless_input = Arc::clone(&input);
-        match run_interactive(archive, model, input, options.run_scripts) {
+        match run_interactive(&runtime, &options) {
             Ok(()) => Ok(()),
             Err(err) => {
                 if err.downcast_ref::<WindowInitError>().is_some() {
                     eprintln!(
                         "{err}. Falling back to --summary-only mode (set DISPLAY or install X11 libs to enable rendering)."
                     );
-                    run_headless(
-                        headless_archive,
-                        headless_model,
-                        headless_input,
-                        options.run_scripts,
-                    )
+                    run_headless(&runtime, options.run_scripts)
                 } else {
                     Err(err)
                 }
@@ -102,38 +105,21 @@ fn run() -> Result<()> {
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn run_headless(
-    archive: Arc<CGameArchive>,
-    model: DataModel,
-    input: Arc<InputState>,
-    run_scripts: bool,
-) -> Result<()> {
+fn run_headless(runtime: &Runtime, run_scripts: bool) -> Result<()> {
     if run_scripts {
         println!("Starting Lua scripts...");
-        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
-            Arc::new(StaticViewport::new(1280, 720));
-        let mut manager = LuaScriptManager::new(
-            Arc::clone(&archive),
-            model.clone(),
-            Arc::clone(&input),
-            viewport,
-        );
-        let count = manager.start().context("failed to launch scripts")?;
+        let (mut manager, count) =
+            runtime.start_scripts(Arc::new(StaticViewport::new(1280, 720)))?;
         println!("Launched {count} script(s)");
         manager.wait().context("script execution failed")?;
     }
 
-    print_final_state(&model);
+    print_final_state(runtime.data_model());
     Ok(())
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn run_interactive(
-    archive: Arc<CGameArchive>,
-    model: DataModel,
-    input: Arc<InputState>,
-    run_scripts: bool,
-) -> Result<()> {
+fn run_interactive(runtime: &Runtime, options: &CliOptions) -> Result<()> {
     let default_hook = panic::take_hook();
     panic::set_hook(Box::new(|_| {}));
     let event_loop = panic::catch_unwind(AssertUnwindSafe(EventLoop::new));
@@ -158,21 +144,26 @@ fn run_interactive(
             .map_err(|err| WindowInitError::from_error("window", err))?,
     );
 
-    let renderer = block_on(Renderer::new(Arc::clone(&window), Arc::clone(&archive)))?;
+    let mut renderer = block_on(Renderer::new(
+        Arc::clone(&window),
+        Arc::clone(runtime.archive()),
+    ))?;
+    renderer.set_scene_root(runtime.scene().root_transform());
+    let adapter = renderer.adapter_info();
+    println!(
+        "Rendering with {} ({:?}, {:?})",
+        adapter.name, adapter.backend, adapter.device_type
+    );
     let viewport = Arc::new(WindowViewport::new(
         window.inner_size().width,
         window.inner_size().height,
     ));
     let viewport_provider: Arc<dyn ViewportProvider + Send + Sync> = viewport.clone();
 
-    let script_manager = if run_scripts {
+    let script_manager = if options.run_scripts {
         println!("Starting Lua scripts...");
-        let mut manager = LuaScriptManager::new(
-            Arc::clone(&archive),
-            model.clone(),
-            Arc::clone(&input),
-            viewport_provider,
-        );
+        let mut manager = runtime.script_manager(viewport_provider);
+        manager.set_debug_draw(renderer.debug_draw());
         let count = manager.start().context("failed to launch scripts")?;
         println!("Launched {count} script(s)");
         Some(manager)
@@ -180,19 +171,40 @@ fn run_interactive(
         None
     };
 
+    let archive_watcher = match (options.watch, script_manager.is_some()) {
+        (true, true) => Some(
+            ArchiveWatcher::new(&options.path)
+                .with_context(|| format!("failed to watch {}", options.path))?,
+        ),
+        (true, false) => {
+            eprintln!("--watch only reloads scripts; pass --run-scripts to enable it");
+            None
+        }
+        (false, _) => None,
+    };
+
+    let debug_draw = renderer.debug_draw();
+    let monitor = renderer.window().current_monitor();
     let app = Rc::new(RefCell::new(AppState {
         renderer,
-        data_model: model,
-        input,
+        monitor,
+        data_model: runtime.data_model().clone(),
+        input: Arc::clone(runtime.input()),
         viewport,
         script_manager,
+        archive_watcher,
+        orbit: options.orbit.then(OrbitController::default),
+        pacer: FramePacer::new(options.max_fps),
+        redraw: RedrawTracker::new(options.continuous),
+        debug_draw,
+        scroll_delta: 0.0,
+        cursor_locked: false,
         last_error: None,
     }));
 
     let app_runner = Rc::clone(&app);
     #[allow(deprecated)]
     let run_result = event_loop.run(move |event, elwt| {
-        elwt.set_control_flow(ControlFlow::Poll);
         let mut app = app_runner.borrow_mut();
         if let Err(err) = app.process_event(&event, elwt) {
             app.last_error = Some(err);
@@ -212,16 +224,98 @@ fn run_interactive(
     Ok(())
 }
 
+/// How often an idle loop wakes to look for script changes to the model.
+#[cfg(not(target_arch = "wasm32"))]
+const SCRIPT_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Approximate pixel height of one scroll-wheel line, used to normalise
+/// trackpad deltas for the orbit controller.
+#[cfg(not(target_arch = "wasm32"))]
+const PIXELS_PER_LINE: f32 = 40.0;
+
 #[cfg(not(target_arch = "wasm32"))]
 struct AppState {
     renderer: Renderer,
+    /// Monitor the window was last seen on, used to detect display changes.
+    monitor: Option<winit::monitor::MonitorHandle>,
     data_model: DataModel,
     input: Arc<InputState>,
     viewport: Arc<WindowViewport>,
     script_manager: Option<LuaScriptManager>,
+    archive_watcher: Option<ArchiveWatcher>,
+    orbit: Option<OrbitController>,
+    pacer: FramePacer,
+    redraw: RedrawTracker,
+    /// Queue scripts draw debug lines into, checked for pending lines.
+    debug_draw: DebugDraw,
+    scroll_delta: f32,
+    /// Whether the cursor is grabbed for a script, so raw mouse motion
+    /// feeds the mouse delta.
+    cursor_locked: bool,
     last_error: Option<anyhow::Error>,
 }
 
+/// Reads the whole archive into memory, for `--watch`.
+///
+/// [`CGameArchive::open`] maps the file or rereads entries from it, so an
+/// exporter overwriting the archive in place rather than renaming a new file
+/// over it would change the bytes under a running scene.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_archive(path: &Path) -> Result<CGameArchive> {
+    let data = std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+    CGameArchive::from_bytes(path.display().to_string(), data)
+        .with_context(|| format!("failed to open archive {}", path.display()))
+}
+
+/// Watches the archive's directory and reports when the archive file changes.
+///
+/// The parent directory is watched rather than the file itself because
+/// exporters commonly replace the archive via rename, which would orphan a
+/// watch placed on the original inode.
+#[cfg(not(target_arch = "wasm32"))]
+struct ArchiveWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    path: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ArchiveWatcher {
+    fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+        watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            path,
+        })
+    }
+
+    /// Drains pending events and returns whether any touched the archive.
+    fn take_changed(&self) -> bool {
+        let file_name = self.path.file_name();
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) if event.kind.is_modify() || event.kind.is_create() => {
+                    changed |= event.paths.iter().any(|path| path.file_name() == file_name);
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("Archive watcher error: {err}"),
+            }
+        }
+        changed
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[derive(Debug)]
 struct WindowInitError {
@@ -269,19 +363,30 @@ impl AppState {
     fn process_event(&mut self, event: &Event<()>, elwt: &ActiveEventLoop) -> Result<()> {
         match event {
             Event::WindowEvent { event, window_id } if *window_id == self.renderer.window_id() => {
+                if !matches!(event, WindowEvent::RedrawRequested) {
+                    // Input, resizes and moves can all change the frame.
+                    self.redraw.mark_dirty();
+                }
                 match event {
                     WindowEvent::CloseRequested => {
                         elwt.exit();
                     }
                     WindowEvent::Resized(size) => {
-                        self.renderer.resize(*size);
+                        self.renderer.set_pending_size(*size);
                         self.viewport.update(size.width, size.height);
                     }
                     WindowEvent::ScaleFactorChanged { .. } => {
                         let size = self.renderer.window().inner_size();
-                        self.renderer.resize(size);
+                        self.renderer.set_pending_size(size);
                         self.viewport.update(size.width, size.height);
                     }
+                    WindowEvent::Moved(_) => {
+                        let monitor = self.renderer.window().current_monitor();
+                        if monitor != self.monitor {
+                            self.monitor = monitor;
+                            self.renderer.reconfigure();
+                        }
+                    }
                     WindowEvent::KeyboardInput { event, .. } => {
                         self.handle_keyboard(event);
                     }
@@ -292,13 +397,36 @@ impl AppState {
                         let pos = Vec2::new(position.x as f32, position.y as f32);
                         self.input.set_mouse_position(pos);
                     }
+                    WindowEvent::Focused(false) => {
+                        self.input.reset();
+                        self.scroll_delta = 0.0;
+                    }
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        self.scroll_delta += match delta {
+                            MouseScrollDelta::LineDelta(_, y) => *y,
+                            MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / PIXELS_PER_LINE,
+                        };
+                    }
                     WindowEvent::RedrawRequested => {
-                        let objects = self.data_model.all_objects();
+                        self.input.begin_frame();
                         let aspect = self.renderer_aspect();
-                        let camera = camera_from_objects(&objects, aspect);
-                        let light = light_from_objects(&objects);
+                        let camera = self.frame_camera(aspect);
+                        let light = light_from_model(&self.data_model);
                         self.renderer.update_globals(&camera, &light);
-                        if let Err(err) = self.renderer.render(&objects) {
+                        let revision = self.data_model.revision();
+                        // Only building the draws holds the read lock, so
+                        // scripts never wait on surface acquire, mesh loads
+                        // or present.
+                        let frame = {
+                            let objects = self.data_model.read();
+                            self.renderer.prepare_frame(&objects)
+                        };
+                        let result = self.renderer.render_prepared(frame);
+                        match &result {
+                            Ok(()) => self.redraw.frame_drawn(revision),
+                            Err(_) => self.redraw.mark_dirty(),
+                        }
+                        if let Err(err) = result {
                             match err {
                                 wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => {
                                     let size = self.renderer.window().inner_size();
@@ -310,8 +438,11 @@ impl AppState {
                                 wgpu::SurfaceError::Timeout => {
                                     info!("Surface timeout; retrying next frame");
                                 }
-                                wgpu::SurfaceError::Other => {
-                                    info!("Surface reported an unknown error; retrying next frame");
+                                // `Other` and any variants added by future wgpu
+                                // releases: rebuild the surface and keep going.
+                                other => {
+                                    warn!("Surface error ({other}); reconfiguring");
+                                    self.renderer.reconfigure();
                                 }
                             }
                         }
@@ -319,8 +450,41 @@ impl AppState {
                     _ => {}
                 }
             }
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } if self.cursor_locked => {
+                self.input
+                    .add_mouse_motion(Vec2::new(delta.0 as f32, delta.1 as f32));
+                self.redraw.mark_dirty();
+            }
             Event::AboutToWait => {
-                self.renderer.window().request_redraw();
+                self.reload_scripts_if_changed();
+                self.log_host_events();
+                self.apply_cursor_lock_request();
+                let now = Instant::now();
+                // Held input keeps orbiting and script polling going, and
+                // queued debug lines need a frame to be drawn and drained.
+                let dirty = self.redraw.is_dirty(self.data_model.revision())
+                    || self.input.any_held()
+                    || self.debug_draw.has_pending();
+                let control_flow = if dirty {
+                    // Input events still wake a capped loop immediately, so
+                    // the cap only delays when their effect is drawn, by at
+                    // most one frame interval.
+                    let (due, deadline) = self.pacer.tick(now);
+                    if due {
+                        self.renderer.window().request_redraw();
+                    }
+                    deadline.map_or(ControlFlow::Poll, ControlFlow::WaitUntil)
+                } else if self.script_manager.is_some() {
+                    // Scripts and archive reloads change the model off the
+                    // event loop, so keep checking for them.
+                    ControlFlow::WaitUntil(now + SCRIPT_POLL_INTERVAL)
+                } else {
+                    ControlFlow::Wait
+                };
+                elwt.set_control_flow(control_flow);
             }
             Event::LoopExiting => {
                 self.shutdown();
@@ -330,6 +494,80 @@ impl AppState {
         Ok(())
     }
 
+    fn reload_scripts_if_changed(&mut self) {
+        let Some(watcher) = self.archive_watcher.as_ref() else {
+            return;
+        };
+        if !watcher.take_changed() {
+            return;
+        }
+        let archive = match read_archive(&watcher.path) {
+            Ok(archive) => Arc::new(archive),
+            Err(err) => {
+                // Exporters may still be writing; the next change event retries.
+                eprintln!("Failed to reload {}: {err:?}", watcher.path.display());
+                return;
+            }
+        };
+        if let Some(manager) = self.script_manager.as_mut() {
+            match Scene::from_xml(archive.scene_xml()) {
+                Ok(scene) => manager.set_inline_scripts(scene.scripts),
+                Err(err) => eprintln!("Keeping previous inline scripts: {err:?}"),
+            }
+            match manager.reload(archive) {
+                Ok(count) => println!("Reloaded {count} script(s)"),
+                Err(err) => eprintln!("Error reloading scripts: {err:?}"),
+            }
+        }
+    }
+
+    /// Drains the events scripts sent with `host.emit`. This host has no
+    /// use for them beyond logging, but the queue grows until drained.
+    fn log_host_events(&self) {
+        let Some(manager) = self.script_manager.as_ref() else {
+            return;
+        };
+        for event in manager.drain_events() {
+            info!("script event {}: {}", event.name, event.payload);
+        }
+    }
+
+    /// Grabs and hides the cursor, or releases it, as scripts asked through
+    /// `service.input.SetCursorLocked`.
+    fn apply_cursor_lock_request(&mut self) {
+        let Some(locked) = self.input.take_cursor_lock_request() else {
+            return;
+        };
+        let window = self.renderer.window();
+        let result = if locked {
+            // Not every platform can lock the cursor in place; confining it
+            // to the window is the closest fallback.
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(err) = result {
+            warn!("Unable to change the cursor grab: {err}");
+        }
+        window.set_cursor_visible(!locked);
+        self.cursor_locked = locked;
+    }
+
+    /// Uses the orbit controller when enabled and the scene has no camera.
+    fn frame_camera(&mut self, aspect: f32) -> CameraParams {
+        let scroll = std::mem::take(&mut self.scroll_delta);
+        if let Some(orbit) = self.orbit.as_mut() {
+            let has_camera = self.data_model.first_of_type("camera").is_some();
+            if !has_camera {
+                orbit.update(&self.input, scroll);
+                return orbit.camera(aspect);
+            }
+        }
+        camera_from_model(&self.data_model, aspect)
+    }
+
     fn renderer_aspect(&self) -> f32 {
         let size = self.renderer.window().inner_size();
         if size.height == 0 {
@@ -375,6 +613,12 @@ struct CliOptions {
     path: String,
     run_scripts: bool,
     summary_only: bool,
+    watch: bool,
+    orbit: bool,
+    /// Redraw rate cap from `--max-fps`; 0 redraws as fast as possible.
+    max_fps: u32,
+    /// Redraw every frame, even when nothing changed.
+    continuous: bool,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -383,18 +627,33 @@ impl CliOptions {
         let mut args = env::args().skip(1);
         let Some(path) = args.next() else {
             return Err(anyhow!(
-                "Usage: crystal-runtime <scene.cgame> [--run-scripts] [--summary-only]"
+                "Usage: crystal-runtime <scene.cgame> [--run-scripts] [--summary-only] [--watch] [--orbit] [--max-fps <n>] [--continuous]"
             ));
         };
         let mut run_scripts = false;
         let mut summary_only = false;
-        for arg in args {
+        let mut watch = false;
+        let mut orbit = false;
+        let mut max_fps = 0;
+        let mut continuous = false;
+        while let Some(arg) = args.next() {
             match arg.as_str() {
                 "--run-scripts" => run_scripts = true,
                 "--summary-only" => summary_only = true,
+                "--watch" => watch = true,
+                "--orbit" => orbit = true,
+                "--continuous" => continuous = true,
+                "--max-fps" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--max-fps expects a frame rate"))?;
+                    max_fps = value
+                        .parse()
+                        .with_context(|| format!("invalid --max-fps value {value:?}"))?;
+                }
                 other => {
                     return Err(anyhow!(
-                        "Unknown argument: {other}. Expected --run-scripts or --summary-only"
+                        "Unknown argument: {other}. Expected --run-scripts, --summary-only, --watch, --orbit, --max-fps or --continuous"
                     ));
                 }
             }
@@ -403,6 +662,10 @@ impl CliOptions {
             path,
             run_scripts,
             summary_only,
+            watch,
+            orbit,
+            max_fps,
+            continuous,
         })
     }
 }
@@ -1,25 +1,92 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use anyhow::{anyhow, Context, Result};
 use glam::Vec3;
-use serde::{Deserialize, Serialize};
 
-/// GPU ready mesh buffers produced from an OBJ file.
-#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
-pub struct ObjMesh {
-    pub vertices: Vec<f32>,
-    pub indices: Vec<u32>,
-}
+pub use crate::types::{ObjMesh, VERTEX_STRIDE};
+
+/// Lines parsed between checks of the cancel flag.
+const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+/// Error returned by [`load_obj_from_str_with_cancel`] when the cancel flag
+/// was raised; recover it with `err.downcast_ref::<Cancelled>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("OBJ parsing was cancelled")]
+pub struct Cancelled;
 
 /// Parses an OBJ file from memory and returns interleaved vertex/index arrays.
 ///
-/// Vertices are laid out as `position.xyz` followed by `normal.xyz`.
+/// Vertices are laid out as `position.xyz`, `normal.xyz` and `color.rgb`.
+/// Vertex colors come from the extended `v x y z r g b` form and default to
+/// white when absent.
 pub fn load_obj_from_str(data: &str) -> Result<ObjMesh> {
+    load_obj_from_str_with_cancel(data, &AtomicBool::new(false))
+}
+
+/// Like [`load_obj_from_str`], but gives up with [`Cancelled`] soon after
+/// `cancel` is set, so hosts parsing huge meshes on a worker can abort.
+pub fn load_obj_from_str_with_cancel(data: &str, cancel: &AtomicBool) -> Result<ObjMesh> {
+    let obj = parse_obj(data, cancel)?;
+    obj.build(&obj.faces)
+}
+
+/// Parses an OBJ file into one mesh per `o`/`g` statement, in file order.
+///
+/// Faces before the first statement belong to a group named `default`, and
+/// a name used again continues its earlier group. Groups without faces are
+/// left out. Each mesh holds only the vertices its faces reference.
+pub fn load_obj_groups_from_str(data: &str) -> Result<Vec<(String, ObjMesh)>> {
+    let obj = parse_obj(data, &AtomicBool::new(false))?;
+    obj.groups
+        .iter()
+        .filter(|(_, faces)| !faces.is_empty())
+        .map(|(name, faces)| {
+            let faces: Vec<[FaceIndex; 3]> = faces.iter().map(|&face| obj.faces[face]).collect();
+            let mesh = obj
+                .build(&faces)
+                .with_context(|| format!("invalid OBJ group {name:?}"))?;
+            Ok((name.clone(), mesh))
+        })
+        .collect()
+}
+
+/// Name of the group collecting faces that precede any `o`/`g` statement.
+const DEFAULT_GROUP: &str = "default";
+
+/// Vertex pools shared by every group of an OBJ file.
+struct ParsedObj {
+    positions: Vec<Vec3>,
+    colors: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    /// Triangles in file order.
+    faces: Vec<[FaceIndex; 3]>,
+    /// Group names with the indices of their triangles in `faces`.
+    groups: Vec<(String, Vec<usize>)>,
+}
+
+impl ParsedObj {
+    fn build(&self, faces: &[[FaceIndex; 3]]) -> Result<ObjMesh> {
+        let mut mesh = build_mesh(&self.positions, &self.colors, &self.normals, faces)?;
+        if needs_normals(&mesh.vertices) {
+            compute_normals(&mut mesh);
+        }
+        Ok(mesh)
+    }
+}
+
+fn parse_obj(data: &str, cancel: &AtomicBool) -> Result<ParsedObj> {
     let mut positions = Vec::new();
+    let mut colors = Vec::new();
     let mut normals = Vec::new();
     let mut faces: Vec<[FaceIndex; 3]> = Vec::new();
+    let mut groups: Vec<(String, Vec<usize>)> = vec![(DEFAULT_GROUP.to_string(), Vec::new())];
+    let mut current = 0;
 
     for (line_no, line) in data.lines().enumerate() {
+        if line_no % CANCEL_CHECK_INTERVAL == 0 && cancel.load(Ordering::Relaxed) {
+            return Err(Cancelled.into());
+        }
         let trimmed = line.trim();
         if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
@@ -29,10 +96,12 @@ pub fn load_obj_from_str(data: &str) -> Result<ObjMesh> {
             continue;
         };
         match tag {
-            "v" => positions.push(
-                parse_vec3(parts)
-                    .with_context(|| format!("invalid vertex on line {}", line_no + 1))?,
-            ),
+            "v" => {
+                let (position, color) = parse_vertex(parts)
+                    .with_context(|| format!("invalid vertex on line {}", line_no + 1))?;
+                positions.push(position);
+                colors.push(color);
+            }
             "vn" => normals.push(
                 parse_vec3(parts)
                     .with_context(|| format!("invalid normal on line {}", line_no + 1))?,
@@ -40,7 +109,20 @@ pub fn load_obj_from_str(data: &str) -> Result<ObjMesh> {
             "f" => {
                 let polygon = parse_face(parts)
                     .with_context(|| format!("invalid face on line {}", line_no + 1))?;
+                let first = faces.len();
                 triangulate_face(&polygon, &mut faces);
+                groups[current].1.extend(first..faces.len());
+            }
+            "o" | "g" => {
+                let name = trimmed[1..].trim();
+                let name = if name.is_empty() { DEFAULT_GROUP } else { name };
+                current = match groups.iter().position(|(existing, _)| existing == name) {
+                    Some(index) => index,
+                    None => {
+                        groups.push((name.to_string(), Vec::new()));
+                        groups.len() - 1
+                    }
+                };
             }
             _ => {}
         }
@@ -49,12 +131,17 @@ pub fn load_obj_from_str(data: &str) -> Result<ObjMesh> {
     if positions.is_empty() {
         return Err(anyhow!("OBJ file does not define any vertices"));
     }
-
-    let mut mesh = build_mesh(&positions, &normals, &faces)?;
-    if needs_normals(&mesh.vertices) {
-        compute_normals(&mut mesh);
+    if cancel.load(Ordering::Relaxed) {
+        return Err(Cancelled.into());
     }
-    Ok(mesh)
+
+    Ok(ParsedObj {
+        positions,
+        colors,
+        normals,
+        faces,
+        groups,
+    })
 }
 
 fn parse_vec3<'a>(mut parts: impl Iterator<Item = &'a str>) -> Result<Vec3> {
@@ -73,6 +160,20 @@ fn parse_vec3<'a>(mut parts: impl Iterator<Item = &'a str>) -> Result<Vec3> {
     Ok(Vec3::new(x, y, z))
 }
 
+fn parse_vertex<'a>(mut parts: impl Iterator<Item = &'a str>) -> Result<(Vec3, Vec3)> {
+    let position = parse_vec3(&mut parts)?;
+    let extra = parts
+        .map(|part| part.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    // `v x y z r g b` and `v x y z w r g b` both end with the color triple.
+    let color = if extra.len() >= 3 {
+        Vec3::from_slice(&extra[extra.len() - 3..])
+    } else {
+        Vec3::ONE
+    };
+    Ok((position, color))
+}
+
 fn parse_face<'a>(parts: impl Iterator<Item = &'a str>) -> Result<Vec<FaceIndex>> {
     let mut indices = Vec::new();
     for part in parts {
@@ -131,7 +232,12 @@ struct FaceIndex {
     vn: i32,
 }
 
-fn build_mesh(positions: &[Vec3], normals: &[Vec3], faces: &[[FaceIndex; 3]]) -> Result<ObjMesh> {
+fn build_mesh(
+    positions: &[Vec3],
+    colors: &[Vec3],
+    normals: &[Vec3],
+    faces: &[[FaceIndex; 3]],
+) -> Result<ObjMesh> {
     let mut lookup: HashMap<Key, u32> = HashMap::new();
     let mut vertices = Vec::new();
     let mut indices = Vec::new();
@@ -145,12 +251,14 @@ fn build_mesh(positions: &[Vec3], normals: &[Vec3], faces: &[[FaceIndex; 3]]) ->
                 position: pos_index,
                 normal: normal_index,
             };
-            let next_index = (vertices.len() / 6) as u32;
+            let next_index = (vertices.len() / VERTEX_STRIDE) as u32;
             let entry = lookup.entry(key).or_insert_with(|| {
                 let position = positions[pos_index];
                 vertices.extend_from_slice(&[position.x, position.y, position.z]);
                 let normal = normal_index.map(|i| normals[i]).unwrap_or(Vec3::ZERO);
                 vertices.extend_from_slice(&[normal.x, normal.y, normal.z]);
+                let color = colors[pos_index];
+                vertices.extend_from_slice(&[color.x, color.y, color.z]);
                 next_index
             });
             indices.push(*entry);
@@ -172,23 +280,23 @@ fn fix_index(index: i32, len: usize) -> Option<usize> {
     }
 }
 
-fn needs_normals(vertices: &[f32]) -> bool {
+pub(crate) fn needs_normals(vertices: &[f32]) -> bool {
     vertices
-        .chunks_exact(6)
+        .chunks_exact(VERTEX_STRIDE)
         .any(|chunk| chunk[3] == 0.0 && chunk[4] == 0.0 && chunk[5] == 0.0)
 }
 
-fn compute_normals(mesh: &mut ObjMesh) {
-    let vertex_count = mesh.vertices.len() / 6;
+pub(crate) fn compute_normals(mesh: &mut ObjMesh) {
+    let vertex_count = mesh.vertices.len() / VERTEX_STRIDE;
     let mut accum = vec![Vec3::ZERO; vertex_count];
 
     for triangle in mesh.indices.chunks_exact(3) {
         let i0 = triangle[0] as usize;
         let i1 = triangle[1] as usize;
         let i2 = triangle[2] as usize;
-        let p0 = Vec3::from_slice(&mesh.vertices[i0 * 6..i0 * 6 + 3]);
-        let p1 = Vec3::from_slice(&mesh.vertices[i1 * 6..i1 * 6 + 3]);
-        let p2 = Vec3::from_slice(&mesh.vertices[i2 * 6..i2 * 6 + 3]);
+        let p0 = vertex_position(&mesh.vertices, i0);
+        let p1 = vertex_position(&mesh.vertices, i1);
+        let p2 = vertex_position(&mesh.vertices, i2);
         let normal = (p1 - p0).cross(p2 - p0);
         if normal.length_squared() > f32::EPSILON {
             let normal = normal.normalize();
@@ -200,12 +308,18 @@ fn compute_normals(mesh: &mut ObjMesh) {
 
     for (i, normal) in accum.into_iter().enumerate() {
         let normal = normal.normalize_or_zero();
-        mesh.vertices[i * 6 + 3] = normal.x;
-        mesh.vertices[i * 6 + 4] = normal.y;
-        mesh.vertices[i * 6 + 5] = normal.z;
+        let base = i * VERTEX_STRIDE;
+        mesh.vertices[base + 3] = normal.x;
+        mesh.vertices[base + 4] = normal.y;
+        mesh.vertices[base + 5] = normal.z;
     }
 }
 
+fn vertex_position(vertices: &[f32], index: usize) -> Vec3 {
+    let base = index * VERTEX_STRIDE;
+    Vec3::from_slice(&vertices[base..base + 3])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -215,14 +329,120 @@ mod tests {
         let obj = "\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
         let mesh = load_obj_from_str(obj).unwrap();
         assert_eq!(mesh.indices, vec![0, 1, 2]);
-        assert_eq!(mesh.vertices.len(), 18);
+        assert_eq!(mesh.vertices.len(), 3 * VERTEX_STRIDE);
+        for chunk in mesh.vertices.chunks_exact(VERTEX_STRIDE) {
+            assert_eq!(Vec3::from_slice(&chunk[6..9]), Vec3::ONE);
+        }
+    }
+
+    #[test]
+    fn parses_vertex_colors() {
+        let obj = "\nv 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 0 0 1\nf 1 2 3\n";
+        let mesh = load_obj_from_str(obj).unwrap();
+        let colors: Vec<Vec3> = mesh
+            .vertices
+            .chunks_exact(VERTEX_STRIDE)
+            .map(|chunk| Vec3::from_slice(&chunk[6..9]))
+            .collect();
+        assert_eq!(colors, vec![Vec3::X, Vec3::Y, Vec3::Z]);
+    }
+
+    #[test]
+    fn groups_split_on_object_statements() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n\
+                   o Base\nf 1 2 3\n\
+                   o Lid\nf 1 3 4\nf -1 -2 -3\n";
+        let groups = load_obj_groups_from_str(obj).unwrap();
+        let names: Vec<&str> = groups.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Base", "Lid"]);
+        assert_eq!(groups[0].1.indices, vec![0, 1, 2]);
+        assert_eq!(groups[0].1.vertices.len(), 3 * VERTEX_STRIDE);
+        assert_eq!(groups[1].1.indices.len(), 6);
+        assert_eq!(groups[1].1.vertices.len(), 4 * VERTEX_STRIDE);
+
+        let merged = load_obj_from_str(obj).unwrap();
+        assert_eq!(merged.indices.len(), 9);
+        assert_eq!(merged.vertices.len(), 4 * VERTEX_STRIDE);
+    }
+
+    #[test]
+    fn append_offsets_indices() {
+        let triangle = load_obj_from_str("\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let mut mesh = triangle.clone();
+        mesh.append(&triangle);
+        assert_eq!(mesh.vertices.len(), 6 * VERTEX_STRIDE);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn bounds_span_vertex_positions() {
+        let mesh = load_obj_from_str("\nv -1 0 2\nv 3 -4 0\nv 0 5 1\nf 1 2 3\n").unwrap();
+        assert_eq!(
+            mesh.bounds(),
+            Some((Vec3::new(-1.0, -4.0, 0.0), Vec3::new(3.0, 5.0, 2.0)))
+        );
+        assert_eq!(ObjMesh::default().bounds(), None);
+    }
+
+    #[test]
+    fn weld_merges_coincident_vertices() {
+        // Two triangles exported with their shared corners duplicated.
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 0 0\nv 1.00001 1 0\nv 0 1 0\nvn 0 0 1\n\
+                   f 1//1 2//1 3//1\nf 4//1 5//1 6//1\n";
+        let mut mesh = load_obj_from_str(obj).unwrap();
+        assert_eq!(mesh.vertices.len(), 6 * VERTEX_STRIDE);
+
+        assert_eq!(mesh.weld(1e-4), 2);
+        assert_eq!(mesh.vertices.len(), 4 * VERTEX_STRIDE);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 1, 3, 2]);
+    }
+
+    #[test]
+    fn weld_without_tolerance_merges_only_identical_vertices() {
+        // Far from the origin, where a grid cell of f32::EPSILON would
+        // saturate and lump every vertex together.
+        let obj = "v 1e9 0 0\nv 2e9 0 0\nv 1e9 1 0\nv 2e9 0 0\nv 3e9 1 0\nv -0 1 0\n\
+                   vn 0 0 1\nf 1//1 2//1 3//1\nf 4//1 5//1 6//1\n";
+        let mut mesh = load_obj_from_str(obj).unwrap();
+        assert_eq!(mesh.weld(0.0), 1);
+        assert_eq!(mesh.vertices.len(), 5 * VERTEX_STRIDE);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 1, 3, 4]);
+    }
+
+    #[test]
+    fn weld_keeps_hard_edges() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nvn 0 1 0\n\
+                   f 1//1 2//1 3//1\nf 1//2 2//2 3//2\n";
+        let mut mesh = load_obj_from_str(obj).unwrap();
+        assert_eq!(mesh.weld(1e-4), 0);
+        assert_eq!(mesh.vertices.len(), 6 * VERTEX_STRIDE);
+    }
+
+    #[test]
+    fn flip_winding_reverses_triangles() {
+        let mut mesh =
+            load_obj_from_str("\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        mesh.flip_winding();
+        assert_eq!(mesh.indices, vec![0, 2, 1, 0, 3, 2]);
+    }
+
+    #[test]
+    fn cancel_flag_stops_parsing() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n".repeat(CANCEL_CHECK_INTERVAL);
+        let cancel = AtomicBool::new(true);
+        let err = load_obj_from_str_with_cancel(&obj, &cancel).unwrap_err();
+        assert_eq!(err.downcast_ref::<Cancelled>(), Some(&Cancelled));
+
+        cancel.store(false, Ordering::Relaxed);
+        assert!(load_obj_from_str_with_cancel(&obj, &cancel).is_ok());
     }
 
     #[test]
     fn computes_missing_normals() {
         let obj = "\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
         let mesh = load_obj_from_str(obj).unwrap();
-        for chunk in mesh.vertices.chunks_exact(6) {
+        for chunk in mesh.vertices.chunks_exact(VERTEX_STRIDE) {
             let normal = Vec3::new(chunk[3], chunk[4], chunk[5]);
             assert!((normal.length() - 1.0).abs() < 1e-5);
         }
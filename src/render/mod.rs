@@ -1,9 +1,943 @@
-#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+
+use glam::{Mat4, Vec3};
+#[cfg(feature = "graphics")]
+use log::{info, warn};
+use parking_lot::Mutex;
+
+use crate::LightType;
+#[cfg(feature = "graphics")]
+use crate::SceneObject;
+
+#[cfg(all(feature = "graphics", not(target_arch = "wasm32")))]
 pub mod native;
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "graphics", target_arch = "wasm32"))]
 pub mod wasm;
 
-#[cfg(not(target_arch = "wasm32"))]
-pub use native::{CameraParams, LightParams, Renderer};
-#[cfg(target_arch = "wasm32")]
-pub use wasm::{CameraParams, LightParams, Renderer};
+#[cfg(all(feature = "graphics", not(target_arch = "wasm32")))]
+pub use native::{MeshErrorHandler, PreparedFrame, Renderer};
+#[cfg(all(feature = "graphics", target_arch = "wasm32"))]
+pub use wasm::{MeshErrorHandler, PreparedFrame, Renderer};
+
+/// Camera parameters consumed by the renderer's uniform buffer.
+pub struct CameraParams {
+    pub view_proj: Mat4,
+    pub position: Vec3,
+    /// World-to-camera transform; billboards copy its inverse rotation.
+    pub view: Mat4,
+}
+
+impl CameraParams {
+    /// Camera uniforms with an identity view; see [`CameraParams::with_view`].
+    pub fn new(view_proj: impl Into<Mat4>, position: impl Into<Vec3>) -> Self {
+        Self {
+            view_proj: view_proj.into(),
+            position: position.into(),
+            view: Mat4::IDENTITY,
+        }
+    }
+
+    /// Sets the view matrix that billboarded objects turn to face.
+    pub fn with_view(mut self, view: impl Into<Mat4>) -> Self {
+        self.view = view.into();
+        self
+    }
+}
+
+/// Lighting state consumed by the renderer's uniform buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightParams {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub light_type: LightType,
+    /// Unit vector directional and spot lights shine along.
+    pub direction: Vec3,
+    /// Spot cone half-angle in degrees.
+    pub spot_angle: f32,
+}
+
+impl LightParams {
+    /// A point light; set the other fields for directional or spot lights.
+    pub fn new(position: impl Into<Vec3>, color: impl Into<Vec3>, intensity: f32) -> Self {
+        Self {
+            position: position.into(),
+            color: color.into(),
+            intensity,
+            light_type: LightType::Point,
+            direction: Vec3::NEG_Y,
+            spot_angle: 30.0,
+        }
+    }
+
+    /// The `light_shape` uniform: x selects the light type (0 point,
+    /// 1 directional, 2 spot) and y is the cosine of the spot cone.
+    #[cfg(feature = "graphics")]
+    fn shape(&self) -> [f32; 4] {
+        let kind = match self.light_type {
+            LightType::Point => 0.0,
+            LightType::Directional => 1.0,
+            LightType::Spot => 2.0,
+        };
+        let cone = self.spot_angle.clamp(0.0, 90.0).to_radians().cos();
+        [kind, cone, 0.0, 0.0]
+    }
+}
+
+/// How fog thickens between [`FogParams::start`] and [`FogParams::end`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FogMode {
+    /// Ramps evenly from clear at `start` to fully fogged at `end`.
+    #[default]
+    Linear,
+    /// Thickens exponentially from `start`, reaching 95% at `end`.
+    Exponential,
+}
+
+/// Distance fog blended over shaded fragments.
+///
+/// Fog is measured as the world-space distance from the camera. The default
+/// leaves fog off; an `end` of zero or less disables it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FogParams {
+    pub color: Vec3,
+    pub start: f32,
+    pub end: f32,
+    pub mode: FogMode,
+}
+
+impl Default for FogParams {
+    fn default() -> Self {
+        Self {
+            color: Vec3::splat(0.5),
+            start: 0.0,
+            end: 0.0,
+            mode: FogMode::Linear,
+        }
+    }
+}
+
+impl FogParams {
+    pub fn new(color: impl Into<Vec3>, start: f32, end: f32, mode: FogMode) -> Self {
+        Self {
+            color: color.into(),
+            start,
+            end,
+            mode,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.end > 0.0
+    }
+
+    /// The `fog_color` and `fog_params` uniforms: fog_params holds start,
+    /// end and the mode (0 linear, 1 exponential), with end 0 when off.
+    #[cfg(feature = "graphics")]
+    fn uniforms(&self) -> ([f32; 4], [f32; 4]) {
+        if !self.is_enabled() {
+            return ([0.0; 4], [0.0; 4]);
+        }
+        let mode = match self.mode {
+            FogMode::Linear => 0.0,
+            FogMode::Exponential => 1.0,
+        };
+        let start = self.start.clamp(0.0, self.end);
+        (self.color.extend(1.0).into(), [start, self.end, mode, 0.0])
+    }
+}
+
+/// Geometry totals for one frame's draw list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SceneStats {
+    /// Objects in the snapshot, drawable or not.
+    pub objects: usize,
+    /// Objects that produce a draw call.
+    pub visible_objects: usize,
+    pub triangles: u64,
+    pub vertices: u64,
+}
+
+impl SceneStats {
+    /// Counts one drawn instance of a mesh.
+    pub fn add_mesh(&mut self, vertex_count: u32, index_count: u32) {
+        self.visible_objects += 1;
+        self.triangles += u64::from(index_count / 3);
+        self.vertices += u64::from(vertex_count);
+    }
+}
+
+/// Mesh counts reported by [`Renderer::preload_meshes`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreloadStats {
+    /// Distinct meshes now cached and ready to draw.
+    pub loaded: usize,
+    /// Distinct meshes that failed to load and will draw as the fallback.
+    pub failed: usize,
+}
+
+/// Distinct mesh names referenced by `objects`, in first-use order.
+#[cfg(feature = "graphics")]
+fn referenced_meshes(objects: &[SceneObject]) -> Vec<&str> {
+    let mut seen = std::collections::HashSet::new();
+    objects
+        .iter()
+        .filter_map(|object| object.mesh.as_deref())
+        .filter(|name| seen.insert(*name))
+        .collect()
+}
+
+/// Model matrix for `object` under `root`, given its `world` matrix from
+/// [`world_matrices`](crate::scene::world_matrices), including its mesh
+/// scale.
+///
+/// Billboarded objects take the camera's world rotation in place of their
+/// own, so their local +Z points back at the viewer, while keeping their
+/// position and scale.
+#[cfg(feature = "graphics")]
+fn object_model_matrix(root: Mat4, world: Mat4, object: &SceneObject, camera_view: Mat4) -> Mat4 {
+    let mut model = root * world;
+    if object.billboard {
+        let (scale, _, translation) = model.to_scale_rotation_translation();
+        let (_, camera_rotation, _) = camera_view.inverse().to_scale_rotation_translation();
+        model = Mat4::from_scale_rotation_translation(scale, camera_rotation, translation);
+    }
+    model * Mat4::from_scale(object.mesh_scale)
+}
+
+/// Vertical field of view, in degrees, of a point light's shadow frustum.
+#[cfg(feature = "graphics")]
+const SHADOW_FOV: f32 = 120.0;
+
+/// Radius around the origin the shadow frustum covers when nothing is drawn.
+#[cfg(feature = "graphics")]
+const EMPTY_SHADOW_RADIUS: f32 = 10.0;
+
+/// Axis-aligned box around every local `(min, max)` box under its model
+/// matrix, or `None` when there are none.
+#[cfg(feature = "graphics")]
+fn transformed_bounds(
+    boxes: impl IntoIterator<Item = (Mat4, (Vec3, Vec3))>,
+) -> Option<(Vec3, Vec3)> {
+    boxes
+        .into_iter()
+        .flat_map(|(model, (min, max))| {
+            (0..8).map(move |corner: u32| {
+                let pick =
+                    |bit: u32, low: f32, high: f32| if corner & bit == 0 { low } else { high };
+                model.transform_point3(Vec3::new(
+                    pick(1, min.x, max.x),
+                    pick(2, min.y, max.y),
+                    pick(4, min.z, max.z),
+                ))
+            })
+        })
+        .fold(None, |bounds, point| match bounds {
+            None => Some((point, point)),
+            Some((min, max)) => Some((min.min(point), max.max(point))),
+        })
+}
+
+/// View-projection of the shadow-casting `light`, fitted to the sphere
+/// around the drawn scene's `bounds`.
+///
+/// Directional lights get an orthographic box along their direction and
+/// spot lights a perspective cone along theirs. Point lights look from
+/// their position toward the scene's center.
+#[cfg(feature = "graphics")]
+fn light_view_projection(light: &LightParams, bounds: Option<(Vec3, Vec3)>) -> Mat4 {
+    let (center, radius) = bounds
+        .map(|(min, max)| ((min + max) * 0.5, (max - min).length() * 0.5))
+        .unwrap_or((Vec3::ZERO, EMPTY_SHADOW_RADIUS));
+    let radius = radius.max(0.01);
+    let up_for = |direction: Vec3| {
+        if direction.dot(Vec3::Y).abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        }
+    };
+
+    if light.light_type == LightType::Directional {
+        let direction = light.direction.try_normalize().unwrap_or(Vec3::NEG_Y);
+        // From twice the radius back, the sphere spans depths r..3r.
+        let eye = center - direction * (2.0 * radius);
+        let view = Mat4::look_at_rh(eye, center, up_for(direction));
+        let projection =
+            Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.5 * radius, 3.5 * radius);
+        return projection * view;
+    }
+
+    let (direction, fov) = match light.light_type {
+        LightType::Spot => (
+            light.direction.try_normalize().unwrap_or(Vec3::NEG_Y),
+            (2.0 * light.spot_angle).clamp(1.0, 170.0),
+        ),
+        _ => (
+            (center - light.position)
+                .try_normalize()
+                .unwrap_or(Vec3::NEG_Y),
+            SHADOW_FOV,
+        ),
+    };
+    let distance = light.position.distance(center);
+    let far = (distance + radius).max(0.1);
+    let near = (distance - radius).max(far * 1e-3);
+    let view = Mat4::look_at_rh(
+        light.position,
+        light.position + direction,
+        up_for(direction),
+    );
+    Mat4::perspective_rh(fov.to_radians(), 1.0, near, far) * view
+}
+
+/// Replaces the uniform scale of a scene root built by
+/// [`Scene::root_transform`](crate::Scene::root_transform), keeping its
+/// up-axis rotation.
+#[cfg(feature = "graphics")]
+fn rescaled_root(root: Mat4, scale: f32) -> Mat4 {
+    let (_, rotation, _) = root.to_scale_rotation_translation();
+    Mat4::from_scale(Vec3::splat(scale)) * Mat4::from_quat(rotation)
+}
+
+/// Interleaved position and color vertices for `lines`, two per line, with
+/// the endpoints moved from scene units into the renderer's space by `root`
+/// like every mesh.
+#[cfg(feature = "graphics")]
+fn debug_line_vertices(lines: &[DebugLine], root: Mat4) -> Vec<f32> {
+    lines
+        .iter()
+        .flat_map(|line| {
+            [
+                root.transform_point3(line.start),
+                line.color,
+                root.transform_point3(line.end),
+                line.color,
+            ]
+        })
+        .flat_map(|v| v.to_array())
+        .collect()
+}
+
+/// Clips a viewport rect `(x, y, width, height)`, in pixels from the top
+/// left, to a `target_width` x `target_height` target and returns the
+/// covered pixels as a scissor rect, or `None` when none are covered.
+#[cfg(feature = "graphics")]
+fn viewport_scissor(
+    rect: (f32, f32, f32, f32),
+    target_width: u32,
+    target_height: u32,
+) -> Option<[u32; 4]> {
+    let (x, y, width, height) = rect;
+    if !(width > 0.0 && height > 0.0) {
+        return None;
+    }
+    let clip = |start: f32, end: f32, limit: u32| {
+        let limit = limit as f32;
+        let low = start.floor().clamp(0.0, limit) as u32;
+        let high = end.ceil().clamp(0.0, limit) as u32;
+        (high > low).then_some((low, high - low))
+    };
+    let (left, clipped_width) = clip(x, x + width, target_width)?;
+    let (top, clipped_height) = clip(y, y + height, target_height)?;
+    Some([left, top, clipped_width, clipped_height])
+}
+
+/// Shrinks a viewport rect to what wgpu accepts on a device whose largest
+/// texture side is `max_dimension`: at most that wide and tall, starting
+/// no further than twice that from the origin.
+#[cfg(feature = "graphics")]
+fn clamp_viewport(rect: (f32, f32, f32, f32), max_dimension: u32) -> (f32, f32, f32, f32) {
+    let max = max_dimension as f32;
+    let range = max * 2.0;
+    let width = rect.2.clamp(0.0, max);
+    let height = rect.3.clamp(0.0, max);
+    let x = rect.0.clamp(-range, range - 1.0 - width);
+    let y = rect.1.clamp(-range, range - 1.0 - height);
+    (x, y, width, height)
+}
+
+/// Default half-extent, in world units, of the cross drawn for a debug point.
+const DEFAULT_DEBUG_POINT_SIZE: f32 = 0.1;
+
+/// Colored line segment queued for the debug overlay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLine {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub color: Vec3,
+}
+
+/// Queue of debug lines and points drawn over the next frame.
+///
+/// Clones share the queue, so the host and scripts can submit from any
+/// thread. The renderer drains it every frame; primitives that should stay
+/// visible must be submitted again each frame.
+#[derive(Clone)]
+pub struct DebugDraw {
+    inner: Arc<Mutex<DebugQueue>>,
+}
+
+struct DebugQueue {
+    lines: Vec<DebugLine>,
+    point_size: f32,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(DebugQueue {
+                lines: Vec::new(),
+                point_size: DEFAULT_DEBUG_POINT_SIZE,
+            })),
+        }
+    }
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a line from `start` to `end`.
+    pub fn line(&self, start: Vec3, end: Vec3, color: Vec3) {
+        self.inner
+            .lock()
+            .lines
+            .push(DebugLine { start, end, color });
+    }
+
+    /// Queues a point, drawn as an axis-aligned cross sized by
+    /// [`set_point_size`](Self::set_point_size). GPUs do not widen lines
+    /// or points, so this is the only size control.
+    pub fn point(&self, position: Vec3, color: Vec3) {
+        let mut queue = self.inner.lock();
+        let size = queue.point_size;
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            queue.lines.push(DebugLine {
+                start: position - axis * size,
+                end: position + axis * size,
+                color,
+            });
+        }
+    }
+
+    /// Sets the half-extent, in world units, of crosses queued by later
+    /// [`point`](Self::point) calls.
+    pub fn set_point_size(&self, size: f32) {
+        self.inner.lock().point_size = size.max(0.0);
+    }
+
+    /// Whether lines are queued for the next frame, so hosts that skip
+    /// unchanged frames still draw them.
+    pub fn has_pending(&self) -> bool {
+        !self.inner.lock().lines.is_empty()
+    }
+
+    /// Removes and returns everything queued so far.
+    pub fn take_lines(&self) -> Vec<DebugLine> {
+        std::mem::take(&mut self.inner.lock().lines)
+    }
+}
+
+/// Color format of [`Renderer::render_to_image`] captures, independent of
+/// the window surface: 8-bit RGBA, sRGB-encoded by the GPU on store, so the
+/// same scene reads back the same bytes on every machine.
+#[cfg(feature = "graphics")]
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Gamma the fragment shader applies before writing to a surface of
+/// `format`: none for sRGB formats, which encode on store, and 2.2 for
+/// linear ones, which would otherwise look washed out.
+#[cfg(feature = "graphics")]
+fn surface_output_gamma(format: wgpu::TextureFormat) -> f32 {
+    if format.is_srgb() {
+        info!("surface format {format:?} is sRGB; leaving gamma to the hardware");
+        1.0
+    } else {
+        info!("surface format {format:?} is not sRGB; applying gamma 2.2 in the shader");
+        2.2
+    }
+}
+
+/// Rejects meshes that cannot stand in for the default cube: empty ones and
+/// ones whose indices run past their vertices.
+#[cfg(feature = "graphics")]
+fn check_primitive_mesh(mesh: &crate::ObjMesh) -> anyhow::Result<()> {
+    let vertex_count = mesh.vertices.len() / crate::types::VERTEX_STRIDE;
+    if mesh.indices.is_empty() || !mesh.indices.len().is_multiple_of(3) {
+        anyhow::bail!(
+            "primitive mesh needs whole triangles, got {} indices",
+            mesh.indices.len()
+        );
+    }
+    if let Some(index) = mesh
+        .indices
+        .iter()
+        .find(|&&index| index as usize >= vertex_count)
+    {
+        anyhow::bail!("primitive mesh index {index} is out of range for {vertex_count} vertices");
+    }
+    Ok(())
+}
+
+/// Maps a pick ID read from the ID buffer to the name of the object drawn
+/// with it; 0 marks pixels no object covered.
+#[cfg(all(feature = "graphics", not(target_arch = "wasm32")))]
+fn pick_name(names: &[String], id: u32) -> Option<&str> {
+    let index = usize::try_from(id).ok()?.checked_sub(1)?;
+    names.get(index).map(String::as_str)
+}
+
+/// Picks the geometry drawn for an object: its loaded mesh, `primitive` when
+/// it names no mesh, or `fallback` when its mesh failed to load.
+#[cfg(feature = "graphics")]
+fn select_mesh<'a, M>(
+    name: Option<&str>,
+    cache: &'a std::collections::HashMap<String, M>,
+    primitive: &'a M,
+    fallback: &'a M,
+) -> &'a M {
+    match name {
+        Some(name) => cache.get(name).unwrap_or(fallback),
+        None => primitive,
+    }
+}
+
+/// Whether `object` is drawn with the line pipeline. Wireframe objects fall
+/// back to the fill pipeline when the device has no line polygon mode.
+#[cfg(feature = "graphics")]
+fn uses_line_pipeline(object: &crate::SceneObject, line_available: bool) -> bool {
+    object.wireframe && line_available
+}
+
+/// Object types that never draw anything of their own.
+#[cfg(feature = "graphics")]
+const NON_VISUAL_TYPES: &[&str] = &["camera", "light"];
+
+/// Whether `object` is drawn: anything naming a mesh, `mesh` and `part`
+/// objects, and with `draw_unknown_types` every type except cameras and
+/// lights.
+#[cfg(feature = "graphics")]
+fn object_wants_mesh(object: &crate::SceneObject, draw_unknown_types: bool) -> bool {
+    if object.mesh.is_some() {
+        true
+    } else if draw_unknown_types {
+        !NON_VISUAL_TYPES.contains(&object.object_type.as_str())
+    } else {
+        matches!(object.object_type.as_str(), "mesh" | "part")
+    }
+}
+
+/// Replaces `${VAR}` references in `path` with environment values.
+///
+/// Unset variables expand to nothing and are logged; an unterminated `${`
+/// is kept as written.
+#[cfg(feature = "graphics")]
+fn expand_env_vars(path: &str) -> String {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find("${") {
+        let Some(length) = rest[start + 2..].find('}') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        let variable = &rest[start + 2..start + 2 + length];
+        match std::env::var(variable) {
+            Ok(value) => expanded.push_str(&value),
+            Err(_) => warn!("environment variable {variable} in {path} is not set"),
+        }
+        rest = &rest[start + 3 + length..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn viewport_rect_is_clipped_to_the_target() {
+        assert_eq!(
+            viewport_scissor((0.0, 0.0, 320.0, 480.0), 640, 480),
+            Some([0, 0, 320, 480])
+        );
+        assert_eq!(
+            viewport_scissor((320.5, 100.0, 400.0, 50.25), 640, 480),
+            Some([320, 100, 320, 51])
+        );
+        assert_eq!(
+            viewport_scissor((-10.0, -10.0, 20.0, 20.0), 640, 480),
+            Some([0, 0, 10, 10])
+        );
+        assert_eq!(viewport_scissor((700.0, 0.0, 10.0, 10.0), 640, 480), None);
+        assert_eq!(viewport_scissor((0.0, 0.0, 0.0, 10.0), 640, 480), None);
+        assert_eq!(viewport_scissor((0.0, 0.0, f32::NAN, 10.0), 640, 480), None);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn rescaling_the_root_keeps_the_up_axis() {
+        let scene = crate::Scene::from_xml("<scene up_axis=\"z\" scene_scale=\"2\" />").unwrap();
+        let root = rescaled_root(scene.root_transform(), 0.5);
+        let up = root.transform_vector3(Vec3::Z);
+        assert!(up.abs_diff_eq(Vec3::new(0.0, 0.5, 0.0), 1e-6));
+        assert_eq!(
+            rescaled_root(Mat4::IDENTITY, 3.0),
+            Mat4::from_scale(Vec3::splat(3.0))
+        );
+    }
+
+    /// Normalized device coordinates of `point` under `view_proj`.
+    #[cfg(feature = "graphics")]
+    fn project(view_proj: Mat4, point: Vec3) -> Vec3 {
+        let clip = view_proj * point.extend(1.0);
+        clip.truncate() / clip.w
+    }
+
+    #[cfg(feature = "graphics")]
+    fn in_shadow_frustum(ndc: Vec3) -> bool {
+        ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 && (0.0..=1.0).contains(&ndc.z)
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn scene_bounds_cover_transformed_boxes() {
+        let unit = (Vec3::splat(-0.5), Vec3::splat(0.5));
+        let moved =
+            Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)) * Mat4::from_scale(Vec3::splat(2.0));
+        assert_eq!(
+            transformed_bounds([(Mat4::IDENTITY, unit), (moved, unit)]),
+            Some((Vec3::new(-0.5, -1.0, -1.0), Vec3::new(11.0, 1.0, 1.0)))
+        );
+        assert_eq!(transformed_bounds([]), None);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn directional_shadows_follow_the_light_direction() {
+        let bounds = (Vec3::new(-20.0, 0.0, -20.0), Vec3::new(20.0, 4.0, 20.0));
+        let mut light = LightParams::new(Vec3::new(3.0, 5.0, -3.0), Vec3::ONE, 1.0);
+        light.light_type = LightType::Directional;
+        light.direction = Vec3::new(1.0, -1.0, 0.0).normalize();
+        let view_proj = light_view_projection(&light, Some(bounds));
+
+        for corner in [bounds.0, bounds.1, Vec3::new(-20.0, 4.0, 20.0)] {
+            assert!(in_shadow_frustum(project(view_proj, corner)), "{corner}");
+        }
+        // Depth grows along the light direction, whatever its position.
+        let near = project(view_proj, Vec3::ZERO);
+        let far = project(view_proj, light.direction);
+        assert!(far.z > near.z);
+        assert!((far.truncate() - near.truncate()).length() < 1e-5);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn spot_shadows_look_along_the_cone() {
+        let mut light = LightParams::new(Vec3::new(0.0, 5.0, 0.0), Vec3::ONE, 1.0);
+        light.light_type = LightType::Spot;
+        light.direction = Vec3::new(1.0, 0.0, 0.0);
+        let bounds = (Vec3::splat(-1.0), Vec3::new(8.0, 6.0, 1.0));
+        let view_proj = light_view_projection(&light, Some(bounds));
+        let ahead = project(view_proj, Vec3::new(4.0, 5.0, 0.0));
+        assert!(in_shadow_frustum(ahead), "{ahead}");
+        assert!(ahead.x.abs() < 1e-4 && ahead.y.abs() < 1e-4);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn point_shadows_look_at_the_scene_center() {
+        let bounds = (Vec3::new(4.0, -1.0, 4.0), Vec3::new(6.0, 1.0, 6.0));
+        for position in [
+            Vec3::new(3.0, 5.0, -3.0),
+            Vec3::new(5.0, 5.0, 5.0),
+            Vec3::new(5.0, 0.0, 5.0),
+        ] {
+            let light = LightParams::new(position, Vec3::ONE, 1.0);
+            let view_proj = light_view_projection(&light, Some(bounds));
+            assert!(
+                view_proj.is_finite(),
+                "non-finite projection from {position}"
+            );
+            if position != Vec3::new(5.0, 0.0, 5.0) {
+                let center = project(view_proj, Vec3::new(5.0, 0.0, 5.0));
+                assert!(center.x.abs() < 1e-4 && center.y.abs() < 1e-4);
+                assert!(in_shadow_frustum(center), "{center}");
+            }
+        }
+        let empty = light_view_projection(
+            &LightParams::new(Vec3::new(3.0, 5.0, -3.0), Vec3::ONE, 1.0),
+            None,
+        );
+        assert!(in_shadow_frustum(project(empty, Vec3::ZERO)));
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn debug_lines_follow_the_scene_root() {
+        let scene = crate::Scene::from_xml("<scene up_axis=\"z\" scene_scale=\"2\" />").unwrap();
+        let line = DebugLine {
+            start: Vec3::ZERO,
+            end: Vec3::Z,
+            color: Vec3::X,
+        };
+        let vertices = debug_line_vertices(&[line], scene.root_transform());
+        assert_eq!(vertices.len(), 12);
+        assert_eq!(&vertices[3..6], &[1.0, 0.0, 0.0]);
+        let end = Vec3::from_slice(&vertices[6..9]);
+        assert!(end.abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-6), "{end}");
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn viewport_rect_is_clamped_to_device_limits() {
+        assert_eq!(
+            clamp_viewport((0.0, 0.0, 640.0, 480.0), 8192),
+            (0.0, 0.0, 640.0, 480.0)
+        );
+        assert_eq!(
+            clamp_viewport((-100.0, 10.0, 20000.0, 300.0), 8192),
+            (-100.0, 10.0, 8192.0, 300.0)
+        );
+        assert_eq!(
+            clamp_viewport((16000.0, -20000.0, 500.0, 500.0), 8192),
+            (15883.0, -16384.0, 500.0, 500.0)
+        );
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn linear_surfaces_get_manual_gamma() {
+        assert_eq!(
+            surface_output_gamma(wgpu::TextureFormat::Bgra8UnormSrgb),
+            1.0
+        );
+        assert_eq!(surface_output_gamma(wgpu::TextureFormat::Rgba8Unorm), 2.2);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn wireframe_objects_route_to_line_pipeline() {
+        let wire = crate::SceneObject::builder()
+            .name("Rock")
+            .wireframe(true)
+            .build();
+        let solid = crate::SceneObject::builder().name("Floor").build();
+        assert!(uses_line_pipeline(&wire, true));
+        assert!(!uses_line_pipeline(&solid, true));
+        assert!(!uses_line_pipeline(&wire, false));
+    }
+
+    #[cfg(all(feature = "graphics", not(target_arch = "wasm32")))]
+    #[test]
+    fn pick_ids_map_to_drawn_names() {
+        let names = vec!["Floor".to_string(), "Cube".to_string()];
+        assert_eq!(pick_name(&names, 0), None);
+        assert_eq!(pick_name(&names, 1), Some("Floor"));
+        assert_eq!(pick_name(&names, 2), Some("Cube"));
+        assert_eq!(pick_name(&names, 3), None);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn primitive_mesh_only_replaces_meshless_objects() {
+        let cache = std::collections::HashMap::from([("rock.obj".to_string(), "rock")]);
+        assert_eq!(*select_mesh(None, &cache, &"plane", &"cube"), "plane");
+        assert_eq!(
+            *select_mesh(Some("rock.obj"), &cache, &"plane", &"cube"),
+            "rock"
+        );
+        assert_eq!(
+            *select_mesh(Some("missing.obj"), &cache, &"plane", &"cube"),
+            "cube"
+        );
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn primitive_mesh_must_be_whole_triangles_in_range() {
+        let mut plane = crate::ObjMesh {
+            vertices: vec![0.0; crate::types::VERTEX_STRIDE * 4],
+            indices: vec![0, 1, 2, 0, 2, 3],
+        };
+        assert!(check_primitive_mesh(&plane).is_ok());
+        plane.indices.push(0);
+        assert!(check_primitive_mesh(&plane).is_err());
+        plane.indices = vec![0, 1, 4];
+        assert!(check_primitive_mesh(&plane).is_err());
+        assert!(check_primitive_mesh(&crate::ObjMesh::default()).is_err());
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn expands_environment_variables() {
+        std::env::set_var("CRYSTAL_TEST_ASSETS", "models");
+        assert_eq!(
+            expand_env_vars("${CRYSTAL_TEST_ASSETS}/cube.obj"),
+            "models/cube.obj"
+        );
+        assert_eq!(
+            expand_env_vars("${CRYSTAL_TEST_UNSET_VARIABLE}cube.obj"),
+            "cube.obj"
+        );
+        assert_eq!(expand_env_vars("${open/cube.obj"), "${open/cube.obj");
+    }
+
+    #[test]
+    fn debug_points_expand_to_crosses() {
+        let debug = DebugDraw::new();
+        debug.set_point_size(0.5);
+        debug.line(Vec3::ZERO, Vec3::X, Vec3::ONE);
+        debug.clone().point(Vec3::Y, Vec3::X);
+        assert!(debug.has_pending());
+        let lines = debug.take_lines();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1].start, Vec3::new(-0.5, 1.0, 0.0));
+        assert_eq!(lines[3].end, Vec3::new(0.0, 1.0, 0.5));
+        assert!(debug.take_lines().is_empty());
+        assert!(!debug.has_pending());
+    }
+
+    #[test]
+    fn params_accept_plain_arrays() {
+        let light = LightParams::new([0.0, 5.0, 0.0], [1.0, 0.5, 0.0], 2.0);
+        assert_eq!(light.position, Vec3::new(0.0, 5.0, 0.0));
+        let color: [f32; 3] = light.color.into();
+        assert_eq!(color, [1.0, 0.5, 0.0]);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn light_shape_encodes_type_and_cone() {
+        let mut light = LightParams::new(Vec3::ZERO, Vec3::ONE, 1.0);
+        assert_eq!(light.shape()[0], 0.0);
+        light.light_type = LightType::Spot;
+        light.spot_angle = 60.0;
+        let shape = light.shape();
+        assert_eq!(shape[0], 2.0);
+        assert!((shape[1] - 0.5).abs() < 1e-6);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn fog_uniforms_encode_range_and_mode() {
+        assert_eq!(FogParams::default().uniforms(), ([0.0; 4], [0.0; 4]));
+
+        let fog = FogParams::new([0.2, 0.3, 0.4], 10.0, 50.0, FogMode::Exponential);
+        let (color, params) = fog.uniforms();
+        assert_eq!(color, [0.2, 0.3, 0.4, 1.0]);
+        assert_eq!(params, [10.0, 50.0, 1.0, 0.0]);
+
+        let clamped = FogParams::new(Vec3::ONE, 80.0, 20.0, FogMode::Linear);
+        assert_eq!(clamped.uniforms().1, [20.0, 20.0, 0.0, 0.0]);
+    }
+
+    #[cfg(feature = "mint")]
+    #[test]
+    fn params_convert_to_and_from_mint() {
+        let camera = CameraParams::new(
+            mint::ColumnMatrix4::from(Mat4::IDENTITY),
+            mint::Vector3::from([1.0, 2.0, 3.0]),
+        );
+        assert_eq!(camera.view_proj, Mat4::IDENTITY);
+        let position: mint::Vector3<f32> = camera.position.into();
+        assert_eq!((position.x, position.y, position.z), (1.0, 2.0, 3.0));
+
+        let object = crate::SceneObject::builder()
+            .position(mint::Vector3::from([4.0, 5.0, 6.0]))
+            .build();
+        assert_eq!(object.position, Vec3::new(4.0, 5.0, 6.0));
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn referenced_meshes_are_deduplicated() {
+        let objects = [
+            SceneObject::builder().name("A").mesh("rock.obj").build(),
+            SceneObject::builder().name("B").build(),
+            SceneObject::builder().name("C").mesh("tree.obj").build(),
+            SceneObject::builder().name("D").mesh("rock.obj").build(),
+        ];
+        assert_eq!(referenced_meshes(&objects), ["rock.obj", "tree.obj"]);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn offscreen_format_needs_no_shader_gamma() {
+        assert_eq!(OFFSCREEN_FORMAT.block_copy_size(None), Some(4));
+        assert_eq!(surface_output_gamma(OFFSCREEN_FORMAT), 1.0);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn billboards_face_the_camera() {
+        let objects = [SceneObject::builder()
+            .name("Sprite")
+            .position(Vec3::new(1.0, 0.0, 0.0))
+            .rotation(Vec3::new(0.0, 45.0, 30.0))
+            .scale(Vec3::splat(2.0))
+            .billboard(true)
+            .build()];
+        let eye = Vec3::new(6.0, 0.0, 0.0);
+        let view = Mat4::look_at_rh(eye, Vec3::new(1.0, 0.0, 0.0), Vec3::Y);
+        let model =
+            object_model_matrix(Mat4::IDENTITY, objects[0].local_matrix(), &objects[0], view);
+
+        let facing = model.transform_vector3(Vec3::Z).normalize();
+        assert!(facing.abs_diff_eq(Vec3::X, 1e-5), "{facing}");
+        assert!(model
+            .transform_vector3(Vec3::Y)
+            .abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-5));
+        assert!(model
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::X, 1e-5));
+
+        let plain = SceneObject::builder().billboard(false).build();
+        let model = object_model_matrix(Mat4::IDENTITY, plain.local_matrix(), &plain, view);
+        assert_eq!(model, Mat4::IDENTITY);
+    }
+
+    #[cfg(feature = "graphics")]
+    #[test]
+    fn mesh_scale_stays_out_of_children() {
+        let xml = r#"
+        <scene>
+            <object name="Base" position="0 1 0" scale="2 2 2" mesh_scale="3 1 1">
+                <object name="Lamp" position="1 0 0" />
+            </object>
+        </scene>
+        "#;
+        let scene = crate::Scene::from_xml(xml).unwrap();
+        let (base, lamp) = (&scene.objects[0], &scene.objects[1]);
+        assert_eq!(base.mesh_scale, Vec3::new(3.0, 1.0, 1.0));
+        assert_eq!(lamp.mesh_scale, Vec3::ONE);
+
+        let worlds = crate::scene::world_matrices(&scene.objects);
+        let model = object_model_matrix(Mat4::IDENTITY, worlds[0], base, Mat4::IDENTITY);
+        assert!(model
+            .transform_point3(Vec3::X)
+            .abs_diff_eq(Vec3::new(6.0, 1.0, 0.0), 1e-5));
+
+        let child = crate::scene::world_matrix(&scene.objects, lamp).transform_point3(Vec3::ZERO);
+        assert!(child.abs_diff_eq(Vec3::new(2.0, 1.0, 0.0), 1e-5), "{child}");
+        assert_eq!(worlds[1].transform_point3(Vec3::ZERO), child);
+        let child_model = object_model_matrix(Mat4::IDENTITY, worlds[1], lamp, Mat4::IDENTITY);
+        assert_eq!(child_model.transform_point3(Vec3::ZERO), child);
+    }
+
+    #[test]
+    fn stats_count_every_instance() {
+        let mut stats = SceneStats {
+            objects: 3,
+            ..SceneStats::default()
+        };
+        stats.add_mesh(24, 36);
+        stats.add_mesh(24, 36);
+        assert_eq!(stats.visible_objects, 2);
+        assert_eq!(stats.triangles, 24);
+        assert_eq!(stats.vertices, 48);
+    }
+}
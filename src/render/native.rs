@@ -4,12 +4,25 @@ use std::sync::Arc;
 use anyhow::{anyhow, Context, Result};
 use bytemuck::{bytes_of, Pod, Zeroable};
 use glam::{Mat3, Mat4, Vec3};
-use log::error;
+use log::{error, info, warn};
 use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalSize;
 use winit::window::{Window, WindowId};
 
-use crate::{CGameArchive, ObjMesh, SceneObject};
+use super::{
+    check_primitive_mesh, clamp_viewport, debug_line_vertices, expand_env_vars,
+    light_view_projection, object_model_matrix, object_wants_mesh, pick_name, referenced_meshes,
+    rescaled_root, select_mesh, surface_output_gamma, transformed_bounds, uses_line_pipeline,
+    viewport_scissor, CameraParams, DebugDraw, DebugLine, FogParams, LightParams, PreloadStats,
+    SceneStats, OFFSCREEN_FORMAT,
+};
+use crate::obj::VERTEX_STRIDE;
+use crate::scene::world_matrices;
+use crate::telemetry::load_span;
+use crate::{CGameArchive, ObjMesh, SceneObject, Shading};
+
+/// Callback invoked with the mesh name and error when a mesh fails to load.
+pub type MeshErrorHandler = Arc<dyn Fn(&str, &anyhow::Error) + Send + Sync>;
 
 /// GPU renderer backed by wgpu that draws meshes from the data model.
 pub struct Renderer {
@@ -17,17 +30,67 @@ pub struct Renderer {
     surface: wgpu::Surface<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    adapter: wgpu::Adapter,
+    adapter_info: wgpu::AdapterInfo,
     config: wgpu::SurfaceConfiguration,
     size: PhysicalSize<u32>,
+    configured: bool,
     depth: DepthBuffer,
-    pipeline: wgpu::RenderPipeline,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// Pipelines targeting the surface format.
+    pipelines: ScenePipelines,
+    debug_shader: wgpu::ShaderModule,
+    debug_pipeline_layout: wgpu::PipelineLayout,
+    debug_draw: DebugDraw,
     global_buffer: wgpu::Buffer,
+    /// Uniforms from the last `update_globals`, restored after offscreen
+    /// renders that override the output gamma.
+    globals: GlobalUniform,
     global_bind_group: wgpu::BindGroup,
+    global_layout: wgpu::BindGroupLayout,
     object_layout: wgpu::BindGroupLayout,
+    shadow_pipeline: wgpu::RenderPipeline,
+    shadow_bind_group: wgpu::BindGroup,
+    shadow_sampler: wgpu::Sampler,
+    shadow_map: ShadowMap,
+    shadows_enabled: bool,
+    /// Light passed to the last [`Renderer::update_globals`].
+    light: Option<LightParams>,
+    /// World-space bounds of the last frame's draws, which the shadow
+    /// frustum is fitted to.
+    shadow_bounds: Option<(Vec3, Vec3)>,
+    /// Gamma matching the surface format, see [`surface_output_gamma`].
+    surface_gamma: f32,
+    /// Gamma chosen with [`Renderer::set_output_gamma`], if any.
+    output_gamma: Option<f32>,
     mesh_cache: HashMap<String, MeshBuffers>,
     missing_meshes: HashSet<String>,
+    mesh_error_handler: MeshErrorHandler,
+    expand_env: bool,
+    flip_winding: bool,
+    flip_winding_overrides: HashMap<String, bool>,
     archive: Arc<CGameArchive>,
     default_mesh: MeshBuffers,
+    /// Replaces `default_mesh` for objects that name no mesh.
+    primitive_mesh: Option<MeshBuffers>,
+    /// Root transform converting scene units to meters.
+    scene_root: Mat4,
+    /// Sub-rectangle of the target drawn into, or `None` for all of it.
+    viewport_rect: Option<(f32, f32, f32, f32)>,
+    /// Draw objects of unrecognized types with the default primitive.
+    draw_unknown_types: bool,
+    /// Latest size from [`Renderer::set_pending_size`], applied by `render`.
+    pending_size: Option<PhysicalSize<u32>>,
+    /// View from the last `update_globals`, used to orient billboards.
+    camera_view: Mat4,
+    fog: FogParams,
+    /// Object ID target, present while picking is enabled.
+    picking: Option<PickTarget>,
+    /// Names of the objects drawn last frame; pick ID `n` is entry `n - 1`.
+    pick_names: Vec<String>,
+    /// Target of [`Renderer::render_to_image`], kept between captures.
+    offscreen: Option<OffscreenTarget>,
 }
 
 impl Renderer {
@@ -54,10 +117,18 @@ impl Renderer {
             })
             .await
             .context("failed to acquire GPU adapter")?;
+        let adapter_info = adapter.get_info();
+        info!(
+            "using GPU adapter {} ({:?}, {:?})",
+            adapter_info.name, adapter_info.backend, adapter_info.device_type
+        );
 
+        // Line polygons are optional; without them wireframe objects are
+        // drawn filled.
+        let line_features = adapter.features() & wgpu::Features::POLYGON_MODE_LINE;
         let device_descriptor = wgpu::DeviceDescriptor {
             label: Some("renderer-device"),
-            required_features: wgpu::Features::empty(),
+            required_features: line_features,
             required_limits: wgpu::Limits::default(),
             experimental_features: Default::default(),
             memory_hints: Default::default(),
@@ -69,12 +140,8 @@ impl Renderer {
             .context("failed to create GPU device")?;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .find(|format| format.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
+        let surface_format = preferred_surface_format(&surface_caps);
+        let surface_gamma = surface_output_gamma(surface_format);
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -98,7 +165,9 @@ impl Renderer {
         };
         surface.configure(&device, &config);
 
-        let depth = DepthBuffer::create(&device, config.width, config.height);
+        let depth_format = DepthBuffer::choose_format(&adapter);
+        info!("Using depth format {depth_format:?}");
+        let depth = DepthBuffer::create(&device, depth_format, config.width, config.height);
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("renderer-shader"),
@@ -107,21 +176,37 @@ impl Renderer {
 
         let global_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("global-bind-layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: Some(
-                        std::num::NonZeroU64::new(std::mem::size_of::<GlobalUniform>() as u64)
-                            .unwrap(),
-                    ),
+            entries: &[
+                global_uniform_entry(wgpu::ShaderStages::VERTEX_FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
         });
 
+        // The shadow pass only needs the uniforms; binding the shadow map
+        // there would conflict with using it as the depth attachment. The
+        // debug pass shares this layout and encodes its output in the
+        // fragment stage.
+        let shadow_global_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("shadow-global-bind-layout"),
+                entries: &[global_uniform_entry(wgpu::ShaderStages::VERTEX_FRAGMENT)],
+            });
+
         // Per-object uniform layout
         let object_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("object-bind-layout"),
@@ -152,38 +237,71 @@ impl Renderer {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
-        let global_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("global-bind-group"),
-            layout: &global_layout,
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow-sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        // Shadows start disabled with a placeholder map so the bind group is
+        // always complete.
+        let shadow_map = ShadowMap::create(&device, 1);
+        let global_bind_group = create_global_bind_group(
+            &device,
+            &global_layout,
+            &global_buffer,
+            &shadow_map,
+            &shadow_sampler,
+        );
+        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow-global-bind-group"),
+            layout: &shadow_global_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: global_buffer.as_entire_binding(),
             }],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("renderer-pipeline"),
-            layout: Some(&pipeline_layout),
+        let pipeline = create_scene_pipeline(
+            &device,
+            &pipeline_layout,
+            &shader,
+            surface_format,
+            depth_format,
+            wgpu::PolygonMode::Fill,
+        );
+        let wireframe_pipeline = (!line_features.is_empty()).then(|| {
+            create_scene_pipeline(
+                &device,
+                &pipeline_layout,
+                &shader,
+                surface_format,
+                depth_format,
+                wgpu::PolygonMode::Line,
+            )
+        });
+
+        let shadow_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shadow-shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADOW_SHADER.into()),
+        });
+        let shadow_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("shadow-pipeline-layout"),
+                bind_group_layouts: &[&shadow_global_layout, &object_layout],
+                push_constant_ranges: &[],
+            });
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shadow-pipeline"),
+            layout: Some(&shadow_pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
+                module: &shadow_shader,
+                entry_point: Some("vs_shadow"),
                 compilation_options: Default::default(),
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: (6 * std::mem::size_of::<f32>()) as u64,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttribute {
-                            format: wgpu::VertexFormat::Float32x3,
-                            offset: 0,
-                            shader_location: 0,
-                        },
-                        wgpu::VertexAttribute {
-                            format: wgpu::VertexFormat::Float32x3,
-                            offset: (3 * std::mem::size_of::<f32>()) as u64,
-                            shader_location: 1,
-                        },
-                    ],
-                }],
+                buffers: &[vertex_buffer_layout()],
             },
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
@@ -193,27 +311,42 @@ impl Renderer {
                 ..Default::default()
             },
             depth_stencil: Some(wgpu::DepthStencilState {
-                format: DepthBuffer::FORMAT,
+                format: ShadowMap::FORMAT,
                 depth_write_enabled: true,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: Default::default(),
-                bias: Default::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
             }),
             multisample: wgpu::MultisampleState::default(),
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
+            fragment: None,
             multiview: None,
             cache: None,
         });
 
+        // Debug lines only read the uniforms, so they share the shadow
+        // pass's bind group layout.
+        let debug_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("debug-shader"),
+            source: wgpu::ShaderSource::Wgsl(DEBUG_SHADER.into()),
+        });
+        let debug_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("debug-pipeline-layout"),
+                bind_group_layouts: &[&shadow_global_layout],
+                push_constant_ranges: &[],
+            });
+        let debug_pipeline = create_debug_pipeline(
+            &device,
+            &debug_pipeline_layout,
+            &debug_shader,
+            surface_format,
+            depth_format,
+        );
+
         let default_mesh = MeshBuffers::from_mesh(
             &device,
             &ObjMesh {
@@ -228,17 +361,54 @@ impl Renderer {
             surface,
             device,
             queue,
+            adapter,
+            adapter_info,
             config,
             size,
+            configured: true,
             depth,
-            pipeline,
+            shader,
+            pipeline_layout,
+            pipelines: ScenePipelines {
+                scene: pipeline,
+                wireframe: wireframe_pipeline,
+                debug: debug_pipeline,
+            },
+            debug_shader,
+            debug_pipeline_layout,
+            debug_draw: DebugDraw::new(),
             global_buffer,
+            globals: GlobalUniform::zeroed(),
             global_bind_group,
+            global_layout,
             object_layout,
+            shadow_pipeline,
+            shadow_bind_group,
+            shadow_sampler,
+            shadow_map,
+            surface_gamma,
+            output_gamma: None,
+            shadows_enabled: false,
+            light: None,
+            shadow_bounds: None,
             mesh_cache: HashMap::new(),
             missing_meshes: HashSet::new(),
+            mesh_error_handler: Arc::new(log_mesh_error),
+            expand_env: false,
+            flip_winding: false,
+            flip_winding_overrides: HashMap::new(),
             archive,
             default_mesh,
+            primitive_mesh: None,
+            scene_root: Mat4::IDENTITY,
+            viewport_rect: None,
+            draw_unknown_types: false,
+            pending_size: None,
+            camera_view: Mat4::IDENTITY,
+            fog: FogParams::default(),
+            picking: None,
+            pick_names: Vec::new(),
+            offscreen: None,
         })
     }
 
@@ -252,32 +422,369 @@ impl Renderer {
         &self.window
     }
 
+    /// Describes the adapter and backend chosen during initialization.
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    /// Replaces the callback run when a mesh cannot be loaded.
+    ///
+    /// The default handler logs the failure. Meshes that fail are still
+    /// recorded as missing and drawn with the default cube.
+    pub fn set_mesh_error_handler(&mut self, handler: MeshErrorHandler) {
+        self.mesh_error_handler = handler;
+    }
+
+    /// Reverses the triangle winding of every loaded mesh, for assets
+    /// exported from left-handed tools. Off by default; meshes already
+    /// loaded are reloaded on their next draw.
+    pub fn set_flip_winding(&mut self, enabled: bool) {
+        self.flip_winding = enabled;
+        self.mesh_cache.clear();
+    }
+
+    /// Overrides [`set_flip_winding`](Self::set_flip_winding) for one mesh.
+    pub fn set_mesh_flip_winding(&mut self, name: &str, flip: bool) {
+        self.flip_winding_overrides.insert(name.to_string(), flip);
+        self.mesh_cache.remove(name);
+    }
+
+    /// Replaces the unit cube drawn for `mesh`/`part` objects that name no
+    /// mesh, e.g. with a plane or sphere; `None` restores the cube. Meshes
+    /// that fail to load are still drawn as the cube. Call before the first
+    /// frame to avoid a visible swap.
+    pub fn set_default_mesh(&mut self, mesh: Option<ObjMesh>) -> Result<()> {
+        self.primitive_mesh = match mesh {
+            Some(mesh) => {
+                check_primitive_mesh(&mesh)?;
+                Some(MeshBuffers::from_mesh(&self.device, &mesh, "primitive"))
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Scales every object by `scale` meters per scene unit, usually
+    /// [`Scene::scale`](crate::Scene::scale), keeping the up-axis rotation
+    /// set by [`set_scene_root`](Self::set_scene_root). Pair it with
+    /// `DataModel::set_scene_scale` so the camera and light match.
+    pub fn set_scene_scale(&mut self, scale: f32) {
+        self.scene_root = rescaled_root(self.scene_root, scale);
+    }
+
+    /// Replaces the transform applied on top of every object, usually
+    /// [`Scene::root_transform`](crate::Scene::root_transform) so both the
+    /// scale and up axis apply.
+    pub fn set_scene_root(&mut self, root: Mat4) {
+        self.scene_root = root;
+    }
+
+    /// Draws objects of custom types such as `enemy` or `prop` that name no
+    /// mesh with the default primitive, instead of skipping them. Cameras
+    /// and lights are never drawn. Off by default.
+    pub fn set_draw_unknown_types(&mut self, enabled: bool) {
+        self.draw_unknown_types = enabled;
+    }
+
+    /// Retries mesh names the archive does not contain with `${VAR}`
+    /// references replaced from the process environment. Off by default.
+    pub fn set_expand_env(&mut self, enabled: bool) {
+        self.expand_env = enabled;
+    }
+
     /// Resizes the swap chain to match the new dimensions.
+    ///
+    /// A zero-sized window (e.g. minimized) leaves the surface unconfigured
+    /// until the next non-zero size, at which point it is fully rebuilt.
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        self.pending_size = None;
         if new_size.width == 0 || new_size.height == 0 {
+            self.configured = false;
             return;
         }
         self.size = new_size;
         self.config.width = new_size.width;
         self.config.height = new_size.height;
         self.surface.configure(&self.device, &self.config);
-        self.depth = DepthBuffer::create(&self.device, new_size.width, new_size.height);
+        self.depth = DepthBuffer::create(
+            &self.device,
+            self.depth.format,
+            new_size.width,
+            new_size.height,
+        );
+        if let Some(picking) = &mut self.picking {
+            picking.resize(&self.device, new_size.width, new_size.height);
+        }
+        self.configured = true;
+    }
+
+    /// Enables or disables the object ID pass read by [`Renderer::pick`].
+    ///
+    /// While enabled every frame draws the scene a second time into an
+    /// `R32Uint` target, so leave it off unless picking is needed.
+    pub fn set_picking(&mut self, enabled: bool) {
+        if !enabled {
+            self.picking = None;
+            self.pick_names.clear();
+        } else if self.picking.is_none() {
+            self.picking = Some(PickTarget::create(
+                &self.device,
+                &self.pipeline_layout,
+                &self.shader,
+                self.depth.format,
+                self.size,
+                self.pipelines.wireframe.is_some(),
+            ));
+        }
+    }
+
+    /// Records a new window size for the next [`render`](Self::render) to
+    /// apply, so dragging a window edge reconfigures the surface at most
+    /// once per frame rather than once per `Resized` event.
+    ///
+    /// While the surface is unconfigured, such as before the first frame or
+    /// after a minimize, the size is applied at once.
+    pub fn set_pending_size(&mut self, size: PhysicalSize<u32>) {
+        if self.configured {
+            self.pending_size = Some(size);
+        } else {
+            self.resize(size);
+        }
+    }
+
+    /// Enables or disables shadows cast from the primary light.
+    ///
+    /// `resolution` is the edge length of the square shadow map in texels;
+    /// the map is only reallocated when it changes.
+    pub fn set_shadows(&mut self, enabled: bool, resolution: u32) {
+        self.shadows_enabled = enabled;
+        let resolution = if enabled { resolution.max(1) } else { 1 };
+        if resolution == self.shadow_map.resolution {
+            return;
+        }
+        self.shadow_map = ShadowMap::create(&self.device, resolution);
+        self.global_bind_group = create_global_bind_group(
+            &self.device,
+            &self.global_layout,
+            &self.global_buffer,
+            &self.shadow_map,
+            &self.shadow_sampler,
+        );
+    }
+
+    /// Queries the surface capabilities again and reconfigures it.
+    ///
+    /// Moving the window to another display can change the supported
+    /// formats and present modes; the scene pipeline is rebuilt if the
+    /// current format is no longer offered.
+    pub fn reconfigure(&mut self) {
+        let caps = self.surface.get_capabilities(&self.adapter);
+        if caps.formats.is_empty() {
+            return;
+        }
+        if !caps.formats.contains(&self.config.format) {
+            self.config.format = preferred_surface_format(&caps);
+            self.surface_gamma = surface_output_gamma(self.config.format);
+            self.pipelines = self.create_pipelines(self.config.format);
+        }
+        if !caps.present_modes.contains(&self.config.present_mode) {
+            self.config.present_mode = wgpu::PresentMode::Fifo;
+        }
+        if !caps.alpha_modes.contains(&self.config.alpha_mode) {
+            self.config.alpha_mode = caps.alpha_modes[0];
+        }
+        self.resize(self.window.inner_size());
+    }
+
+    /// Builds the scene, wireframe and debug pipelines for a color target
+    /// of `format`, with a wireframe pipeline only if the surface has one.
+    fn create_pipelines(&self, format: wgpu::TextureFormat) -> ScenePipelines {
+        let polygon_pipeline = |mode| {
+            create_scene_pipeline(
+                &self.device,
+                &self.pipeline_layout,
+                &self.shader,
+                format,
+                self.depth.format,
+                mode,
+            )
+        };
+        ScenePipelines {
+            scene: polygon_pipeline(wgpu::PolygonMode::Fill),
+            wireframe: self
+                .pipelines
+                .wireframe
+                .is_some()
+                .then(|| polygon_pipeline(wgpu::PolygonMode::Line)),
+            debug: create_debug_pipeline(
+                &self.device,
+                &self.debug_pipeline_layout,
+                &self.debug_shader,
+                format,
+                self.depth.format,
+            ),
+        }
+    }
+
+    /// Returns whether the surface is configured for a drawable size.
+    pub fn is_configured(&self) -> bool {
+        self.configured
+    }
+
+    /// Restricts drawing to the `(x, y, width, height)` rect, in pixels
+    /// from the top left of the target, or to the whole target for `None`.
+    ///
+    /// Each frame clears the whole target and draws one view, so the rest
+    /// of it shows the clear color. Build the camera for the rect's aspect
+    /// ratio. Sizes past the device's texture limit are clamped to it.
+    pub fn set_viewport_rect(&mut self, rect: Option<(f32, f32, f32, f32)>) {
+        self.viewport_rect = rect;
+    }
+
+    /// Applies the viewport rect to `pass` on a `width` x `height` target,
+    /// returning `false` when the rect covers none of it.
+    fn apply_viewport_rect(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        width: u32,
+        height: u32,
+    ) -> bool {
+        let Some(rect) = self.viewport_rect else {
+            return true;
+        };
+        let Some([x, y, scissor_width, scissor_height]) = viewport_scissor(rect, width, height)
+        else {
+            return false;
+        };
+        let (x_offset, y_offset, viewport_width, viewport_height) =
+            clamp_viewport(rect, self.device.limits().max_texture_dimension_2d);
+        pass.set_viewport(
+            x_offset,
+            y_offset,
+            viewport_width,
+            viewport_height,
+            0.0,
+            1.0,
+        );
+        pass.set_scissor_rect(x, y, scissor_width, scissor_height);
+        true
+    }
+
+    /// Overrides the gamma applied to shader output; `None` restores the
+    /// default picked from the surface format, and `Some(1.0)` disables
+    /// manual correction. Takes effect on the next `update_globals`.
+    pub fn set_output_gamma(&mut self, gamma: Option<f32>) {
+        self.output_gamma = gamma.filter(|gamma| *gamma > 0.0);
+    }
+
+    /// Sets the distance fog; takes effect on the next `update_globals`.
+    pub fn set_fog(&mut self, fog: FogParams) {
+        self.fog = fog;
+    }
+
+    pub fn fog(&self) -> FogParams {
+        self.fog
+    }
+
+    /// Gamma currently applied to shader output.
+    pub fn output_gamma(&self) -> f32 {
+        self.output_gamma.unwrap_or(self.surface_gamma)
+    }
+
+    /// Handle to the queue of debug lines and points drawn over the next
+    /// frame, for passing to scripts or other threads.
+    pub fn debug_draw(&self) -> DebugDraw {
+        self.debug_draw.clone()
+    }
+
+    /// Queues a line drawn over the scene in the next frame only.
+    pub fn draw_debug_line(&self, start: Vec3, end: Vec3, color: Vec3) {
+        self.debug_draw.line(start, end, color);
+    }
+
+    /// Queues a point drawn over the scene in the next frame only.
+    pub fn draw_debug_point(&self, position: Vec3, color: Vec3) {
+        self.debug_draw.point(position, color);
+    }
+
+    /// Sets the half-extent, in world units, of debug point crosses.
+    pub fn set_debug_point_size(&self, size: f32) {
+        self.debug_draw.set_point_size(size);
     }
 
     /// Updates the camera and lighting uniforms before rendering.
-    pub fn update_globals(&self, camera: &CameraParams, light: &LightParams) {
+    pub fn update_globals(&mut self, camera: &CameraParams, light: &LightParams) {
+        let shadow_params = if self.shadows_enabled {
+            [1.0, 0.002, 1.0 / self.shadow_map.resolution as f32, 0.0]
+        } else {
+            [0.0; 4]
+        };
+        self.camera_view = camera.view;
+        self.light = Some(*light);
+        let (fog_color, fog_params) = self.fog.uniforms();
         let uniform = GlobalUniform {
             view_proj: camera.view_proj.to_cols_array_2d(),
+            light_view_proj: light_view_projection(light, self.shadow_bounds).to_cols_array_2d(),
             camera_position: camera.position.extend(1.0).into(),
             light_position: light.position.extend(1.0).into(),
             light_color: light.color.extend(light.intensity).into(),
+            light_direction: light.direction.extend(0.0).into(),
+            light_shape: light.shape(),
+            shadow_params,
+            output_params: [self.output_gamma(), 0.0, 0.0, 0.0],
+            fog_color,
+            fog_params,
         };
         self.queue
             .write_buffer(&self.global_buffer, 0, bytes_of(&uniform));
+        self.globals = uniform;
     }
 
-    /// Draws the meshes stored in the provided scene snapshot.
+    /// Draws `objects`; shorthand for [`prepare_frame`](Self::prepare_frame)
+    /// followed by [`render_prepared`](Self::render_prepared).
+    ///
+    /// Frames are skipped while the window has no drawable area.
     pub fn render(&mut self, objects: &[SceneObject]) -> Result<(), wgpu::SurfaceError> {
+        let frame = self.prepare_frame(objects);
+        self.render_prepared(frame)
+    }
+
+    /// Builds the per-object uniforms for `objects` without touching the
+    /// surface or loading meshes, so a caller can hold the data model's
+    /// read lock for just this step.
+    pub fn prepare_frame(&mut self, objects: &[SceneObject]) -> PreparedFrame {
+        let pick_names = if self.picking.is_some() {
+            objects
+                .iter()
+                .filter(|object| object_wants_mesh(object, self.draw_unknown_types))
+                .map(|object| object.name.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        PreparedFrame {
+            draws: self.prepare_draws(objects),
+            pick_names,
+        }
+    }
+
+    /// Loads the meshes `frame` needs, then draws and presents it.
+    ///
+    /// Frames are skipped while the window has no drawable area.
+    pub fn render_prepared(&mut self, frame: PreparedFrame) -> Result<(), wgpu::SurfaceError> {
+        if let Some(size) = self.pending_size.take() {
+            if size != self.size {
+                self.resize(size);
+            }
+        }
+        if !self.configured {
+            // Restoring from a zero-sized window does not always deliver a
+            // resize event, so pick up the current size before drawing.
+            self.resize(self.window.inner_size());
+            if !self.configured {
+                return Ok(());
+            }
+        }
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -288,32 +795,88 @@ impl Renderer {
                 label: Some("renderer-encoder"),
             });
 
-        // Build the draw list and ensure meshes are cached
-        let mut draw_list = Vec::new();
-        for (index, object) in objects.iter().enumerate() {
-            if !object_wants_mesh(object) {
-                continue;
-            }
-            if let Some(name) = object.mesh.as_deref() {
-                self.ensure_mesh_loaded(name);
-                draw_list.push((Some(name.to_string()), index));
-            } else {
-                draw_list.push((None, index));
+        self.load_draw_meshes(&frame.draws);
+        self.fit_shadow_frustum(&frame.draws);
+        let bind_groups = frame.draws;
+        let debug_lines = self.debug_draw.take_lines();
+        self.encode_scene(
+            &mut encoder,
+            &self.pipelines,
+            SceneTarget {
+                color: &view,
+                depth: &self.depth.view,
+                width: self.config.width,
+                height: self.config.height,
+            },
+            &bind_groups,
+            &debug_lines,
+        );
+
+        if let Some(picking) = &self.picking {
+            let mut pick_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("pick-pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &picking.view,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &picking.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            if self.apply_viewport_rect(&mut pick_pass, self.config.width, self.config.height) {
+                pick_pass.set_pipeline(&picking.pipeline);
+                pick_pass.set_bind_group(0, &self.global_bind_group, &[]);
+                self.draw_meshes(&mut pick_pass, &bind_groups, false);
+                if let Some(wireframe_pipeline) = &picking.wireframe {
+                    pick_pass.set_pipeline(wireframe_pipeline);
+                }
+                self.draw_meshes(&mut pick_pass, &bind_groups, true);
             }
+            drop(pick_pass);
+            self.pick_names = frame.pick_names;
         }
 
-        // Begin the single render pass
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+
+    /// Builds one bind group per drawn object, numbering pick IDs from 1 in
+    /// draw order. Meshes are only named here; see
+    /// [`load_draw_meshes`](Self::load_draw_meshes).
+    fn prepare_draws(&self, objects: &[SceneObject]) -> Vec<ObjectDraw> {
+        let draw_list: Vec<_> = objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| object_wants_mesh(object, self.draw_unknown_types))
+            .map(|(index, object)| (object.mesh.clone(), index))
+            .collect();
+
+        let worlds = world_matrices(objects);
         let mut bind_groups = Vec::new();
 
-        for (mesh_name, obj_index) in draw_list.iter() {
+        for (pick_id, (mesh_name, obj_index)) in (1..).zip(draw_list.iter()) {
             let object = &objects[*obj_index];
-            let model = object_model_matrix(object);
-            let normal = Mat3::from_mat4(model).inverse().transpose();
-            let constants = ObjectConstants {
-                model: model.to_cols_array_2d(),
-                normal: mat3_to_3x4(normal),
-                color: object.color.extend(1.0).into(),
-            };
+            let model = object_model_matrix(
+                self.scene_root,
+                worlds[*obj_index],
+                object,
+                self.camera_view,
+            );
+            let mut constants = ObjectConstants::new(model, object);
+            constants.shading[1] = pick_id;
 
             let object_buffer = self
                 .device
@@ -332,13 +895,48 @@ impl Renderer {
                 label: Some("object-bind-group"),
             });
 
-            bind_groups.push((mesh_name.clone(), object_bind_group));
+            let wireframe = uses_line_pipeline(object, self.pipelines.wireframe.is_some());
+            bind_groups.push((mesh_name.clone(), object_bind_group, wireframe, model));
+        }
+
+        bind_groups
+    }
+
+    /// Records the shadow pass, then the scene and `debug_lines` into
+    /// `target` with `pipelines`, which must match its formats.
+    fn encode_scene(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipelines: &ScenePipelines,
+        target: SceneTarget<'_>,
+        bind_groups: &[ObjectDraw],
+        debug_lines: &[DebugLine],
+    ) {
+        if self.shadows_enabled {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow-pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.shadow_map.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            shadow_pass.set_pipeline(&self.shadow_pipeline);
+            shadow_pass.set_bind_group(0, &self.shadow_bind_group, &[]);
+            self.draw_meshes(&mut shadow_pass, bind_groups, false);
+            self.draw_meshes(&mut shadow_pass, bind_groups, true);
         }
 
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("main-pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
+                view: target.color,
                 depth_slice: None,
                 resolve_target: None,
                 ops: wgpu::Operations {
@@ -352,7 +950,7 @@ impl Renderer {
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth.view,
+                view: target.depth,
                 depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Clear(1.0),
                     store: wgpu::StoreOp::Store,
@@ -363,25 +961,359 @@ impl Renderer {
             occlusion_query_set: None,
         });
 
-        pass.set_pipeline(&self.pipeline);
+        if !self.apply_viewport_rect(&mut pass, target.width, target.height) {
+            return;
+        }
+        pass.set_pipeline(&pipelines.scene);
         pass.set_bind_group(0, &self.global_bind_group, &[]);
+        self.draw_meshes(&mut pass, bind_groups, false);
+        if let Some(wireframe_pipeline) = &pipelines.wireframe {
+            pass.set_pipeline(wireframe_pipeline);
+            self.draw_meshes(&mut pass, bind_groups, true);
+        }
 
-        for ((mesh_name, _obj_index), (_, bind_group)) in draw_list.iter().zip(bind_groups.iter()) {
-            let mesh = match mesh_name.as_ref() {
-                Some(name) => self.mesh_cache.get(name).unwrap_or(&self.default_mesh),
-                None => &self.default_mesh,
-            };
+        if !debug_lines.is_empty() {
+            let vertices = debug_line_vertices(debug_lines, self.scene_root);
+            let debug_buffer = self
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("debug-lines"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+            pass.set_pipeline(&pipelines.debug);
+            pass.set_bind_group(0, &self.shadow_bind_group, &[]);
+            pass.set_vertex_buffer(0, debug_buffer.slice(..));
+            pass.draw(0..(debug_lines.len() * 2) as u32, 0..1);
+        }
+    }
+
+    /// Renders `objects` into an offscreen [`OFFSCREEN_FORMAT`] image and
+    /// returns its pixels as tightly packed RGBA8 rows from the top left.
+    ///
+    /// The camera and light come from the last `update_globals`, so build
+    /// the camera for `width / height`. Pixels do not depend on the
+    /// surface format or [`Renderer::set_output_gamma`], which makes them
+    /// comparable across machines. Debug lines stay queued for the next
+    /// window frame. Blocks until the GPU finishes.
+    pub fn render_to_image(
+        &mut self,
+        objects: &[SceneObject],
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>> {
+        if width == 0 || height == 0 {
+            return Err(anyhow!("cannot render an image of {width}x{height} pixels"));
+        }
+        match &mut self.offscreen {
+            Some(target) => target.resize(&self.device, width, height),
+            None => {
+                let pipelines = self.create_pipelines(OFFSCREEN_FORMAT);
+                self.offscreen = Some(OffscreenTarget::create(
+                    &self.device,
+                    pipelines,
+                    self.depth.format,
+                    width,
+                    height,
+                ));
+            }
+        }
+        let bind_groups = self.prepare_draws(objects);
+        self.load_draw_meshes(&bind_groups);
+        self.fit_shadow_frustum(&bind_groups);
+        let target = self
+            .offscreen
+            .as_ref()
+            .expect("offscreen target was created");
+
+        // The sRGB target encodes on write, so the shader must not.
+        let mut globals = self.globals;
+        globals.output_params[0] = 1.0;
+        self.queue
+            .write_buffer(&self.global_buffer, 0, bytes_of(&globals));
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("offscreen-encoder"),
+            });
+        self.encode_scene(
+            &mut encoder,
+            &target.pipelines,
+            SceneTarget {
+                color: &target.view,
+                depth: &target.depth.view,
+                width,
+                height,
+            },
+            &bind_groups,
+            &[],
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.queue
+            .write_buffer(&self.global_buffer, 0, bytes_of(&self.globals));
+
+        self.read_texture(&target.texture, wgpu::TextureAspect::All, 4)
+            .context("failed to read back offscreen image")
+    }
+
+    /// Copies a whole texture into memory as tightly packed rows of
+    /// `bytes_per_pixel`, blocking until the GPU finishes.
+    fn read_texture(
+        &self,
+        texture: &wgpu::Texture,
+        aspect: wgpu::TextureAspect,
+        bytes_per_pixel: u32,
+    ) -> Result<Vec<u8>> {
+        let size = texture.size();
+        let (width, height) = (size.width, size.height);
+        let row_bytes = width * bytes_per_pixel;
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("texture-readback"),
+            size: u64::from(padded_row_bytes) * u64::from(height),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("texture-readback-encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .context("failed waiting for texture readback")?;
+        receiver
+            .recv()
+            .context("texture readback was dropped")?
+            .context("failed to map texture readback buffer")?;
+
+        let mapped = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        for row in mapped.chunks_exact(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        drop(mapped);
+        buffer.unmap();
+        Ok(pixels)
+    }
+
+    /// Reads back the depth buffer of the last rendered frame as raw
+    /// `0..=1` values, one per pixel in row-major order from the top left.
+    ///
+    /// Blocks until the GPU finishes. Fails if the depth format cannot be
+    /// copied, which is only the case when `Depth32Float` is unsupported.
+    pub fn capture_depth(&mut self) -> Result<Vec<f32>> {
+        if self.depth.format != wgpu::TextureFormat::Depth32Float {
+            return Err(anyhow!(
+                "depth format {:?} cannot be read back",
+                self.depth.format
+            ));
+        }
+        let bytes = self
+            .read_texture(&self.depth.texture, wgpu::TextureAspect::DepthOnly, 4)
+            .context("failed to read back depth")?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|value| f32::from_ne_bytes(value.try_into().expect("four bytes")))
+            .collect())
+    }
+
+    /// Returns the name of the object drawn at window pixel `(x, y)` in the
+    /// last frame, or `None` for background, out-of-range coordinates, or
+    /// when picking is disabled with [`Renderer::set_picking`].
+    ///
+    /// Blocks until the GPU finishes; readback failures are logged.
+    pub fn pick(&self, x: u32, y: u32) -> Option<String> {
+        let picking = self.picking.as_ref()?;
+        let size = picking.texture.size();
+        if x >= size.width || y >= size.height {
+            return None;
+        }
+        match self.read_pick_id(&picking.texture, x, y) {
+            Ok(id) => pick_name(&self.pick_names, id).map(str::to_owned),
+            Err(err) => {
+                warn!("Failed to read pick buffer: {err:#}");
+                None
+            }
+        }
+    }
+
+    fn read_pick_id(&self, texture: &wgpu::Texture, x: u32, y: u32) -> Result<u32> {
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pick-readback"),
+            size: u64::from(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("pick-readback-encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .context("failed waiting for pick readback")?;
+        receiver
+            .recv()
+            .context("pick readback was dropped")?
+            .context("failed to map pick readback buffer")?;
+
+        let mapped = slice.get_mapped_range();
+        let id = u32::from_ne_bytes(mapped[..4].try_into().expect("four bytes"));
+        drop(mapped);
+        buffer.unmap();
+        Ok(id)
+    }
+
+    /// Loads every mesh `objects` reference so the first frames do not stall
+    /// on lazy loads, e.g. behind a loading screen before the first
+    /// `render`.
+    ///
+    /// Meshes that fail are reported to the mesh error handler once and
+    /// remembered, exactly as when `render` meets them.
+    pub fn preload_meshes(&mut self, objects: &[SceneObject]) -> PreloadStats {
+        let mut stats = PreloadStats::default();
+        for name in referenced_meshes(objects) {
+            self.ensure_mesh_loaded(name);
+            if self.mesh_cache.contains_key(name) {
+                stats.loaded += 1;
+            } else {
+                stats.failed += 1;
+            }
+        }
+        stats
+    }
+
+    /// Totals the geometry `render` would draw for `objects`.
+    ///
+    /// Meshes are loaded on demand, so this may be called before the first
+    /// frame. Objects without a usable mesh count as the geometry drawn in
+    /// their place.
+    pub fn scene_stats(&mut self, objects: &[SceneObject]) -> SceneStats {
+        let mut stats = SceneStats {
+            objects: objects.len(),
+            ..SceneStats::default()
+        };
+        let draw_unknown_types = self.draw_unknown_types;
+        for object in objects
+            .iter()
+            .filter(|object| object_wants_mesh(object, draw_unknown_types))
+        {
+            if let Some(name) = object.mesh.as_deref() {
+                self.ensure_mesh_loaded(name);
+            }
+            let mesh = self.mesh_for(object.mesh.as_deref());
+            stats.add_mesh(mesh.vertex_count, mesh.index_count);
+        }
+        stats
+    }
+
+    /// Issues one indexed draw per object using its per-object bind group,
+    /// limited to the objects whose wireframe flag matches `wireframe`.
+    fn draw_meshes(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        bind_groups: &[ObjectDraw],
+        wireframe: bool,
+    ) {
+        for (mesh_name, bind_group, _, _) in bind_groups
+            .iter()
+            .filter(|(_, _, line, _)| *line == wireframe)
+        {
+            let mesh = self.mesh_for(mesh_name.as_deref());
 
             pass.set_vertex_buffer(0, mesh.vertex.slice(..));
             pass.set_index_buffer(mesh.index.slice(..), wgpu::IndexFormat::Uint32);
             pass.set_bind_group(1, bind_group, &[]);
             pass.draw_indexed(0..mesh.index_count, 0, 0..1);
         }
+    }
 
-        drop(pass); // explicit to satisfy lifetimes on some backends
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
-        Ok(())
+    fn mesh_for(&self, name: Option<&str>) -> &MeshBuffers {
+        let primitive = self.primitive_mesh.as_ref().unwrap_or(&self.default_mesh);
+        select_mesh(name, &self.mesh_cache, primitive, &self.default_mesh)
+    }
+
+    /// Loads every mesh `draws` names that is not cached yet.
+    fn load_draw_meshes(&mut self, draws: &[ObjectDraw]) {
+        for (mesh_name, _, _, _) in draws {
+            if let Some(name) = mesh_name {
+                self.ensure_mesh_loaded(name);
+            }
+        }
+    }
+
+    /// Refits the shadow frustum to the world-space bounds of `draws`,
+    /// whose meshes must already be loaded.
+    fn fit_shadow_frustum(&mut self, draws: &[ObjectDraw]) {
+        let bounds = transformed_bounds(
+            draws
+                .iter()
+                .map(|(name, _, _, model)| (*model, self.mesh_for(name.as_deref()).bounds)),
+        );
+        if bounds == self.shadow_bounds {
+            return;
+        }
+        self.shadow_bounds = bounds;
+        if let Some(light) = &self.light {
+            self.globals.light_view_proj = light_view_projection(light, bounds).to_cols_array_2d();
+            self.queue
+                .write_buffer(&self.global_buffer, 0, bytes_of(&self.globals));
+        }
     }
 
     fn ensure_mesh_loaded(&mut self, name: &str) {
@@ -393,68 +1325,296 @@ impl Renderer {
                 self.mesh_cache.insert(name.to_string(), mesh);
             }
             Err(err) => {
-                error!("failed to load mesh {name}: {err:?}");
+                (self.mesh_error_handler)(name, &err);
                 self.missing_meshes.insert(name.to_string());
             }
         }
     }
 
     fn load_mesh(&self, name: &str) -> Result<MeshBuffers> {
-        let bytes = self
-            .archive
-            .extract_file(name)
-            .with_context(|| format!("unable to extract {name} from archive"))?;
+        let span = load_span("mesh_load", &name);
+        let bytes = match self.archive.extract_file(name) {
+            Ok(bytes) => bytes,
+            Err(_) if self.expand_env && name.contains("${") => {
+                let expanded = expand_env_vars(name);
+                self.archive.extract_file(&expanded).with_context(|| {
+                    format!("unable to extract {name} (expanded to {expanded}) from archive")
+                })?
+            }
+            Err(err) => return Err(err.context(format!("unable to extract {name} from archive"))),
+        };
+        #[cfg(feature = "gltf")]
+        if is_gltf(name) {
+            let mut mesh = crate::ObjMesh::default();
+            for primitive in crate::load_gltf_from_bytes(&bytes)
+                .with_context(|| format!("failed to parse glTF mesh {name}"))?
+            {
+                mesh.append(&primitive);
+            }
+            span.finish(mesh.indices.len() / 3);
+            return Ok(self.upload_mesh(mesh, name));
+        }
         let contents =
             String::from_utf8(bytes).with_context(|| format!("{name} is not valid UTF-8"))?;
         let mesh = crate::load_obj_from_str(&contents)
             .with_context(|| format!("failed to parse OBJ mesh {name}"))?;
-        Ok(MeshBuffers::from_mesh(&self.device, &mesh, name))
+        span.finish(mesh.indices.len() / 3);
+        Ok(self.upload_mesh(mesh, name))
+    }
+
+    fn upload_mesh(&self, mut mesh: ObjMesh, name: &str) -> MeshBuffers {
+        let flip = self
+            .flip_winding_overrides
+            .get(name)
+            .copied()
+            .unwrap_or(self.flip_winding);
+        if flip {
+            mesh.flip_winding();
+        }
+        MeshBuffers::from_mesh(&self.device, &mesh, name)
     }
 }
 
-fn object_model_matrix(object: &SceneObject) -> Mat4 {
-    let translation = Mat4::from_translation(object.position);
-    let rotation = Mat4::from_rotation_z(object.rotation.z.to_radians())
-        * Mat4::from_rotation_y(object.rotation.y.to_radians())
-        * Mat4::from_rotation_x(object.rotation.x.to_radians());
-    let scale = Mat4::from_scale(object.scale);
-    translation * rotation * scale
+#[cfg(feature = "gltf")]
+fn is_gltf(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".glb") || name.ends_with(".gltf")
 }
 
-fn mat3_to_3x4(matrix: Mat3) -> [[f32; 4]; 3] {
-    let cols = matrix.to_cols_array();
-    [
-        [cols[0], cols[1], cols[2], 0.0],
-        [cols[3], cols[4], cols[5], 0.0],
-        [cols[6], cols[7], cols[8], 0.0],
-    ]
+fn log_mesh_error(name: &str, err: &anyhow::Error) {
+    error!("failed to load mesh {name}: {err:?}");
 }
 
-fn object_wants_mesh(object: &SceneObject) -> bool {
-    if object.mesh.is_some() {
-        true
-    } else {
-        matches!(object.object_type.as_str(), "mesh" | "part")
+/// Prefers an sRGB surface format, falling back to the first one offered.
+fn preferred_surface_format(caps: &wgpu::SurfaceCapabilities) -> wgpu::TextureFormat {
+    caps.formats
+        .iter()
+        .find(|format| format.is_srgb())
+        .copied()
+        .unwrap_or(caps.formats[0])
+}
+
+/// Builds the lit scene pipeline targeting `format`.
+fn create_scene_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    polygon_mode: wgpu::PolygonMode,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(match polygon_mode {
+            wgpu::PolygonMode::Fill => "renderer-pipeline",
+            _ => "renderer-wireframe-pipeline",
+        }),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[vertex_buffer_layout()],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Builds the overlay pipeline for debug lines, which ignores depth so
+/// they stay visible through geometry.
+fn create_debug_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("debug-pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_debug"),
+            compilation_options: Default::default(),
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: (6 * std::mem::size_of::<f32>()) as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &ATTRIBUTES,
+            }],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_debug"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Builds the pipeline writing pick IDs, sharing the scene vertex stage and
+/// `polygon_mode` so IDs cover exactly the pixels the scene pass draws.
+fn create_pick_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    depth_format: wgpu::TextureFormat,
+    polygon_mode: wgpu::PolygonMode,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(match polygon_mode {
+            wgpu::PolygonMode::Fill => "pick-pipeline",
+            _ => "pick-wireframe-pipeline",
+        }),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[vertex_buffer_layout()],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_pick"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: PickTarget::FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x3];
+    wgpu::VertexBufferLayout {
+        array_stride: (VERTEX_STRIDE * std::mem::size_of::<f32>()) as u64,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &ATTRIBUTES,
+    }
+}
+
+fn global_uniform_entry(visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: Some(
+                std::num::NonZeroU64::new(std::mem::size_of::<GlobalUniform>() as u64).unwrap(),
+            ),
+        },
+        count: None,
     }
 }
 
-/// Camera parameters consumed by the renderer's uniform buffer.
-pub struct CameraParams {
-    pub view_proj: Mat4,
-    pub position: Vec3,
+fn create_global_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    shadow_map: &ShadowMap,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("global-bind-group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
 }
 
-/// Lighting state consumed by the renderer's uniform buffer.
-pub struct LightParams {
-    pub position: Vec3,
-    pub color: Vec3,
-    pub intensity: f32,
+fn mat3_to_3x4(matrix: Mat3) -> [[f32; 4]; 3] {
+    let cols = matrix.to_cols_array();
+    [
+        [cols[0], cols[1], cols[2], 0.0],
+        [cols[3], cols[4], cols[5], 0.0],
+        [cols[6], cols[7], cols[8], 0.0],
+    ]
 }
 
 struct MeshBuffers {
     vertex: wgpu::Buffer,
     index: wgpu::Buffer,
+    vertex_count: u32,
     index_count: u32,
+    /// Local-space `(min, max)` corners of the vertex positions.
+    bounds: (Vec3, Vec3),
 }
 
 impl MeshBuffers {
@@ -472,20 +1632,54 @@ impl MeshBuffers {
         Self {
             vertex,
             index,
+            vertex_count: (mesh.vertices.len() / VERTEX_STRIDE) as u32,
             index_count: mesh.indices.len() as u32,
+            bounds: mesh.bounds().unwrap_or_default(),
         }
     }
 }
 
 struct DepthBuffer {
-    _texture: wgpu::Texture,
+    texture: wgpu::Texture,
     view: wgpu::TextureView,
+    format: wgpu::TextureFormat,
 }
 
 impl DepthBuffer {
-    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+    /// Depth formats in order of preference. `Depth32Float` comes ahead of
+    /// `Depth24Plus` because it is the only one whose depth can be copied
+    /// out for [`Renderer::capture_depth`].
+    const CANDIDATES: [wgpu::TextureFormat; 3] = [
+        wgpu::TextureFormat::Depth32Float,
+        wgpu::TextureFormat::Depth24Plus,
+        wgpu::TextureFormat::Depth24PlusStencil8,
+    ];
+
+    /// Picks the first candidate the adapter can render to.
+    fn choose_format(adapter: &wgpu::Adapter) -> wgpu::TextureFormat {
+        Self::CANDIDATES
+            .into_iter()
+            .find(|format| {
+                adapter
+                    .get_texture_format_features(*format)
+                    .allowed_usages
+                    .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            })
+            .unwrap_or(Self::CANDIDATES[0])
+    }
+
+    /// Texture usages for a depth buffer of `format`. Only `Depth32Float`
+    /// may be a copy source; WebGPU rejects copies from the `Depth24Plus`
+    /// formats, so adding `COPY_SRC` there fails texture creation.
+    fn usage(format: wgpu::TextureFormat) -> wgpu::TextureUsages {
+        if format == wgpu::TextureFormat::Depth32Float {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        }
+    }
 
-    fn create(device: &wgpu::Device, width: u32, height: u32) -> Self {
+    fn create(device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("depth-texture"),
             size: wgpu::Extent3d {
@@ -496,14 +1690,202 @@ impl DepthBuffer {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: Self::usage(format),
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            texture,
+            view,
+            format,
+        }
+    }
+}
+
+/// Object IDs drawn by the pick pass, with a depth buffer of its own so the
+/// nearest object wins each pixel.
+struct PickTarget {
+    pipeline: wgpu::RenderPipeline,
+    /// Line-mode twin of `pipeline` for wireframe objects, so only their
+    /// edges can be picked, as only their edges are drawn.
+    wireframe: Option<wgpu::RenderPipeline>,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    depth: DepthBuffer,
+}
+
+impl PickTarget {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+    fn create(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        depth_format: wgpu::TextureFormat,
+        size: PhysicalSize<u32>,
+        wireframe: bool,
+    ) -> Self {
+        let pipeline = |mode| create_pick_pipeline(device, layout, shader, depth_format, mode);
+        let (texture, view) = Self::create_texture(device, size.width, size.height);
+        let depth = DepthBuffer::create(device, depth_format, size.width, size.height);
+        Self {
+            pipeline: pipeline(wgpu::PolygonMode::Fill),
+            wireframe: wireframe.then(|| pipeline(wgpu::PolygonMode::Line)),
+            texture,
+            view,
+            depth,
+        }
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.texture, self.view) = Self::create_texture(device, width, height);
+        self.depth = DepthBuffer::create(device, self.depth.format, width, height);
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("pick-texture"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
             format: Self::FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+}
+
+/// Bind group of one drawn object: its mesh, whether it is drawn by the
+/// wireframe pipeline, and its model matrix.
+type ObjectDraw = (Option<String>, wgpu::BindGroup, bool, Mat4);
+
+/// Draws built by [`Renderer::prepare_frame`] for
+/// [`Renderer::render_prepared`].
+pub struct PreparedFrame {
+    draws: Vec<ObjectDraw>,
+    /// Names of the drawn objects, kept only while picking is enabled.
+    pick_names: Vec<String>,
+}
+
+/// Color and depth attachments the scene is drawn into, both `width` x
+/// `height` pixels.
+struct SceneTarget<'a> {
+    color: &'a wgpu::TextureView,
+    depth: &'a wgpu::TextureView,
+    width: u32,
+    height: u32,
+}
+
+/// Scene, wireframe and debug pipelines for one color target format.
+struct ScenePipelines {
+    scene: wgpu::RenderPipeline,
+    /// Line-mode twin of `scene`, present when the device supports it.
+    wireframe: Option<wgpu::RenderPipeline>,
+    debug: wgpu::RenderPipeline,
+}
+
+/// Color and depth targets of [`Renderer::render_to_image`].
+struct OffscreenTarget {
+    pipelines: ScenePipelines,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    depth: DepthBuffer,
+}
+
+impl OffscreenTarget {
+    fn create(
+        device: &wgpu::Device,
+        pipelines: ScenePipelines,
+        depth_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let (texture, view) = Self::create_texture(device, width, height);
+        let depth = DepthBuffer::create(device, depth_format, width, height);
+        Self {
+            pipelines,
+            texture,
+            view,
+            depth,
+        }
+    }
+
+    /// Reallocates the textures if the requested size changed.
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let size = self.texture.size();
+        if (size.width, size.height) == (width, height) {
+            return;
+        }
+        (self.texture, self.view) = Self::create_texture(device, width, height);
+        self.depth = DepthBuffer::create(device, self.depth.format, width, height);
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen-texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+}
+
+/// Depth rendered from the primary light, sampled to find occluded fragments.
+struct ShadowMap {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    resolution: u32,
+}
+
+impl ShadowMap {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    fn create(device: &wgpu::Device, resolution: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow-map"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         Self {
             _texture: texture,
             view,
+            resolution,
         }
     }
 }
@@ -512,9 +1894,20 @@ impl DepthBuffer {
 #[derive(Clone, Copy, Pod, Zeroable)]
 struct GlobalUniform {
     view_proj: [[f32; 4]; 4],
+    light_view_proj: [[f32; 4]; 4],
     camera_position: [f32; 4],
     light_position: [f32; 4],
     light_color: [f32; 4],
+    light_direction: [f32; 4],
+    /// See [`LightParams::shape`].
+    light_shape: [f32; 4],
+    /// x: shadows enabled, y: depth bias, z: shadow-map texel size.
+    shadow_params: [f32; 4],
+    /// x: gamma applied to the final color.
+    output_params: [f32; 4],
+    /// See [`FogParams::uniforms`].
+    fog_color: [f32; 4],
+    fog_params: [f32; 4],
 }
 
 #[repr(C)]
@@ -523,37 +1916,92 @@ struct ObjectConstants {
     model: [[f32; 4]; 4],
     normal: [[f32; 4]; 3],
     color: [f32; 4],
+    /// `x` selects the lighting model, see [`shading_mode`]; `y` is the
+    /// object's pick ID, with 0 meaning no object.
+    shading: [u32; 4],
+    /// x: alpha threshold for [`Shading::Cutout`].
+    material: [f32; 4],
+    /// rgb: linear emitted color added to the shaded result.
+    emissive: [f32; 4],
+}
+
+impl ObjectConstants {
+    fn new(model: Mat4, object: &SceneObject) -> Self {
+        let normal = Mat3::from_mat4(model).inverse().transpose();
+        let threshold = match object.shading {
+            Shading::Cutout { threshold } => threshold,
+            _ => 0.0,
+        };
+        Self {
+            model: model.to_cols_array_2d(),
+            normal: mat3_to_3x4(normal),
+            color: object.color.extend(object.alpha).into(),
+            shading: [shading_mode(object.shading), 0, 0, 0],
+            material: [threshold, 0.0, 0.0, 0.0],
+            emissive: object.emissive.extend(0.0).into(),
+        }
+    }
+}
+
+/// Mode index branched on in `fs_main`.
+fn shading_mode(shading: Shading) -> u32 {
+    match shading {
+        Shading::Lit => 0,
+        Shading::Unlit => 1,
+        Shading::Flat => 2,
+        Shading::Checker => 3,
+        Shading::Cutout { .. } => 4,
+    }
 }
 
 const SHADER: &str = r#"
 struct GlobalUniform {
     view_proj: mat4x4<f32>,
+    light_view_proj: mat4x4<f32>,
     camera_position: vec4<f32>,
     light_position: vec4<f32>,
     light_color: vec4<f32>,
+    light_direction: vec4<f32>,
+    light_shape: vec4<f32>,
+    shadow_params: vec4<f32>,
+    output_params: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_params: vec4<f32>,
 }
 
 struct ObjectConstants {
     model: mat4x4<f32>,
     normal: mat3x4<f32>,
     color: vec4<f32>,
+    shading: vec4<u32>,
+    material: vec4<f32>,
+    emissive: vec4<f32>,
 }
 
 @group(0) @binding(0)
 var<uniform> globals: GlobalUniform;
 
+@group(0) @binding(1)
+var shadow_map: texture_depth_2d;
+
+@group(0) @binding(2)
+var shadow_sampler: sampler_comparison;
+
 @group(1) @binding(0)
 var<uniform> object: ObjectConstants;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
+    @location(2) color: vec3<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) world_pos: vec3<f32>,
     @location(1) normal: vec3<f32>,
+    @location(2) color: vec3<f32>,
+    @location(3) local_pos: vec3<f32>,
 }
 
 @vertex
@@ -570,33 +2018,212 @@ fn vs_main(input: VertexInput) -> VertexOutput {
     ) * input.normal;
 
     out.normal = normalize(world_normal);
+    out.color = input.color;
+    out.local_pos = input.position;
     return out;
 }
 
+// Fraction of the light reaching `world_pos`, using 3x3 PCF over the shadow map.
+fn shadow_visibility(world_pos: vec3<f32>) -> f32 {
+    if (globals.shadow_params.x < 0.5) {
+        return 1.0;
+    }
+    let clip = globals.light_view_proj * vec4<f32>(world_pos, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    let depth = ndc.z - globals.shadow_params.y;
+    let texel = globals.shadow_params.z;
+    var visibility = 0.0;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            visibility += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, depth);
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Applies manual gamma for surfaces that do not encode sRGB themselves.
+fn encode_output(color: vec4<f32>) -> vec4<f32> {
+    let gamma = globals.output_params.x;
+    return vec4<f32>(pow(max(color.rgb, vec3<f32>(0.0)), vec3<f32>(1.0 / gamma)), color.a);
+}
+
+// Blends toward the fog color by distance from the camera; fog_params.y is 0
+// while fog is off.
+fn apply_fog(color: vec3<f32>, world_pos: vec3<f32>) -> vec3<f32> {
+    let end = globals.fog_params.y;
+    if (end <= 0.0) {
+        return color;
+    }
+    let start = globals.fog_params.x;
+    let distance = length(world_pos - globals.camera_position.xyz);
+    var amount = 0.0;
+    if (globals.fog_params.z > 0.5) {
+        // exp(-3) leaves 5% of the color at the end distance.
+        let density = 3.0 / max(end - start, 0.0001);
+        amount = 1.0 - exp(-density * max(distance - start, 0.0));
+    } else {
+        amount = clamp((distance - start) / max(end - start, 0.0001), 0.0, 1.0);
+    }
+    return mix(color, globals.fog_color.rgb, amount);
+}
+
 @fragment
 fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
-    let light_dir = normalize(globals.light_position.xyz - input.world_pos);
-    let normal = normalize(input.normal);
-    let diffuse = max(dot(normal, light_dir), 0.0);
+    // Cutout drops fragments below the threshold and draws the rest opaque,
+    // so blending leaves them unchanged.
+    let cutout = object.shading.x == 4u;
+    if (cutout && object.color.a < object.material.x) {
+        discard;
+    }
+    if (object.shading.x == 1u) {
+        let unlit_color = object.color.rgb + object.emissive.rgb;
+        return encode_output(vec4<f32>(apply_fog(unlit_color, input.world_pos), object.color.a));
+    }
+    var light_dir = normalize(globals.light_position.xyz - input.world_pos);
+    let light_kind = globals.light_shape.x;
+    var cone = 1.0;
+    if (light_kind > 0.5 && light_kind < 1.5) {
+        light_dir = -normalize(globals.light_direction.xyz);
+    } else if (light_kind > 1.5) {
+        // Soften the spot edge over a small band inside the cutoff.
+        let cutoff = globals.light_shape.y;
+        let cos_angle = dot(-light_dir, normalize(globals.light_direction.xyz));
+        cone = smoothstep(cutoff, min(cutoff + 0.02, 1.0), cos_angle);
+    }
+    var normal = normalize(input.normal);
+    if (object.shading.x == 2u) {
+        // Framebuffer y points down, so this order faces the camera.
+        normal = normalize(cross(dpdy(input.world_pos), dpdx(input.world_pos)));
+    }
+    let diffuse = max(dot(normal, light_dir), 0.0) * cone * shadow_visibility(input.world_pos);
     let ambient = 0.15;
     let intensity = globals.light_color.w;
     let light_color = globals.light_color.xyz;
-    let lit_color = (ambient + diffuse * intensity) * object.color.rgb * light_color;
-    return vec4<f32>(lit_color, object.color.a);
+    var base_color = object.color.rgb * input.color;
+    if (object.shading.x == 3u) {
+        let cell = floor(input.local_pos * 4.0);
+        let odd = fract((cell.x + cell.y + cell.z) * 0.5) * 2.0;
+        base_color *= mix(1.0, 0.5, odd);
+    }
+    let lit_color = apply_fog(
+        (ambient + diffuse * intensity) * base_color * light_color + object.emissive.rgb,
+        input.world_pos,
+    );
+    return encode_output(vec4<f32>(lit_color, select(object.color.a, 1.0, cutout)));
+}
+
+@fragment
+fn fs_pick(input: VertexOutput) -> @location(0) u32 {
+    if (object.shading.x == 4u && object.color.a < object.material.x) {
+        discard;
+    }
+    return object.shading.y;
+}
+"#;
+
+const SHADOW_SHADER: &str = r#"
+struct GlobalUniform {
+    view_proj: mat4x4<f32>,
+    light_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light_position: vec4<f32>,
+    light_color: vec4<f32>,
+    light_direction: vec4<f32>,
+    light_shape: vec4<f32>,
+    shadow_params: vec4<f32>,
+    output_params: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_params: vec4<f32>,
+}
+
+struct ObjectConstants {
+    model: mat4x4<f32>,
+    normal: mat3x4<f32>,
+    color: vec4<f32>,
+    shading: vec4<u32>,
+    material: vec4<f32>,
+    emissive: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniform;
+
+@group(1) @binding(0)
+var<uniform> object: ObjectConstants;
+
+@vertex
+fn vs_shadow(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return globals.light_view_proj * object.model * vec4<f32>(position, 1.0);
+}
+"#;
+
+const DEBUG_SHADER: &str = r#"
+struct GlobalUniform {
+    view_proj: mat4x4<f32>,
+    light_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light_position: vec4<f32>,
+    light_color: vec4<f32>,
+    light_direction: vec4<f32>,
+    light_shape: vec4<f32>,
+    shadow_params: vec4<f32>,
+    output_params: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_params: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniform;
+
+struct DebugOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+}
+
+// Same as the scene shader's, so debug lines match the scene's output
+// encoding.
+fn encode_output(color: vec4<f32>) -> vec4<f32> {
+    let gamma = globals.output_params.x;
+    return vec4<f32>(pow(max(color.rgb, vec3<f32>(0.0)), vec3<f32>(1.0 / gamma)), color.a);
+}
+
+@vertex
+fn vs_debug(@location(0) position: vec3<f32>, @location(1) color: vec3<f32>) -> DebugOutput {
+    var out: DebugOutput;
+    out.position = globals.view_proj * vec4<f32>(position, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_debug(input: DebugOutput) -> @location(0) vec4<f32> {
+    return encode_output(vec4<f32>(input.color, 1.0));
 }
 "#;
 
 const DEFAULT_CUBE_VERTICES: &[f32] = &[
-    // positions        // normals
-    -0.5, -0.5, 0.5, 0.0, 0.0, 1.0, 0.5, -0.5, 0.5, 0.0, 0.0, 1.0, 0.5, 0.5, 0.5, 0.0, 0.0, 1.0,
-    -0.5, 0.5, 0.5, 0.0, 0.0, 1.0, -0.5, -0.5, -0.5, 0.0, 0.0, -1.0, 0.5, -0.5, -0.5, 0.0, 0.0,
-    -1.0, 0.5, 0.5, -0.5, 0.0, 0.0, -1.0, -0.5, 0.5, -0.5, 0.0, 0.0, -1.0, -0.5, -0.5, -0.5, -1.0,
-    0.0, 0.0, -0.5, -0.5, 0.5, -1.0, 0.0, 0.0, -0.5, 0.5, 0.5, -1.0, 0.0, 0.0, -0.5, 0.5, -0.5,
-    -1.0, 0.0, 0.0, 0.5, -0.5, -0.5, 1.0, 0.0, 0.0, 0.5, -0.5, 0.5, 1.0, 0.0, 0.0, 0.5, 0.5, 0.5,
-    1.0, 0.0, 0.0, 0.5, 0.5, -0.5, 1.0, 0.0, 0.0, -0.5, -0.5, -0.5, 0.0, -1.0, 0.0, 0.5, -0.5,
-    -0.5, 0.0, -1.0, 0.0, 0.5, -0.5, 0.5, 0.0, -1.0, 0.0, -0.5, -0.5, 0.5, 0.0, -1.0, 0.0, -0.5,
-    0.5, -0.5, 0.0, 1.0, 0.0, 0.5, 0.5, -0.5, 0.0, 1.0, 0.0, 0.5, 0.5, 0.5, 0.0, 1.0, 0.0, -0.5,
-    0.5, 0.5, 0.0, 1.0, 0.0,
+    // positions        // normals         // colors
+    -0.5, -0.5, 0.5, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.5, -0.5, 0.5, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0,
+    0.5, 0.5, 0.5, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, -0.5, 0.5, 0.5, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0,
+    -0.5, -0.5, -0.5, 0.0, 0.0, -1.0, 1.0, 1.0, 1.0, 0.5, -0.5, -0.5, 0.0, 0.0, -1.0, 1.0, 1.0,
+    1.0, 0.5, 0.5, -0.5, 0.0, 0.0, -1.0, 1.0, 1.0, 1.0, -0.5, 0.5, -0.5, 0.0, 0.0, -1.0, 1.0, 1.0,
+    1.0, -0.5, -0.5, -0.5, -1.0, 0.0, 0.0, 1.0, 1.0, 1.0, -0.5, -0.5, 0.5, -1.0, 0.0, 0.0, 1.0,
+    1.0, 1.0, -0.5, 0.5, 0.5, -1.0, 0.0, 0.0, 1.0, 1.0, 1.0, -0.5, 0.5, -0.5, -1.0, 0.0, 0.0, 1.0,
+    1.0, 1.0, 0.5, -0.5, -0.5, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.5, -0.5, 0.5, 1.0, 0.0, 0.0, 1.0,
+    1.0, 1.0, 0.5, 0.5, 0.5, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.5, 0.5, -0.5, 1.0, 0.0, 0.0, 1.0, 1.0,
+    1.0, -0.5, -0.5, -0.5, 0.0, -1.0, 0.0, 1.0, 1.0, 1.0, 0.5, -0.5, -0.5, 0.0, -1.0, 0.0, 1.0,
+    1.0, 1.0, 0.5, -0.5, 0.5, 0.0, -1.0, 0.0, 1.0, 1.0, 1.0, -0.5, -0.5, 0.5, 0.0, -1.0, 0.0, 1.0,
+    1.0, 1.0, -0.5, 0.5, -0.5, 0.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.5, 0.5, -0.5, 0.0, 1.0, 0.0, 1.0,
+    1.0, 1.0, 0.5, 0.5, 0.5, 0.0, 1.0, 0.0, 1.0, 1.0, 1.0, -0.5, 0.5, 0.5, 0.0, 1.0, 0.0, 1.0, 1.0,
+    1.0,
 ];
 
 const DEFAULT_CUBE_INDICES: &[u32] = &[
@@ -612,25 +2239,146 @@ const DEFAULT_CUBE_INDICES: &[u32] = &[
 mod tests {
     use super::*;
 
+    /// Opens a hidden window and a renderer for it, or `None` where the
+    /// machine has no display or GPU adapter.
+    #[cfg(target_os = "linux")]
+    fn hidden_renderer() -> Option<(winit::event_loop::EventLoop<()>, Renderer)> {
+        use winit::platform::x11::EventLoopBuilderExtX11;
+
+        let event_loop = winit::event_loop::EventLoop::builder()
+            .with_any_thread(true)
+            .build()
+            .ok()?;
+        #[allow(deprecated)]
+        let window = event_loop
+            .create_window(
+                Window::default_attributes()
+                    .with_visible(false)
+                    .with_inner_size(winit::dpi::PhysicalSize::new(64, 64)),
+            )
+            .ok()?;
+        let archive = CGameArchive::from_bytes(
+            "viewport-test",
+            crate::CGameArchiveBuilder::new("<scene />").to_bytes(),
+        )
+        .ok()?;
+        let renderer =
+            pollster::block_on(Renderer::new(Arc::new(window), Arc::new(archive))).ok()?;
+        Some((event_loop, renderer))
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn viewport_rect_limits_captured_pixels() {
+        let Some((_event_loop, mut renderer)) = hidden_renderer() else {
+            eprintln!("skipping: no display or GPU adapter");
+            return;
+        };
+        let (width, height) = (64, 32);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, 3.0), Vec3::ZERO, Vec3::Y);
+        let projection = Mat4::perspective_rh(60f32.to_radians(), 1.0, 0.1, 100.0);
+        renderer.update_globals(
+            &CameraParams::new(projection * view, Vec3::new(0.0, 0.0, 3.0)).with_view(view),
+            &LightParams::new([0.0, 5.0, 5.0], [1.0, 1.0, 1.0], 1.0),
+        );
+        let background = renderer
+            .render_to_image(&[], width, height)
+            .expect("empty capture");
+        let clear = background[..4].to_vec();
+
+        let cube = SceneObject::builder()
+            .name("Cube")
+            .object_type("part")
+            .scale(Vec3::splat(1.5))
+            .build();
+        renderer.set_viewport_rect(Some((0.0, 0.0, 32.0, 32.0)));
+        let pixels = renderer
+            .render_to_image(&[cube], width, height)
+            .expect("capture");
+
+        let pixel = |x: usize, y: usize| &pixels[(y * width as usize + x) * 4..][..4];
+        let inside = (0..32).flat_map(|y| (0..32).map(move |x| (x, y)));
+        assert!(inside.clone().any(|(x, y)| pixel(x, y) != clear.as_slice()));
+        let mut outside = (0..32).flat_map(|y| (32..64).map(move |x| (x, y)));
+        assert!(outside.all(|(x, y)| pixel(x, y) == clear.as_slice()));
+    }
+
+    #[test]
+    fn only_depth32_float_is_a_copy_source() {
+        for format in DepthBuffer::CANDIDATES {
+            let copyable = DepthBuffer::usage(format).contains(wgpu::TextureUsages::COPY_SRC);
+            assert_eq!(
+                copyable,
+                format == wgpu::TextureFormat::Depth32Float,
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cutout_threshold_reaches_object_constants() {
+        let object = SceneObject::builder()
+            .name("Fern")
+            .shading(Shading::Cutout { threshold: 0.3 })
+            .build();
+        let constants = ObjectConstants::new(Mat4::IDENTITY, &object);
+        assert_eq!(constants.shading[0], 4);
+        assert_eq!(constants.material[0], 0.3);
+
+        let lit = ObjectConstants::new(Mat4::IDENTITY, &SceneObject::default());
+        assert_eq!(lit.shading[0], 0);
+        assert_eq!(lit.material[0], 0.0);
+    }
+
+    #[test]
+    fn emissive_reaches_object_constants() {
+        let object = SceneObject::builder()
+            .name("Beacon")
+            .emissive(Vec3::new(1.0, 0.5, 0.0))
+            .build();
+        let constants = ObjectConstants::new(Mat4::IDENTITY, &object);
+        assert_eq!(constants.emissive, [1.0, 0.5, 0.0, 0.0]);
+
+        let plain = ObjectConstants::new(Mat4::IDENTITY, &SceneObject::default());
+        assert_eq!(plain.emissive, [0.0; 4]);
+    }
+
     #[test]
     fn renders_part_with_mesh() {
-        let mut object = SceneObject::default();
-        object.object_type = "part".to_string();
-        object.mesh = Some("models/cube.obj".to_string());
-        assert!(object_wants_mesh(&object));
+        let object = SceneObject {
+            object_type: "part".to_string(),
+            mesh: Some("models/cube.obj".to_string()),
+            ..SceneObject::default()
+        };
+        assert!(object_wants_mesh(&object, false));
     }
 
     #[test]
     fn renders_part_without_mesh() {
-        let mut object = SceneObject::default();
-        object.object_type = "part".to_string();
-        assert!(object_wants_mesh(&object));
+        let object = SceneObject {
+            object_type: "part".to_string(),
+            ..SceneObject::default()
+        };
+        assert!(object_wants_mesh(&object, false));
     }
 
     #[test]
     fn skips_camera() {
-        let mut object = SceneObject::default();
-        object.object_type = "camera".to_string();
-        assert!(!object_wants_mesh(&object));
+        let object = SceneObject {
+            object_type: "camera".to_string(),
+            ..SceneObject::default()
+        };
+        assert!(!object_wants_mesh(&object, false));
+        assert!(!object_wants_mesh(&object, true));
+    }
+
+    #[test]
+    fn draws_unknown_types_when_enabled() {
+        let object = SceneObject {
+            object_type: "enemy".to_string(),
+            ..SceneObject::default()
+        };
+        assert!(!object_wants_mesh(&object, false));
+        assert!(object_wants_mesh(&object, true));
     }
 }
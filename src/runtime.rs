@@ -0,0 +1,135 @@
+//! One-call setup shared by every entry point.
+//!
+//! [`Runtime`] opens the scene stored in an archive and builds the data model
+//! and input state that renderers and scripts share. The pieces it wires up
+//! stay public, so tools that need a different arrangement can still build
+//! them by hand.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    CGameArchive, DataModel, InputState, LuaScriptManager, Scene, SceneObject, ViewportProvider,
+};
+
+/// An archive together with its parsed scene, live data model and input state.
+///
+/// Cloning is cheap: the clone shares the archive, data model and input
+/// state with the original.
+#[derive(Clone)]
+pub struct Runtime {
+    archive: Arc<CGameArchive>,
+    scene: Scene,
+    data_model: DataModel,
+    input: Arc<InputState>,
+}
+
+impl Runtime {
+    /// Parses the archive's scene and builds a data model from its objects.
+    pub fn load(archive: impl Into<Arc<CGameArchive>>) -> Result<Self> {
+        let archive = archive.into();
+        let scene = Scene::from_xml(archive.scene_xml()).context("failed to parse scene XML")?;
+        let data_model = DataModel::from_objects(scene.objects.clone());
+        data_model.set_scene_scale(scene.scale);
+        data_model.set_up_axis(scene.up_axis);
+        Ok(Self {
+            archive,
+            scene,
+            data_model,
+            input: Arc::new(InputState::new()),
+        })
+    }
+
+    /// Opens the archive at `path` and loads it with [`Runtime::load`].
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let archive = CGameArchive::open(path)
+            .with_context(|| format!("failed to open archive {}", path.display()))?;
+        Self::load(archive)
+    }
+
+    pub fn archive(&self) -> &Arc<CGameArchive> {
+        &self.archive
+    }
+
+    /// The scene as parsed from the archive, before any script changes.
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn data_model(&self) -> &DataModel {
+        &self.data_model
+    }
+
+    pub fn input(&self) -> &Arc<InputState> {
+        &self.input
+    }
+
+    /// Snapshot of the objects currently in the data model.
+    pub fn objects(&self) -> Vec<SceneObject> {
+        self.data_model.all_objects()
+    }
+
+    /// Builds a script manager for the archive and the scene's inline
+    /// scripts without starting it.
+    pub fn script_manager(
+        &self,
+        viewport: Arc<dyn ViewportProvider + Send + Sync>,
+    ) -> LuaScriptManager {
+        let mut manager = LuaScriptManager::new(
+            Arc::clone(&self.archive),
+            self.data_model.clone(),
+            Arc::clone(&self.input),
+            viewport,
+        );
+        manager.set_inline_scripts(self.scene.scripts.clone());
+        manager
+    }
+
+    /// Starts the archive's scripts and returns the manager together with
+    /// the number of scripts launched.
+    pub fn start_scripts(
+        &self,
+        viewport: Arc<dyn ViewportProvider + Send + Sync>,
+    ) -> Result<(LuaScriptManager, usize)> {
+        let mut manager = self.script_manager(viewport);
+        let count = manager.start().context("failed to launch scripts")?;
+        Ok((manager, count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CGameArchiveBuilder, StaticViewport};
+
+    const SCENE_XML: &str = "<scene>\n  <object>\n    <name>Cube</name>\n    <type>mesh</type>\n  </object>\n  <script name=\"spin\">local x = 1</script>\n</scene>\n";
+
+    fn archive() -> CGameArchive {
+        CGameArchiveBuilder::new(SCENE_XML)
+            .build()
+            .expect("archive")
+    }
+
+    #[test]
+    fn load_builds_model_from_scene() {
+        let runtime = Runtime::load(archive()).expect("runtime");
+        let objects = runtime.objects();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].name, "Cube");
+        assert_eq!(runtime.scene().scripts.len(), 1);
+        assert!(runtime.data_model().get("Cube").is_some());
+    }
+
+    #[test]
+    fn start_scripts_launches_inline_scripts() {
+        let runtime = Runtime::load(archive()).expect("runtime");
+        let (mut manager, count) = runtime
+            .start_scripts(Arc::new(StaticViewport::new(64, 64)))
+            .expect("start");
+        assert_eq!(count, 1);
+        manager.wait().expect("wait");
+    }
+}
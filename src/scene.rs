@@ -1,19 +1,157 @@
+use std::collections::BTreeMap;
+
 use anyhow::{anyhow, Context, Result};
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 use roxmltree::{Document, Node};
 use serde::{Deserialize, Serialize};
 
+use crate::telemetry::load_span;
+pub use crate::types::{
+    world_matrices, world_matrix, AttributeValue, InlineScript, Light, LightType, SceneObject,
+    SceneObjectBuilder, Shading,
+};
+
 /// Runtime representation of a scene.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Scene {
     pub objects: Vec<SceneObject>,
+    /// Lights as they were when the scene was parsed. Scripts edit the
+    /// [`DataModel`](crate::DataModel), so use
+    /// [`DataModel::lights`](crate::DataModel::lights) for current values.
     pub lights: Vec<Light>,
+    #[serde(default)]
+    pub scripts: Vec<InlineScript>,
+    /// Meters per scene unit, applied to the whole scene when rendering.
+    /// Read from the root's `scene_scale` divided by its `units_per_meter`,
+    /// so a centimeter scene can give either `0.01` or `100`.
+    #[serde(default = "default_scene_scale")]
+    pub scale: f32,
+    /// How the scene's `<color>` values were authored, read from the
+    /// root's `color_space`. Stored object colors are always linear.
+    #[serde(default)]
+    pub color_space: ColorSpace,
+    /// World up axis the scene was authored with, read from the root's
+    /// `up_axis`. [`Scene::root_transform`] converts it to the renderer's +Y.
+    #[serde(default)]
+    pub up_axis: UpAxis,
+}
+
+/// Encoding of authored `<color>` values.
+///
+/// Colors used to be taken as linear, which made lit output too bright on
+/// sRGB surfaces for colors picked in authoring tools; sRGB is now the
+/// default and `linear` restores the old behavior.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    /// sRGB-encoded, as color pickers show them; converted to linear.
+    #[default]
+    Srgb,
+    /// Already linear; stored as given.
+    Linear,
+}
+
+impl ColorSpace {
+    /// Parses `srgb` or `linear`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "srgb" => Some(Self::Srgb),
+            "linear" => Some(Self::Linear),
+            _ => None,
+        }
+    }
+
+    /// Converts a `0..=1` channel value in this color space to linear.
+    pub fn to_linear(self, value: f32) -> f32 {
+        match self {
+            Self::Linear => value,
+            Self::Srgb if value <= 0.04045 => value / 12.92,
+            Self::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+        }
+    }
+
+    /// Converts a linear `0..=1` channel value into this color space,
+    /// undoing [`ColorSpace::to_linear`].
+    pub fn from_linear(self, value: f32) -> f32 {
+        match self {
+            Self::Linear => value,
+            Self::Srgb if value <= 0.0031308 => value * 12.92,
+            Self::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+        }
+    }
+}
+
+/// Axis that points up in authored content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpAxis {
+    /// +Y is up, matching the renderer; no conversion.
+    #[default]
+    Y,
+    /// +Z is up, as in most CAD and DCC tools exporting Z-up content.
+    Z,
+}
+
+impl UpAxis {
+    /// Parses `y` or `z`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "y" => Some(Self::Y),
+            "z" => Some(Self::Z),
+            _ => None,
+        }
+    }
+
+    /// Basis change from this axis convention to the renderer's Y-up one.
+    ///
+    /// Z-up content is turned -90° about X, taking `(x, y, z)` to
+    /// `(x, z, -y)` so its +Z lands on +Y and handedness is kept.
+    pub fn to_y_up(self) -> Mat4 {
+        match self {
+            Self::Y => Mat4::IDENTITY,
+            Self::Z => Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+        }
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            objects: Vec::new(),
+            lights: Vec::new(),
+            scripts: Vec::new(),
+            scale: default_scene_scale(),
+            color_space: ColorSpace::default(),
+            up_axis: UpAxis::default(),
+        }
+    }
+}
+
+fn default_scene_scale() -> f32 {
+    1.0
 }
 
 impl Scene {
     /// Parses the scene XML produced by the authoring tools.
     pub fn from_xml(xml: &str) -> Result<Self> {
+        let span = load_span("scene_parse", &format_args!("{} bytes", xml.len()));
         let document = Document::parse(xml).context("invalid scene XML")?;
+        let root = document.root_element();
+        let scale = parse_f32(optional_text(&root, "scene_scale"), 1.0)?
+            / parse_f32(optional_text(&root, "units_per_meter"), 1.0)?;
+        if !scale.is_finite() || scale <= 0.0 {
+            return Err(anyhow!("scene scale must be positive, got {scale}"));
+        }
+        let color_space = match optional_text(&root, "color_space") {
+            Some(name) => ColorSpace::from_name(&name)
+                .ok_or_else(|| anyhow!("unknown color space {name:?}, expected srgb or linear"))?,
+            None => ColorSpace::default(),
+        };
+        let up_axis = match optional_text(&root, "up_axis") {
+            Some(name) => UpAxis::from_name(&name)
+                .ok_or_else(|| anyhow!("unknown up axis {name:?}, expected y or z"))?,
+            None => UpAxis::default(),
+        };
         let mut objects = Vec::new();
 
         for node in document.descendants().filter(|n| n.has_tag_name("object")) {
@@ -21,102 +159,111 @@ impl Scene {
             object.name = required_text(&node, "name")?;
             object.object_type = optional_text(&node, "type").unwrap_or_else(|| "mesh".to_string());
             object.mesh = optional_text(&node, "mesh");
-            object.color = parse_color(optional_text(&node, "color"), object.color)?;
+            // An explicit <parent> wins; otherwise a nested <object> is
+            // parented to the nearest enclosing one.
+            object.parent = optional_text(&node, "parent").or_else(|| {
+                node.ancestors()
+                    .skip(1)
+                    .find(|ancestor| ancestor.has_tag_name("object"))
+                    .and_then(|ancestor| optional_text(&ancestor, "name"))
+            });
+            object.color = parse_color(optional_text(&node, "color"), object.color, color_space)?;
+            object.alpha = parse_alpha(optional_text(&node, "color"), object.alpha)?;
+            object.emissive = parse_color(
+                optional_text(&node, "emissive"),
+                object.emissive,
+                color_space,
+            )?;
             object.position = parse_vec3(optional_text(&node, "position"), object.position)?;
             object.rotation = parse_vec3(optional_text(&node, "rotation"), object.rotation)?;
             object.scale = parse_vec3(optional_text(&node, "scale"), object.scale)?;
+            object.mesh_scale = parse_vec3(optional_text(&node, "mesh_scale"), object.mesh_scale)?;
+            object.matrix = parse_matrix(optional_text(&node, "matrix"))?;
             object.fov = parse_f32(optional_text(&node, "fov"), object.fov)?;
+            object.near = parse_f32(optional_text(&node, "near"), object.near)?;
+            object.far = parse_f32(optional_text(&node, "far"), object.far)?;
             object.intensity = parse_f32(optional_text(&node, "intensity"), object.intensity)?;
+            object.light_type =
+                parse_light_type(optional_text(&node, "light_type"), object.light_type)?;
+            object.spot_angle = parse_f32(optional_text(&node, "spot_angle"), object.spot_angle)?;
+            object.shading = parse_shading(optional_text(&node, "shading"), object.shading)?;
+            object.wireframe = parse_bool(optional_text(&node, "wireframe"), object.wireframe)?;
+            object.billboard = parse_bool(optional_text(&node, "billboard"), object.billboard)?;
+            object.attributes = parse_attributes(&node)
+                .with_context(|| format!("invalid attributes on {:?}", object.name))?;
             objects.push(object);
         }
 
         let lights = objects
             .iter()
-            .filter(|obj| obj.object_type == "light")
-            .map(|obj| Light {
-                position: obj.position,
-                color: obj.color,
-                intensity: obj.intensity,
+            .zip(world_matrices(&objects))
+            .filter_map(|(object, world)| Light::from_object(object, world))
+            .collect();
+
+        let scripts = document
+            .descendants()
+            .filter(|n| n.has_tag_name("script"))
+            .enumerate()
+            .filter_map(|(index, node)| {
+                let source = element_text(&node);
+                let source = source.trim();
+                if source.is_empty() {
+                    return None;
+                }
+                let name = node
+                    .attribute("name")
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("inline{index}"));
+                Some(InlineScript {
+                    name,
+                    source: source.to_string(),
+                })
             })
             .collect();
 
-        Ok(Self { objects, lights })
+        span.finish(objects.len());
+        Ok(Self {
+            objects,
+            lights,
+            scripts,
+            scale,
+            color_space,
+            up_axis,
+        })
     }
-}
-
-/// Scene object as described by the authoring tools.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct SceneObject {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub object_type: String,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub mesh: Option<String>,
-    #[serde(default = "default_color")]
-    pub color: Vec3,
-    #[serde(default)]
-    pub position: Vec3,
-    #[serde(default)]
-    pub rotation: Vec3,
-    #[serde(default = "default_scale")]
-    pub scale: Vec3,
-    #[serde(default = "default_fov")]
-    pub fov: f32,
-    #[serde(default = "default_intensity")]
-    pub intensity: f32,
-}
 
-impl Default for SceneObject {
-    fn default() -> Self {
-        Self {
-            name: String::new(),
-            object_type: String::new(),
-            mesh: None,
-            color: default_color(),
-            position: Vec3::ZERO,
-            rotation: Vec3::ZERO,
-            scale: Vec3::ONE,
-            fov: default_fov(),
-            intensity: default_intensity(),
-        }
+    /// Transform applied on top of every object's world matrix to convert
+    /// scene units to meters and the scene's up axis to +Y.
+    pub fn root_transform(&self) -> Mat4 {
+        Mat4::from_scale(Vec3::splat(self.scale)) * self.up_axis.to_y_up()
     }
 }
 
-fn default_color() -> Vec3 {
-    Vec3::ONE
-}
-
-fn default_scale() -> Vec3 {
-    Vec3::ONE
-}
-
-fn default_fov() -> f32 {
-    45.0
-}
-
-fn default_intensity() -> f32 {
-    1.0
-}
-
-/// Light extracted from the scene object list.
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
-pub struct Light {
-    pub position: Vec3,
-    pub color: Vec3,
-    pub intensity: f32,
-}
-
 fn required_text(node: &Node<'_, '_>, tag: &str) -> Result<String> {
     optional_text(node, tag).ok_or_else(|| anyhow!("<{tag}> tag is missing"))
 }
 
+/// Reads a field from the `tag` attribute, falling back to a `<tag>` child.
 fn optional_text(node: &Node<'_, '_>, tag: &str) -> Option<String> {
-    node.children()
-        .find(|child| child.has_tag_name(tag))
-        .and_then(|child| child.text())
+    node.attribute(tag)
         .map(str::trim)
         .filter(|text| !text.is_empty())
-        .map(|text| text.to_string())
+        .map(str::to_string)
+        .or_else(|| {
+            node.children()
+                .find(|child| child.has_tag_name(tag))
+                .map(|child| element_text(&child).trim().to_string())
+        })
+        .filter(|text| !text.is_empty())
+}
+
+/// Every direct text and CDATA child of `node` joined together, so a value
+/// interrupted by a comment still reads whole.
+fn element_text(node: &Node<'_, '_>) -> String {
+    node.children()
+        .filter(Node::is_text)
+        .filter_map(|child| child.text())
+        .collect()
 }
 
 fn parse_vec3(value: Option<String>, default: Vec3) -> Result<Vec3> {
@@ -138,7 +285,25 @@ fn parse_vec3(value: Option<String>, default: Vec3) -> Result<Vec3> {
     Ok(Vec3::new(x, y, z))
 }
 
-fn parse_color(value: Option<String>, default: Vec3) -> Result<Vec3> {
+/// Parses 16 floats in column-major order, as exported by glTF tools.
+fn parse_matrix(value: Option<String>) -> Result<Option<Mat4>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let numbers = value
+        .split_whitespace()
+        .map(|component| component.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| anyhow!("failed to parse matrix: {err}"))?;
+    if numbers.len() != 16 {
+        return Err(anyhow!("matrix needs 16 numbers, found {}", numbers.len()));
+    }
+    Ok(Some(Mat4::from_cols_slice(&numbers)))
+}
+
+/// Reads 0-255 `r g b` components authored in `color_space` as a linear
+/// `0..=1` color. Alpha is never converted.
+fn parse_color(value: Option<String>, default: Vec3, color_space: ColorSpace) -> Result<Vec3> {
     let Some(value) = value else {
         return Ok(default);
     };
@@ -154,7 +319,87 @@ fn parse_color(value: Option<String>, default: Vec3) -> Result<Vec3> {
     let b = numbers
         .next()
         .ok_or_else(|| anyhow!("color is missing components"))?;
-    Ok(Vec3::new(r / 255.0, g / 255.0, b / 255.0))
+    let channel = |value: f32| color_space.to_linear(value / 255.0);
+    Ok(Vec3::new(channel(r), channel(g), channel(b)))
+}
+
+/// Reads the optional fourth (alpha) component of a `<color>` value.
+fn parse_alpha(value: Option<String>, default: f32) -> Result<f32> {
+    let Some(value) = value else {
+        return Ok(default);
+    };
+    match value.split_whitespace().nth(3) {
+        Some(alpha) => alpha
+            .parse::<f32>()
+            .map(|alpha| alpha / 255.0)
+            .map_err(|err| anyhow!("failed to parse color alpha: {err}")),
+        None => Ok(default),
+    }
+}
+
+fn parse_shading(value: Option<String>, default: Shading) -> Result<Shading> {
+    match value {
+        Some(value) => Shading::from_name(&value).ok_or_else(|| {
+            anyhow!("unknown shading mode {value:?}, expected lit, unlit, flat, checker or cutout")
+        }),
+        None => Ok(default),
+    }
+}
+
+fn parse_light_type(value: Option<String>, default: LightType) -> Result<LightType> {
+    match value {
+        Some(value) => LightType::from_name(&value).ok_or_else(|| {
+            anyhow!("unknown light type {value:?}, expected point, directional or spot")
+        }),
+        None => Ok(default),
+    }
+}
+
+/// Reads `<number>`, `<bool>` and `<string>` children of the object's own
+/// `<attributes>` block, each keyed by its `name` attribute.
+fn parse_attributes(node: &Node<'_, '_>) -> Result<BTreeMap<String, AttributeValue>> {
+    let mut attributes = BTreeMap::new();
+    let Some(block) = node
+        .children()
+        .find(|child| child.has_tag_name("attributes"))
+    else {
+        return Ok(attributes);
+    };
+    for entry in block.children().filter(Node::is_element) {
+        let tag = entry.tag_name().name();
+        let key = entry
+            .attribute("name")
+            .ok_or_else(|| anyhow!("<{tag}> attribute is missing its name"))?;
+        let text = Some(element_text(&entry).trim().to_string()).filter(|text| !text.is_empty());
+        let value = match tag {
+            "number" => AttributeValue::Number(
+                text.as_deref()
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|err| anyhow!("failed to parse attribute {key:?}: {err}"))?,
+            ),
+            "bool" => AttributeValue::Bool(
+                parse_bool(text, false).with_context(|| format!("attribute {key:?}"))?,
+            ),
+            "string" => AttributeValue::String(text.unwrap_or_default()),
+            other => {
+                return Err(anyhow!(
+                    "unknown attribute type <{other}>, expected number, bool or string"
+                ))
+            }
+        };
+        attributes.insert(key.to_string(), value);
+    }
+    Ok(attributes)
+}
+
+fn parse_bool(value: Option<String>, default: bool) -> Result<bool> {
+    match value.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        Some("true" | "1" | "yes") => Ok(true),
+        Some("false" | "0" | "no") => Ok(false),
+        Some(other) => Err(anyhow!("failed to parse boolean {other:?}")),
+        None => Ok(default),
+    }
 }
 
 fn parse_f32(value: Option<String>, default: f32) -> Result<f32> {
@@ -169,6 +414,7 @@ fn parse_f32(value: Option<String>, default: f32) -> Result<f32> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::default_fov;
 
     const SAMPLE: &str = r#"
     <scene>
@@ -198,7 +444,323 @@ mod tests {
         let light = scene.lights[0];
         assert_eq!(light.position, Vec3::new(0.0, 5.0, 0.0));
         assert!((light.intensity - 2.5).abs() < f32::EPSILON);
-        assert_eq!(light.color, Vec3::new(1.0, 128.0 / 255.0, 0.0));
+        assert_eq!(
+            light.color,
+            Vec3::new(1.0, ColorSpace::Srgb.to_linear(128.0 / 255.0), 0.0)
+        );
+    }
+
+    #[test]
+    fn attribute_form_matches_element_form() {
+        let attributes = r#"
+        <scene>
+            <object name="Camera" type="camera" fov="90" />
+            <object name="Light" type="light" intensity="2.5" position="0 5 0" color="255 128 0" />
+        </scene>
+        "#;
+        let from_attributes = Scene::from_xml(attributes).unwrap();
+        let from_elements = Scene::from_xml(SAMPLE).unwrap();
+        assert_eq!(from_attributes, from_elements);
+    }
+
+    #[test]
+    fn inline_scripts_are_collected() {
+        let xml = r#"
+        <scene>
+            <object name="Cube" />
+            <script name="spin">place.get('Cube').rotation = Vector3.new(0, 1, 0)</script>
+            <script><![CDATA[ print(1 < 2) ]]></script>
+            <script>   </script>
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        assert_eq!(scene.scripts.len(), 2);
+        assert_eq!(scene.scripts[0].name, "spin");
+        assert_eq!(scene.scripts[1].name, "inline1");
+        assert_eq!(scene.scripts[1].source, "print(1 < 2)");
+    }
+
+    #[test]
+    fn text_split_by_comments_is_joined() {
+        let xml = r#"
+        <scene>
+            <object>
+                <name>Door<!-- renamed from Gate -->Left</name>
+                <position>1 2 <!-- y was 3 --> 4</position>
+                <mesh><![CDATA[models/door.obj]]></mesh>
+            </object>
+            <script name="hello">print("a")<!-- todo -->print("b")</script>
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        let object = &scene.objects[0];
+        assert_eq!(object.name, "DoorLeft");
+        assert_eq!(object.position, Vec3::new(1.0, 2.0, 4.0));
+        assert_eq!(object.mesh.as_deref(), Some("models/door.obj"));
+        assert_eq!(scene.scripts[0].source, "print(\"a\")print(\"b\")");
+    }
+
+    #[test]
+    fn matrix_overrides_trs() {
+        let xml = r#"
+        <scene>
+            <object name="Baked" position="9 9 9" scale="5 5 5">
+                <matrix>2 0 0 0  0 2 0 0  0 0 2 0  1 2 3 1</matrix>
+            </object>
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        let object = &scene.objects[0];
+        let world = world_matrix(&scene.objects, object);
+        assert_eq!(world.transform_point3(Vec3::ZERO), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(world.transform_point3(Vec3::X), Vec3::new(3.0, 2.0, 3.0));
+
+        let short = r#"<scene><object name="Bad" matrix="1 0 0 0 1" /></scene>"#;
+        assert!(Scene::from_xml(short).is_err());
+    }
+
+    #[test]
+    fn scene_scale_shrinks_world_positions() {
+        let xml = r#"
+        <scene scene_scale="0.01">
+            <object name="Crate" position="100 200 -300" />
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        assert_eq!(scene.scale, 0.01);
+        let world = scene.root_transform() * world_matrix(&scene.objects, &scene.objects[0]);
+        assert!((world.w_axis.truncate() - Vec3::new(1.0, 2.0, -3.0)).length() < 1e-5);
+
+        let per_meter =
+            Scene::from_xml("<scene><units_per_meter>100</units_per_meter></scene>").unwrap();
+        assert_eq!(per_meter.scale, 0.01);
+        assert_eq!(Scene::from_xml("<scene />").unwrap().scale, 1.0);
+        assert!(Scene::from_xml(r#"<scene scene_scale="0" />"#).is_err());
+    }
+
+    #[test]
+    fn z_up_scene_is_turned_to_y_up() {
+        let xml = r#"
+        <scene up_axis="z">
+            <object name="Crate" position="1 2 3" />
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        assert_eq!(scene.up_axis, UpAxis::Z);
+        let world = scene.root_transform() * world_matrix(&scene.objects, &scene.objects[0]);
+        assert!((world.w_axis.truncate() - Vec3::new(1.0, 3.0, -2.0)).length() < 1e-5);
+
+        assert_eq!(Scene::from_xml("<scene />").unwrap().up_axis, UpAxis::Y);
+        assert!(Scene::from_xml(r#"<scene up_axis="x" />"#).is_err());
+    }
+
+    #[test]
+    fn nested_objects_inherit_parent() {
+        let xml = r#"
+        <scene>
+            <object name="Car">
+                <object name="Wheel">
+                    <position>1 0 0</position>
+                    <object name="Hubcap" />
+                </object>
+                <object name="Antenna" parent="Roof" />
+            </object>
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        let parents: Vec<(&str, Option<&str>)> = scene
+            .objects
+            .iter()
+            .map(|object| (object.name.as_str(), object.parent.as_deref()))
+            .collect();
+        assert_eq!(
+            parents,
+            vec![
+                ("Car", None),
+                ("Wheel", Some("Car")),
+                ("Hubcap", Some("Wheel")),
+                ("Antenna", Some("Roof")),
+            ]
+        );
+        assert_eq!(scene.objects[1].position, Vec3::X);
+    }
+
+    #[test]
+    fn shading_modes_are_parsed() {
+        let xml = r#"
+        <scene>
+            <object name="Marker" shading="Unlit" />
+            <object name="Rock"><shading>flat</shading></object>
+            <object name="Grid" shading="checker" />
+            <object name="Fern" shading="Cutout:0.25" />
+            <object name="Cube" />
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        let shading: Vec<Shading> = scene.objects.iter().map(|o| o.shading).collect();
+        assert_eq!(
+            shading,
+            vec![
+                Shading::Unlit,
+                Shading::Flat,
+                Shading::Checker,
+                Shading::Cutout { threshold: 0.25 },
+                Shading::Lit
+            ]
+        );
+
+        let invalid = r#"<scene><object name="Cube" shading="glossy" /></scene>"#;
+        assert!(Scene::from_xml(invalid).is_err());
+    }
+
+    #[test]
+    fn builder_applies_defaults_and_overrides() {
+        let object = SceneObject::builder()
+            .name("Lamp")
+            .object_type("light")
+            .position(Vec3::new(1.0, 2.0, 3.0))
+            .intensity(4.0)
+            .build();
+        assert_eq!(object.name, "Lamp");
+        assert_eq!(object.object_type, "light");
+        assert_eq!(object.position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(object.intensity, 4.0);
+        assert_eq!(object.scale, Vec3::ONE);
+        assert_eq!(object.fov, default_fov());
+        assert!(object.mesh.is_none());
+    }
+
+    #[test]
+    fn world_matrix_composes_parent_chain() {
+        let root = SceneObject::builder()
+            .name("Root")
+            .position(Vec3::new(10.0, 0.0, 0.0))
+            .build();
+        let arm = SceneObject::builder()
+            .name("Arm")
+            .parent("Root")
+            .position(Vec3::new(0.0, 2.0, 0.0))
+            .scale(Vec3::splat(2.0))
+            .build();
+        let hand = SceneObject::builder()
+            .name("Hand")
+            .parent("Arm")
+            .position(Vec3::new(1.0, 0.0, 0.0))
+            .build();
+        let objects = vec![root, arm, hand.clone()];
+        let world = world_matrix(&objects, &hand).transform_point3(Vec3::ZERO);
+        assert_eq!(world, Vec3::new(12.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn world_matrices_match_world_matrix() {
+        // Children listed before their parents, plus an orphan and a cycle.
+        let objects = vec![
+            SceneObject::builder()
+                .name("Hand")
+                .parent("Arm")
+                .position(Vec3::new(1.0, 0.0, 0.0))
+                .build(),
+            SceneObject::builder()
+                .name("Arm")
+                .parent("Root")
+                .position(Vec3::new(0.0, 2.0, 0.0))
+                .scale(Vec3::splat(2.0))
+                .build(),
+            SceneObject::builder()
+                .name("Root")
+                .position(Vec3::new(10.0, 0.0, 0.0))
+                .build(),
+            SceneObject::builder()
+                .name("Orphan")
+                .parent("Missing")
+                .position(Vec3::new(0.0, 0.0, 3.0))
+                .build(),
+            SceneObject::builder().name("Loop").parent("Loop").build(),
+        ];
+        let matrices = world_matrices(&objects);
+        assert_eq!(matrices.len(), objects.len());
+        for (object, matrix) in objects.iter().zip(&matrices).take(4) {
+            assert_eq!(*matrix, world_matrix(&objects, object), "{}", object.name);
+        }
+        assert_eq!(matrices[4], Mat4::IDENTITY);
+    }
+
+    #[test]
+    fn color_accepts_optional_alpha() {
+        let xml = r#"
+        <scene>
+            <object name="Glass" color="255 255 255 51" />
+            <object name="Solid" color="10 20 30" />
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        assert!((scene.objects[0].alpha - 0.2).abs() < 1e-6);
+        assert_eq!(scene.objects[0].color, Vec3::ONE);
+        assert_eq!(scene.objects[1].alpha, 1.0);
+    }
+
+    #[test]
+    fn light_type_and_spot_angle_parse() {
+        let xml = r#"
+        <scene>
+            <object name="Sun" type="light" light_type="directional" rotation="-90 0 0" />
+            <object name="Torch" type="light" light_type="spot" spot_angle="15" />
+            <object name="Bulb" type="light" />
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        let sun = scene.lights[0];
+        assert_eq!(sun.light_type, LightType::Directional);
+        assert!((sun.direction - Vec3::NEG_Y).length() < 1e-5);
+        assert_eq!(scene.lights[1].light_type, LightType::Spot);
+        assert_eq!(scene.lights[1].spot_angle, 15.0);
+        assert_eq!(scene.lights[2].light_type, LightType::Point);
+
+        let bad = r#"<scene><object name="L" light_type="area" /></scene>"#;
+        assert!(Scene::from_xml(bad).is_err());
+    }
+
+    #[test]
+    fn attributes_block_is_parsed() {
+        let xml = r#"
+        <scene>
+            <object name="Enemy">
+                <attributes>
+                    <number name="health">100</number>
+                    <bool name="boss">yes</bool>
+                    <string name="state">patrol</string>
+                </attributes>
+                <object name="Sword" />
+            </object>
+        </scene>
+        "#;
+        let scene = Scene::from_xml(xml).unwrap();
+        let attributes = &scene.objects[0].attributes;
+        assert_eq!(attributes["health"], AttributeValue::Number(100.0));
+        assert_eq!(attributes["boss"], AttributeValue::Bool(true));
+        assert_eq!(attributes["state"], AttributeValue::String("patrol".into()));
+        assert!(scene.objects[1].attributes.is_empty());
+
+        let bad = r#"<scene><object name="A"><attributes><list name="x" /></attributes></object></scene>"#;
+        assert!(Scene::from_xml(bad).is_err());
+    }
+
+    #[test]
+    fn srgb_colors_are_linearized() {
+        let gray = r#"<scene><object name="Gray" color="128 128 128" /></scene>"#;
+        let scene = Scene::from_xml(gray).unwrap();
+        assert_eq!(scene.color_space, ColorSpace::Srgb);
+        let linear = scene.objects[0].color;
+        assert!((linear.x - 0.2158605).abs() < 1e-5, "{linear}");
+        assert_eq!(linear, Vec3::splat(linear.x));
+
+        let raw =
+            r#"<scene color_space="linear"><object name="Gray" color="128 128 128" /></scene>"#;
+        let scene = Scene::from_xml(raw).unwrap();
+        assert_eq!(scene.objects[0].color, Vec3::splat(128.0 / 255.0));
+
+        assert!(Scene::from_xml(r#"<scene color_space="cmyk" />"#).is_err());
     }
 
     #[test]
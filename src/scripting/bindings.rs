@@ -1,23 +1,39 @@
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use glam::{Vec2, Vec3};
+use log::warn;
 use mlua::{
-    FromLua, IntoLua, Lua, MultiValue, Result as LuaResult, Table, UserData, UserDataFields,
+    FromLua, Lua, MetaMethod, MultiValue, Result as LuaResult, Table, UserData, UserDataFields,
     UserDataMethods, Value, Variadic,
 };
 
+use crate::archive::CGameArchive;
 use crate::data_model::DataModel;
 use crate::input::InputState;
+use crate::obj::load_obj_from_str;
+use crate::render::DebugDraw;
+use crate::scene::{AttributeValue, ColorSpace, Shading};
 
-use super::native::ViewportProvider;
+use super::native::{ScriptLogHandler, ViewportProvider};
+use super::HostEvent;
 
 pub(super) struct ScriptContext {
     pub data_model: DataModel,
     pub input_state: Arc<InputState>,
     pub viewport: Arc<dyn ViewportProvider + Send + Sync>,
     pub running: Arc<AtomicBool>,
+    pub log_handler: ScriptLogHandler,
+    pub mesh_bounds: Arc<MeshBoundsCache>,
+    /// Queue behind `debug.line` and `debug.point`; without one they do
+    /// nothing.
+    pub debug_draw: Option<DebugDraw>,
+    /// Events pushed by `host.emit`, drained by the host.
+    pub host_events: Arc<parking_lot::Mutex<Vec<HostEvent>>>,
+    /// Build states with only the sandboxed standard libraries.
+    pub sandboxed: bool,
 }
 
 impl ScriptContext {
@@ -26,14 +42,99 @@ impl ScriptContext {
         input_state: Arc<InputState>,
         viewport: Arc<dyn ViewportProvider + Send + Sync>,
         running: Arc<AtomicBool>,
+        log_handler: ScriptLogHandler,
     ) -> Self {
         Self {
             data_model,
             input_state,
             viewport,
             running,
+            log_handler,
+            mesh_bounds: Arc::default(),
+            debug_draw: None,
+            host_events: Arc::default(),
+            sandboxed: false,
         }
     }
+
+    /// Lets `object:MeshBounds()` read meshes from `archive`.
+    pub fn with_archive(mut self, archive: Arc<CGameArchive>) -> Self {
+        self.mesh_bounds = Arc::new(MeshBoundsCache::new(Some(archive)));
+        self
+    }
+
+    /// Sends `debug.line` and `debug.point` calls to `debug_draw`.
+    pub fn with_debug_draw(mut self, debug_draw: Option<DebugDraw>) -> Self {
+        self.debug_draw = debug_draw;
+        self
+    }
+
+    /// Restricts the standard libraries, see
+    /// [`LuaScriptManager::set_sandboxed`](super::LuaScriptManager::set_sandboxed).
+    pub fn with_sandbox(mut self, sandboxed: bool) -> Self {
+        self.sandboxed = sandboxed;
+        self
+    }
+
+    /// Queues `host.emit` calls on `host_events`.
+    pub fn with_host_events(
+        mut self,
+        host_events: Arc<parking_lot::Mutex<Vec<HostEvent>>>,
+    ) -> Self {
+        self.host_events = host_events;
+        self
+    }
+}
+
+/// Bounds of the unit cube drawn for objects without a usable mesh.
+const UNIT_CUBE_BOUNDS: (Vec3, Vec3) = (Vec3::splat(-0.5), Vec3::splat(0.5));
+
+/// Mesh-space bounds of archive meshes, parsed once and shared by every
+/// script of a launch.
+#[derive(Default)]
+pub(super) struct MeshBoundsCache {
+    archive: Option<Arc<CGameArchive>>,
+    bounds: parking_lot::Mutex<HashMap<String, (Vec3, Vec3)>>,
+}
+
+impl MeshBoundsCache {
+    fn new(archive: Option<Arc<CGameArchive>>) -> Self {
+        Self {
+            archive,
+            bounds: Default::default(),
+        }
+    }
+
+    /// Bounds of the named mesh; meshes that are missing, unreadable or
+    /// empty report the unit cube the renderer draws in their place.
+    fn get(&self, mesh: Option<&str>) -> (Vec3, Vec3) {
+        let Some(name) = mesh else {
+            return UNIT_CUBE_BOUNDS;
+        };
+        if let Some(bounds) = self.bounds.lock().get(name) {
+            return *bounds;
+        }
+        let bounds = match self.load(name) {
+            Ok(Some(bounds)) => bounds,
+            Ok(None) => UNIT_CUBE_BOUNDS,
+            Err(err) => {
+                warn!("unable to read bounds of mesh {name}: {err:?}");
+                UNIT_CUBE_BOUNDS
+            }
+        };
+        self.bounds.lock().insert(name.to_string(), bounds);
+        bounds
+    }
+
+    fn load(&self, name: &str) -> anyhow::Result<Option<(Vec3, Vec3)>> {
+        let archive = self
+            .archive
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("no archive is attached to the scripts"))?;
+        let bytes = archive.extract_file(name)?;
+        let source = String::from_utf8(bytes)?;
+        Ok(load_obj_from_str(&source)?.bounds())
+    }
 }
 
 impl Clone for ScriptContext {
@@ -43,6 +144,11 @@ impl Clone for ScriptContext {
             input_state: Arc::clone(&self.input_state),
             viewport: Arc::clone(&self.viewport),
             running: Arc::clone(&self.running),
+            log_handler: Arc::clone(&self.log_handler),
+            mesh_bounds: Arc::clone(&self.mesh_bounds),
+            debug_draw: self.debug_draw.clone(),
+            host_events: Arc::clone(&self.host_events),
+            sandboxed: self.sandboxed,
         }
     }
 }
@@ -50,15 +156,25 @@ impl Clone for ScriptContext {
 pub(super) fn register_globals(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
     println!("Registering Globals");
     register_print(lua)?;
+    register_diagnostics(lua, context)?;
     register_wait(lua, Arc::clone(&context.running))?;
     register_datatypes(lua)?;
     register_scene(lua, context)?;
     register_service(lua, context)?;
     register_screen(lua, context)?;
+    register_debug_draw(lua, context)?;
+    register_host(lua, context)?;
     Ok(())
 }
 
 fn register_datatypes(lua: &Lua) -> LuaResult<()> {
+    let vector2 = lua.create_table()?;
+    vector2.set(
+        "new",
+        lua.create_function(|_, (x, y): (f32, f32)| Ok(LuaVector2::new(Vec2::new(x, y))))?,
+    )?;
+    lua.globals().set("Vector2", vector2)?;
+
     let vector3 = lua.create_table()?;
     vector3.set(
         "new",
@@ -82,33 +198,72 @@ fn register_print(lua: &Lua) -> LuaResult<()> {
     println!("Registering print with a script");
     let print = lua.create_function(|lua, values: Variadic<Value>| {
         println!("Print Called");
-        let mut out = Vec::new();
-        for value in values.iter() {
-            let text = match value {
-                Value::Nil => "nil".to_string(),
-                Value::Boolean(b) => b.to_string(),
-                Value::String(s) => s.to_str()?.to_string(),
-                _ => match lua.coerce_string(value.clone())? {
-                    Some(s) => s.to_str()?.to_string(),
-                    None => format!("{:?}", value),
-                },
-            };
-            out.push(text);
-        }
-        println!("[Lua] {}", out.join("\t"));
+        println!("[Lua] {}", join_values(lua, &values)?);
         Ok(())
     })?;
     lua.globals().set("print", print)?;
     Ok(())
 }
 
+/// Binds `warn(...)` and `log_error(...)`, which hand a leveled message and
+/// the calling chunk's name to the host log handler. Unlike `error`, neither
+/// stops the script.
+fn register_diagnostics(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
+    for (name, level) in [("warn", log::Level::Warn), ("log_error", log::Level::Error)] {
+        let handler = Arc::clone(&context.log_handler);
+        let function = lua.create_function(move |lua, values: Variadic<Value>| {
+            let message = join_values(lua, &values)?;
+            handler(level, &calling_chunk(lua), &message);
+            Ok(())
+        })?;
+        lua.globals().set(name, function)?;
+    }
+    Ok(())
+}
+
+/// Formats values the way `print` does, separated by tabs.
+fn join_values(lua: &Lua, values: &[Value]) -> LuaResult<String> {
+    let mut out = Vec::new();
+    for value in values {
+        let text = match value {
+            Value::Nil => "nil".to_string(),
+            Value::Boolean(b) => b.to_string(),
+            Value::String(s) => s.to_str()?.to_string(),
+            _ => match lua.coerce_string(value.clone())? {
+                Some(s) => s.to_str()?.to_string(),
+                None => format!("{:?}", value),
+            },
+        };
+        out.push(text);
+    }
+    Ok(out.join("\t"))
+}
+
+/// Name of the chunk whose code called the current Rust function.
+fn calling_chunk(lua: &Lua) -> String {
+    lua.inspect_stack(1)
+        .and_then(|debug| debug.source().short_src.map(|src| src.into_owned()))
+        .unwrap_or_else(|| "?".to_string())
+}
+
+/// Registers the blocking `wait(millis)`, which returns the seconds it
+/// actually slept and the seconds since the state was set up, like
+/// Roblox's `wait`.
 fn register_wait(lua: &Lua, running: Arc<AtomicBool>) -> LuaResult<()> {
     let wait_running = Arc::clone(&running);
+    let started = Instant::now();
     let wait = lua.create_function(move |_, millis: Option<u64>| {
+        let called = Instant::now();
+        let elapsed = || {
+            (
+                called.elapsed().as_secs_f64(),
+                started.elapsed().as_secs_f64(),
+            )
+        };
         let mut remaining = millis.unwrap_or(0);
         if remaining == 0 {
             std::thread::yield_now();
-            return Ok(());
+            return Ok(elapsed());
         }
         const CHUNK: u64 = 10;
         while remaining > 0 {
@@ -119,7 +274,7 @@ fn register_wait(lua: &Lua, running: Arc<AtomicBool>) -> LuaResult<()> {
             std::thread::sleep(Duration::from_millis(sleep));
             remaining -= sleep;
         }
-        Ok(())
+        Ok(elapsed())
     })?;
     lua.globals().set("wait", wait)?;
     Ok(())
@@ -137,7 +292,7 @@ fn register_scene(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
         if key.is_empty() || get_context.data_model.get(&key).is_none() {
             return Ok(Value::Nil);
         }
-        let object = PlaceObject::new(get_context.data_model.clone(), key);
+        let object = PlaceObject::new(&get_context, key);
         let userdata = lua.create_userdata(object)?;
         Ok(Value::UserData(userdata))
     })?;
@@ -167,10 +322,13 @@ fn register_scene(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
         if key.is_empty() || get_fn_context.data_model.get(&key).is_none() {
             return Ok(Value::Nil);
         }
-        let object = PlaceObject::new(get_fn_context.data_model.clone(), key);
+        let object = PlaceObject::new(&get_fn_context, key);
         let userdata = lua.create_userdata(object)?;
         Ok(Value::UserData(userdata))
     })?;
+    // `get` skips non-string arguments, so it also serves the Roblox-style
+    // `place:FindFirstChild(name)` method call.
+    table.set("FindFirstChild", get_fn.clone())?;
     table.set("get", get_fn)?;
 
     // Keep your existing `names` function
@@ -190,6 +348,44 @@ fn register_scene(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
     })?;
     table.set("names", names)?;
 
+    let children_context = context.clone();
+    let get_children = lua.create_function(move |lua, _args: MultiValue| {
+        let children = children_context
+            .data_model
+            .object_names()
+            .into_iter()
+            .map(|name| PlaceObject::new(&children_context, name));
+        lua.create_sequence_from(children)
+    })?;
+    table.set("GetChildren", get_children)?;
+
+    // `scene.at(i)` is 1-based, like the sequence `GetChildren` returns.
+    let at_context = context.clone();
+    let at = lua.create_function(move |_, index: i64| {
+        let object = index
+            .checked_sub(1)
+            .and_then(|index| usize::try_from(index).ok())
+            .and_then(|index| at_context.data_model.object_at(index));
+        Ok(object.map(|object| PlaceObject::new(&at_context, object.name)))
+    })?;
+    table.set("at", at)?;
+
+    let radius_context = context.clone();
+    let within_radius = lua.create_function(move |lua, (center, radius): (LuaVector3, f32)| {
+        let names = radius_context
+            .data_model
+            .within_radius(center.as_vec3(), radius);
+        lua.create_sequence_from(names)
+    })?;
+    table.set("within_radius", within_radius)?;
+
+    let camera_context = context.clone();
+    let set_active_camera = lua.create_function(move |_, name: Option<String>| {
+        camera_context.data_model.set_active_camera(name.as_deref());
+        Ok(())
+    })?;
+    table.set("set_active_camera", set_active_camera)?;
+
     globals.set("scene", table.clone())?;
     globals.set("place", table)?;
     Ok(())
@@ -211,12 +407,29 @@ fn register_service(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
     input_table.set("GetKeyDown", get_key_down)?;
 
     let input_state = Arc::clone(&context.input_state);
-    let get_mouse_position = lua.create_function(move |lua, _args: MultiValue| {
-        let pos = input_state.mouse_position();
-        LuaVec2(pos).into_lua(lua)
+    let get_keys_down =
+        lua.create_function(move |_, _args: MultiValue| Ok(input_state.keys_down()))?;
+    input_table.set("GetKeysDown", get_keys_down)?;
+
+    let input_state = Arc::clone(&context.input_state);
+    let get_mouse_position = lua.create_function(move |_, _args: MultiValue| {
+        Ok(LuaVector2::new(input_state.mouse_position()))
     })?;
     input_table.set("GetMousePosition", get_mouse_position)?;
 
+    let input_state = Arc::clone(&context.input_state);
+    let get_mouse_delta = lua.create_function(move |_, _args: MultiValue| {
+        Ok(LuaVector2::new(input_state.mouse_delta()))
+    })?;
+    input_table.set("GetMouseDelta", get_mouse_delta)?;
+
+    let input_state = Arc::clone(&context.input_state);
+    let set_cursor_locked = lua.create_function(move |_, locked: bool| {
+        input_state.request_cursor_lock(locked);
+        Ok(())
+    })?;
+    input_table.set("SetCursorLocked", set_cursor_locked)?;
+
     service.set("input", input_table)?;
     globals.set("service", service)?;
     Ok(())
@@ -226,15 +439,77 @@ fn register_screen(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
     let globals = lua.globals();
     let screen = lua.create_table()?;
     let viewport = Arc::clone(&context.viewport);
-    let get_viewport_size = lua.create_function(move |lua, _args: MultiValue| {
+    let get_viewport_size = lua.create_function(move |_, _args: MultiValue| {
         let (width, height) = viewport.viewport_size();
-        LuaVec2(Vec2::new(width as f32, height as f32)).into_lua(lua)
+        Ok(LuaVector2::new(Vec2::new(width as f32, height as f32)))
     })?;
     screen.set("GetViewportSize", get_viewport_size)?;
     globals.set("screen", screen)?;
     Ok(())
 }
 
+/// Adds `line(a, b, color)` and `point(position, color)` to the `debug`
+/// library. Colors default to white, and both calls are no-ops when the host
+/// has no renderer attached.
+fn register_debug_draw(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
+    let globals = lua.globals();
+    let debug = match globals.get::<_, Option<Table>>("debug")? {
+        Some(table) => table,
+        None => lua.create_table()?,
+    };
+    let queue = context.debug_draw.clone();
+    debug.set(
+        "line",
+        lua.create_function(
+            move |_, (start, end, color): (LuaVector3, LuaVector3, Option<LuaColor3>)| {
+                if let Some(queue) = &queue {
+                    queue.line(start.as_vec3(), end.as_vec3(), debug_color(color));
+                }
+                Ok(())
+            },
+        )?,
+    )?;
+    let queue = context.debug_draw.clone();
+    debug.set(
+        "point",
+        lua.create_function(
+            move |_, (position, color): (LuaVector3, Option<LuaColor3>)| {
+                if let Some(queue) = &queue {
+                    queue.point(position.as_vec3(), debug_color(color));
+                }
+                Ok(())
+            },
+        )?,
+    )?;
+    globals.set("debug", debug)?;
+    Ok(())
+}
+
+/// Adds the `host` library, whose `emit(name, payload)` queues a
+/// [`HostEvent`] with the payload encoded as JSON.
+fn register_host(lua: &Lua, context: &ScriptContext) -> LuaResult<()> {
+    let host = lua.create_table()?;
+    let events = Arc::clone(&context.host_events);
+    host.set(
+        "emit",
+        lua.create_function(move |_, (name, payload): (String, Value)| {
+            let payload = serde_json::to_string(&payload).map_err(|err| {
+                mlua::Error::RuntimeError(format!(
+                    "host.emit payload for {name:?} cannot be encoded as JSON: {err}"
+                ))
+            })?;
+            events.lock().push(HostEvent { name, payload });
+            Ok(())
+        })?,
+    )?;
+    lua.globals().set("host", host)?;
+    Ok(())
+}
+
+fn debug_color(color: Option<LuaColor3>) -> Vec3 {
+    color.map_or(Vec3::ONE, LuaColor3::as_vec3)
+}
+
 fn string_argument(values: &MultiValue) -> LuaResult<Option<String>> {
     for value in values.iter() {
         if let Value::String(s) = value {
@@ -246,12 +521,23 @@ fn string_argument(values: &MultiValue) -> LuaResult<Option<String>> {
 
 struct PlaceObject {
     data_model: DataModel,
+    mesh_bounds: Arc<MeshBoundsCache>,
     name: String,
 }
 
 impl PlaceObject {
-    fn new(data_model: DataModel, name: String) -> Self {
-        Self { data_model, name }
+    fn new(context: &ScriptContext, name: String) -> Self {
+        Self {
+            data_model: context.data_model.clone(),
+            mesh_bounds: Arc::clone(&context.mesh_bounds),
+            name,
+        }
+    }
+
+    /// Mesh-space bounds of the object's mesh, or `None` if the object is gone.
+    fn mesh_bounds(&self) -> Option<(Vec3, Vec3)> {
+        let object = self.data_model.get(&self.name)?;
+        Some(self.mesh_bounds.get(object.mesh.as_deref()))
     }
 }
 
@@ -294,6 +580,15 @@ impl UserData for PlaceObject {
                 Ok(Value::Nil)
             }
         });
+        fields.add_field_method_get("emissive", |lua, this| {
+            if let Some(object) = this.data_model.get(&this.name) {
+                Ok(Value::UserData(lua.create_userdata(
+                    LuaColor3::from_normalized(object.emissive),
+                )?))
+            } else {
+                Ok(Value::Nil)
+            }
+        });
         fields.add_field_method_get("fov", |_, this| {
             Ok(this.data_model.get(&this.name).map(|object| object.fov))
         });
@@ -303,6 +598,18 @@ impl UserData for PlaceObject {
                 .get(&this.name)
                 .map(|object| object.intensity))
         });
+        fields.add_field_method_get("shading", |_, this| {
+            Ok(this
+                .data_model
+                .get(&this.name)
+                .map(|object| object.shading.name()))
+        });
+        fields.add_field_method_get("wireframe", |_, this| {
+            Ok(this
+                .data_model
+                .get(&this.name)
+                .map(|object| object.wireframe))
+        });
 
         fields.add_field_method_set("position", |_, this, value: LuaVector3| {
             this.data_model.set_position(&this.name, value.as_vec3());
@@ -320,6 +627,10 @@ impl UserData for PlaceObject {
             this.data_model.set_color(&this.name, value.as_vec3());
             Ok(())
         });
+        fields.add_field_method_set("emissive", |_, this, value: LuaColor3| {
+            this.data_model.set_emissive(&this.name, value.as_vec3());
+            Ok(())
+        });
         fields.add_field_method_set("fov", |_, this, value: f32| {
             this.data_model.set_fov(&this.name, value);
             Ok(())
@@ -328,9 +639,69 @@ impl UserData for PlaceObject {
             this.data_model.set_intensity(&this.name, value);
             Ok(())
         });
+        fields.add_field_method_set("shading", |_, this, value: String| {
+            let shading = Shading::from_name(&value).ok_or_else(|| {
+                mlua::Error::RuntimeError(format!(
+                    "unknown shading mode {value:?}, expected lit, unlit, flat, checker or cutout"
+                ))
+            })?;
+            this.data_model.set_shading(&this.name, shading);
+            Ok(())
+        });
+        fields.add_field_method_set("wireframe", |_, this, value: bool| {
+            this.data_model.set_wireframe(&this.name, value);
+            Ok(())
+        });
     }
 
-    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(_methods: &mut M) {}
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("WorldPosition", |_, this, ()| {
+            Ok(this
+                .data_model
+                .world_matrix(&this.name)
+                .map(|matrix| LuaVector3::new(matrix.w_axis.truncate())))
+        });
+        methods.add_method("MeshBounds", |_, this, ()| {
+            let bounds = this.mesh_bounds();
+            Ok((
+                bounds.map(|(min, _)| LuaVector3::new(min)),
+                bounds.map(|(_, max)| LuaVector3::new(max)),
+            ))
+        });
+        methods.add_method("MeshSize", |_, this, ()| {
+            Ok(this
+                .mesh_bounds()
+                .map(|(min, max)| LuaVector3::new(max - min)))
+        });
+        methods.add_method("SetAttribute", |_, this, (key, value): (String, Value)| {
+            let value = match value {
+                Value::Nil => {
+                    this.data_model.remove_attribute(&this.name, &key);
+                    return Ok(());
+                }
+                Value::Integer(number) => AttributeValue::Number(number as f64),
+                Value::Number(number) => AttributeValue::Number(number),
+                Value::Boolean(flag) => AttributeValue::Bool(flag),
+                Value::String(text) => AttributeValue::String(text.to_str()?.to_string()),
+                other => {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "attribute {key:?} cannot hold a {}, expected number, boolean or string",
+                        other.type_name()
+                    )))
+                }
+            };
+            this.data_model.set_attribute(&this.name, &key, value);
+            Ok(())
+        });
+        methods.add_method("GetAttribute", |lua, this, key: String| {
+            Ok(match this.data_model.get_attribute(&this.name, &key) {
+                Some(AttributeValue::Number(number)) => Value::Number(number),
+                Some(AttributeValue::Bool(flag)) => Value::Boolean(flag),
+                Some(AttributeValue::String(text)) => Value::String(lua.create_string(&text)?),
+                None => Value::Nil,
+            })
+        });
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -371,12 +742,20 @@ impl<'lua> FromLua<'lua> for LuaVector3 {
     }
 }
 
+/// Linear color; scripts read and write 0-255 sRGB channels, matching
+/// `<color>` values in scene XML.
 #[derive(Debug, Clone, Copy)]
 struct LuaColor3(Vec3);
 
 impl LuaColor3 {
     fn from_rgb(r: f32, g: f32, b: f32) -> Self {
-        Self(Vec3::new(r, g, b) / 255.0)
+        let channel = |value: f32| ColorSpace::Srgb.to_linear(value / 255.0);
+        Self(Vec3::new(channel(r), channel(g), channel(b)))
+    }
+
+    /// A linear channel as the 0-255 sRGB value scripts see.
+    fn channel(value: f32) -> f32 {
+        ColorSpace::Srgb.from_linear(value) * 255.0
     }
 
     fn from_normalized(color: Vec3) -> Self {
@@ -390,12 +769,12 @@ impl LuaColor3 {
 
 impl UserData for LuaColor3 {
     fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
-        fields.add_field_method_get("R", |_, this| Ok(this.0.x * 255.0));
-        fields.add_field_method_get("G", |_, this| Ok(this.0.y * 255.0));
-        fields.add_field_method_get("B", |_, this| Ok(this.0.z * 255.0));
-        fields.add_field_method_get("r", |_, this| Ok(this.0.x * 255.0));
-        fields.add_field_method_get("g", |_, this| Ok(this.0.y * 255.0));
-        fields.add_field_method_get("b", |_, this| Ok(this.0.z * 255.0));
+        fields.add_field_method_get("R", |_, this| Ok(Self::channel(this.0.x)));
+        fields.add_field_method_get("G", |_, this| Ok(Self::channel(this.0.y)));
+        fields.add_field_method_get("B", |_, this| Ok(Self::channel(this.0.z)));
+        fields.add_field_method_get("r", |_, this| Ok(Self::channel(this.0.x)));
+        fields.add_field_method_get("g", |_, this| Ok(Self::channel(this.0.y)));
+        fields.add_field_method_get("b", |_, this| Ok(Self::channel(this.0.z)));
     }
 }
 
@@ -426,30 +805,86 @@ impl<'lua> FromLua<'lua> for LuaColor3 {
 }
 
 #[derive(Debug, Clone, Copy)]
-struct LuaVec2(Vec2);
+struct LuaVector2(Vec2);
+
+impl LuaVector2 {
+    fn new(inner: Vec2) -> Self {
+        Self(inner)
+    }
+}
+
+impl UserData for LuaVector2 {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("X", |_, this| Ok(this.0.x));
+        fields.add_field_method_get("Y", |_, this| Ok(this.0.y));
+        fields.add_field_method_get("x", |_, this| Ok(this.0.x));
+        fields.add_field_method_get("y", |_, this| Ok(this.0.y));
+    }
 
-impl<'lua> IntoLua<'lua> for LuaVec2 {
-    fn into_lua(self, lua: &'lua Lua) -> LuaResult<Value<'lua>> {
-        let table = lua.create_table()?;
-        table.set("x", self.0.x)?;
-        table.set("y", self.0.y)?;
-        Ok(Value::Table(table))
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("Magnitude", |_, this, ()| Ok(this.0.length()));
+        methods.add_method("Normalize", |_, this, ()| {
+            Ok(LuaVector2::new(this.0.normalize_or_zero()))
+        });
+        methods.add_meta_function(
+            MetaMethod::Add,
+            |_, (lhs, rhs): (LuaVector2, LuaVector2)| Ok(LuaVector2::new(lhs.0 + rhs.0)),
+        );
+        methods.add_meta_function(
+            MetaMethod::Sub,
+            |_, (lhs, rhs): (LuaVector2, LuaVector2)| Ok(LuaVector2::new(lhs.0 - rhs.0)),
+        );
+        methods.add_meta_function(MetaMethod::Mul, |lua, (lhs, rhs): (Value, Value)| {
+            let lhs = Vector2Operand::from_value(lhs, lua)?;
+            let rhs = Vector2Operand::from_value(rhs, lua)?;
+            Ok(LuaVector2::new(lhs.as_vec2() * rhs.as_vec2()))
+        });
+        methods.add_meta_function(MetaMethod::Unm, |_, this: LuaVector2| {
+            Ok(LuaVector2::new(-this.0))
+        });
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!("{}, {}", this.0.x, this.0.y))
+        });
     }
 }
 
-impl<'lua> FromLua<'lua> for LuaVec2 {
+impl<'lua> FromLua<'lua> for LuaVector2 {
     fn from_lua(value: Value<'lua>, _: &'lua Lua) -> LuaResult<Self> {
         match value {
             Value::Table(table) => Ok(Self(table_to_vec2(&table)?)),
+            Value::UserData(ud) => ud.borrow::<LuaVector2>().map(|vec| *vec),
             _ => Err(mlua::Error::FromLuaConversionError {
                 from: value.type_name(),
                 to: "Vector2",
-                message: Some("expected table".into()),
+                message: Some("expected Vector2 userdata or table".into()),
             }),
         }
     }
 }
 
+/// Either side of a `Vector2` multiplication: a vector or a scalar.
+enum Vector2Operand {
+    Vector(Vec2),
+    Scalar(f32),
+}
+
+impl Vector2Operand {
+    fn from_value<'lua>(value: Value<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        match value {
+            Value::Integer(value) => Ok(Self::Scalar(value as f32)),
+            Value::Number(value) => Ok(Self::Scalar(value as f32)),
+            other => LuaVector2::from_lua(other, lua).map(|vec| Self::Vector(vec.0)),
+        }
+    }
+
+    fn as_vec2(&self) -> Vec2 {
+        match self {
+            Self::Vector(vec) => *vec,
+            Self::Scalar(value) => Vec2::splat(*value),
+        }
+    }
+}
+
 fn table_to_vec3(table: &Table) -> LuaResult<Vec3> {
     Ok(Vec3::new(
         table_component(table, "x", 1)?,
@@ -478,11 +913,52 @@ mod tests {
     use super::*;
     use crate::data_model::DataModel;
     use crate::input::{InputState, KeyCode, MouseButton, NamedKey};
-    use crate::scene::SceneObject;
+    use crate::scene::{Scene, SceneObject};
     use glam::{Vec2, Vec3};
+    use parking_lot::Mutex;
     use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
 
+    fn discard_log() -> ScriptLogHandler {
+        Arc::new(|_, _, _| {})
+    }
+
+    #[test]
+    fn warn_reaches_log_handler_without_stopping_script() {
+        let lua = Lua::new();
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&messages);
+        let handler: ScriptLogHandler = Arc::new(move |level, chunk, message| {
+            sink.lock()
+                .push((level, chunk.to_string(), message.to_string()));
+        });
+        let context = ScriptContext::new(
+            DataModel::new(),
+            Arc::new(InputState::new()),
+            viewport,
+            Arc::new(AtomicBool::new(true)),
+            handler,
+        );
+        register_globals(&lua, &context).unwrap();
+
+        let finished: bool = lua
+            .load("warn('low health', 3) log_error('bad state') return true")
+            .set_name("scripts/hud.lua")
+            .eval()
+            .unwrap();
+
+        assert!(finished);
+        let messages = messages.lock();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].0, log::Level::Warn);
+        assert!(messages[0].1.contains("scripts/hud.lua"));
+        assert_eq!(messages[0].2, "low health\t3");
+        assert_eq!(messages[1].0, log::Level::Error);
+        assert_eq!(messages[1].2, "bad state");
+    }
+
     #[test]
     fn place_object_getters_and_setters_update_data_model() {
         let lua = Lua::new();
@@ -495,7 +971,7 @@ mod tests {
         let viewport: Arc<dyn ViewportProvider + Send + Sync> =
             Arc::new(StaticViewport::new(640, 480));
         let running = Arc::new(AtomicBool::new(true));
-        let context = ScriptContext::new(model.clone(), input, viewport, running);
+        let context = ScriptContext::new(model.clone(), input, viewport, running, discard_log());
         register_globals(&lua, &context).unwrap();
 
         let (pos_x, color_y, names_len): (f32, f32, i64) = lua
@@ -515,12 +991,172 @@ mod tests {
             .unwrap();
 
         assert!((pos_x - 1.0).abs() < f32::EPSILON);
-        assert!((color_y - 64.0).abs() < f32::EPSILON);
+        assert!((color_y - 64.0).abs() < 1e-3);
         assert_eq!(names_len, 1);
 
         let updated = model.get("Cube").unwrap();
         assert_eq!(updated.position, Vec3::new(1.0, 2.0, 3.0));
-        assert_eq!(updated.color, Vec3::new(128.0 / 255.0, 64.0 / 255.0, 0.0));
+        let srgb = ColorSpace::Srgb;
+        assert_eq!(
+            updated.color,
+            Vec3::new(
+                srgb.to_linear(128.0 / 255.0),
+                srgb.to_linear(64.0 / 255.0),
+                0.0
+            )
+        );
+    }
+
+    #[test]
+    fn color3_matches_scene_xml_colors() {
+        let lua = Lua::new();
+        let scene = Scene::from_xml(
+            "<scene><object><name>Cube</name><color>255 128 0</color></object></scene>",
+        )
+        .unwrap();
+        let authored = scene.objects[0].color;
+        let model = DataModel::from_objects(scene.objects);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(640, 480));
+        let context = ScriptContext::new(
+            model.clone(),
+            Arc::new(InputState::new()),
+            viewport,
+            Arc::new(AtomicBool::new(true)),
+            discard_log(),
+        );
+        register_globals(&lua, &context).unwrap();
+
+        let (r, g, b): (f32, f32, f32) = lua
+            .load(
+                r#"
+                local cube = place.get("Cube")
+                local color = cube.color
+                local r, g, b = color.R, color.G, color.B
+                cube.color = Color3.new(r, g, b)
+                return r, g, b
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert!((r - 255.0).abs() < 1e-3);
+        assert!((g - 128.0).abs() < 1e-3);
+        assert_eq!(b, 0.0);
+        let color = model.get("Cube").unwrap().color;
+        assert!(color.abs_diff_eq(authored, 1e-6));
+    }
+
+    #[test]
+    fn attributes_round_trip_through_lua() {
+        let lua = Lua::new();
+        let model = DataModel::from_objects(vec![SceneObject {
+            name: "Enemy".into(),
+            ..SceneObject::default()
+        }]);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(640, 480));
+        let context = ScriptContext::new(
+            model.clone(),
+            Arc::new(InputState::new()),
+            viewport,
+            Arc::new(AtomicBool::new(true)),
+            discard_log(),
+        );
+        register_globals(&lua, &context).unwrap();
+
+        let (health, missing): (f64, Value) = lua
+            .load(
+                r#"
+                local enemy = place.get("Enemy")
+                enemy:SetAttribute("health", 75)
+                enemy:SetAttribute("state", "idle")
+                enemy:SetAttribute("state", nil)
+                return enemy:GetAttribute("health"), enemy:GetAttribute("state")
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(health, 75.0);
+        assert!(missing.is_nil());
+        assert_eq!(
+            model.get_attribute("Enemy", "health"),
+            Some(AttributeValue::Number(75.0))
+        );
+        assert!(lua
+            .load(r#"place.get("Enemy"):SetAttribute("bad", {})"#)
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn scripted_light_changes_show_in_model_lights() {
+        let lua = Lua::new();
+        let light = SceneObject {
+            name: "Sun".into(),
+            object_type: "light".into(),
+            ..SceneObject::default()
+        };
+        let model = DataModel::from_objects(vec![light]);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(640, 480));
+        let context = ScriptContext::new(
+            model.clone(),
+            Arc::new(InputState::new()),
+            viewport,
+            Arc::new(AtomicBool::new(true)),
+            discard_log(),
+        );
+        register_globals(&lua, &context).unwrap();
+
+        lua.load(
+            r#"
+            local sun = place.get("Sun")
+            sun.intensity = 4.0
+            sun.color = Color3.new(255, 0, 0)
+        "#,
+        )
+        .exec()
+        .unwrap();
+
+        let lights = model.lights();
+        assert_eq!(lights.len(), 1);
+        assert_eq!(lights[0].intensity, 4.0);
+        assert_eq!(lights[0].color, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn debug_library_queues_lines_and_points() {
+        let lua = Lua::new();
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(640, 480));
+        let debug_draw = DebugDraw::new();
+        let context = ScriptContext::new(
+            DataModel::new(),
+            Arc::new(InputState::new()),
+            viewport,
+            Arc::new(AtomicBool::new(true)),
+            discard_log(),
+        )
+        .with_debug_draw(Some(debug_draw.clone()));
+        register_globals(&lua, &context).unwrap();
+
+        let has_traceback: bool = lua
+            .load(
+                r#"
+                debug.line(Vector3.new(0, 0, 0), Vector3.new(0, 5, 0), Color3.new(255, 0, 0))
+                debug.point(Vector3.new(1, 1, 1))
+                return debug.traceback ~= nil
+            "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert!(has_traceback);
+        let lines = debug_draw.take_lines();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].end, Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(lines[0].color, Vec3::X);
+        assert_eq!(lines[1].color, Vec3::ONE);
     }
 
     #[test]
@@ -534,7 +1170,8 @@ mod tests {
         let viewport: Arc<dyn ViewportProvider + Send + Sync> =
             Arc::new(StaticViewport::new(1920, 1080));
         let running = Arc::new(AtomicBool::new(true));
-        let context = ScriptContext::new(model, Arc::clone(&input), viewport, running);
+        let context =
+            ScriptContext::new(model, Arc::clone(&input), viewport, running, discard_log());
         register_globals(&lua, &context).unwrap();
 
         let (space_down, mouse_down, mouse_x, mouse_y, width, height, unknown): (
@@ -569,6 +1206,316 @@ mod tests {
         assert!(!unknown);
     }
 
+    #[test]
+    fn get_keys_down_lists_every_held_key() {
+        let lua = Lua::new();
+        let input = Arc::new(InputState::new());
+        input.set_key_down(KeyCode::Character('W'));
+        input.set_key_down(KeyCode::Named(NamedKey::LeftShift));
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(DataModel::new(), input, viewport, running, discard_log());
+        register_globals(&lua, &context).unwrap();
+
+        let keys: Vec<String> = lua
+            .load("return service.input.GetKeysDown()")
+            .eval()
+            .unwrap();
+        assert_eq!(keys, vec!["LeftShift".to_string(), "W".to_string()]);
+    }
+
+    #[test]
+    fn world_position_resolves_parent_offset() {
+        let parent = SceneObject::builder()
+            .name("Base")
+            .position(Vec3::new(0.0, 10.0, 0.0))
+            .build();
+        let child = SceneObject::builder()
+            .name("Turret")
+            .parent("Base")
+            .position(Vec3::new(1.0, 0.0, 0.0))
+            .build();
+        let model = DataModel::from_objects(vec![parent, child]);
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(model, input, viewport, running, discard_log());
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        let (local_y, world_x, world_y): (f32, f32, f32) = lua
+            .load(
+                r#"
+                local turret = place.get("Turret")
+                local world = turret:WorldPosition()
+                return turret.position.Y, world.X, world.Y
+            "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(local_y, 0.0);
+        assert_eq!(world_x, 1.0);
+        assert_eq!(world_y, 10.0);
+    }
+
+    #[test]
+    fn mesh_bounds_default_to_unit_cube() {
+        let model = DataModel::from_objects(vec![SceneObject::builder().name("Crate").build()]);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(
+            model,
+            Arc::new(InputState::new()),
+            viewport,
+            running,
+            discard_log(),
+        );
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        let (min, max, size): (LuaVector3, LuaVector3, LuaVector3) = lua
+            .load(
+                r#"
+                local crate = place.get("Crate")
+                local min, max = crate:MeshBounds()
+                return min, max, crate:MeshSize()
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(min.as_vec3(), Vec3::splat(-0.5));
+        assert_eq!(max.as_vec3(), Vec3::splat(0.5));
+        assert_eq!(size.as_vec3(), Vec3::ONE);
+    }
+
+    #[test]
+    fn find_first_child_and_get_children_mirror_get() {
+        let model = DataModel::from_objects(vec![
+            SceneObject::builder().name("Cube").build(),
+            SceneObject::builder().name("Lamp").build(),
+        ]);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(
+            model,
+            Arc::new(InputState::new()),
+            viewport,
+            running,
+            discard_log(),
+        );
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        let (found, missing, children): (String, bool, Vec<String>) = lua
+            .load(
+                r#"
+                local names = {}
+                for _, child in ipairs(place:GetChildren()) do
+                    table.insert(names, child.name)
+                end
+                return place:FindFirstChild("Cube").name, place:FindFirstChild("Ghost") == nil, names
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(found, "Cube");
+        assert!(missing);
+        assert_eq!(children, vec!["Cube", "Lamp"]);
+    }
+
+    #[test]
+    fn shading_is_readable_and_writable() {
+        let model = DataModel::from_objects(vec![SceneObject::builder().name("Marker").build()]);
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(model.clone(), input, viewport, running, discard_log());
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        let before: String = lua
+            .load(
+                r#"
+                local marker = place.get("Marker")
+                local before = marker.shading
+                marker.shading = "Unlit"
+                return before
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(before, "lit");
+        assert_eq!(model.get("Marker").unwrap().shading, Shading::Unlit);
+        assert!(lua
+            .load(r#"place.get("Marker").shading = "glossy""#)
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn cutout_threshold_survives_reading_and_writing_shading() {
+        let model = DataModel::from_objects(vec![SceneObject::builder()
+            .name("Fern")
+            .shading(Shading::Cutout { threshold: 0.3 })
+            .build()]);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let context = ScriptContext::new(
+            model.clone(),
+            Arc::new(InputState::new()),
+            viewport,
+            Arc::new(AtomicBool::new(true)),
+            discard_log(),
+        );
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        let name: String = lua
+            .load(
+                r#"
+                local fern = place.get("Fern")
+                fern.shading = fern.shading
+                return fern.shading
+            "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(name, "cutout:0.3");
+        assert_eq!(
+            model.get("Fern").unwrap().shading,
+            Shading::Cutout { threshold: 0.3 }
+        );
+        let default_cutout = Shading::Cutout {
+            threshold: crate::types::DEFAULT_CUTOUT_THRESHOLD,
+        };
+        assert_eq!(default_cutout.name(), "cutout");
+    }
+
+    #[test]
+    fn within_radius_returns_nearby_names() {
+        let near = SceneObject::builder()
+            .name("Coin")
+            .position(Vec3::new(1.0, 0.0, 0.0))
+            .build();
+        let far = SceneObject::builder()
+            .name("Tree")
+            .position(Vec3::new(0.0, 0.0, 50.0))
+            .build();
+        let model = DataModel::from_objects(vec![near, far]);
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(model, input, viewport, running, discard_log());
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        let (count, first): (usize, String) = lua
+            .load(
+                r#"
+                local names = scene.within_radius(Vector3.new(0, 0, 0), 5)
+                return #names, names[1]
+            "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(first, "Coin");
+    }
+
+    #[test]
+    fn scene_at_is_one_based_and_rejects_other_indices() {
+        let model = DataModel::from_objects(vec![
+            SceneObject::builder().name("Coin").build(),
+            SceneObject::builder().name("Tree").build(),
+        ]);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let context = ScriptContext::new(
+            model,
+            Arc::new(InputState::new()),
+            viewport,
+            Arc::new(AtomicBool::new(true)),
+            discard_log(),
+        );
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        let (first, second): (String, String) = lua
+            .load("return scene.at(1).name, scene.at(2).name")
+            .eval()
+            .unwrap();
+        assert_eq!((first.as_str(), second.as_str()), ("Coin", "Tree"));
+
+        for index in ["0", "-1", "3", "-2^63", "2^62"] {
+            let missing: bool = lua
+                .load(format!("return scene.at({index}) == nil"))
+                .eval()
+                .unwrap();
+            assert!(missing, "scene.at({index}) should be nil");
+        }
+    }
+
+    #[test]
+    fn set_active_camera_updates_model() {
+        let model = DataModel::new();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(model.clone(), input, viewport, running, discard_log());
+        let lua = Lua::new();
+        register_globals(&lua, &context).unwrap();
+
+        lua.load(r#"scene.set_active_camera("Cutscene")"#)
+            .exec()
+            .unwrap();
+        assert_eq!(model.active_camera().as_deref(), Some("Cutscene"));
+        lua.load("scene.set_active_camera(nil)").exec().unwrap();
+        assert_eq!(model.active_camera(), None);
+    }
+
+    #[test]
+    fn vector2_supports_arithmetic_and_methods() {
+        let lua = Lua::new();
+        let model = DataModel::new();
+        let input = Arc::new(InputState::new());
+        input.set_mouse_position(Vec2::new(3.0, 4.0));
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(800, 600));
+        let running = Arc::new(AtomicBool::new(true));
+        let context = ScriptContext::new(model, input, viewport, running, discard_log());
+        register_globals(&lua, &context).unwrap();
+
+        let (sum_x, diff_y, scaled_x, magnitude, unit_x, center_y): (f32, f32, f32, f32, f32, f32) =
+            lua.load(
+                r#"
+                local a = Vector2.new(1, 2)
+                local b = Vector2.new(3, 5)
+                local mouse = service.input.GetMousePosition()
+                local center = screen.GetViewportSize() * 0.5
+                return (a + b).X, (b - a).Y, (2 * a).x, mouse:Magnitude(),
+                       Vector2.new(10, 0):Normalize().X, center.Y
+            "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(sum_x, 4.0);
+        assert_eq!(diff_y, 3.0);
+        assert_eq!(scaled_x, 2.0);
+        assert_eq!(magnitude, 5.0);
+        assert_eq!(unit_x, 1.0);
+        assert_eq!(center_y, 300.0);
+    }
+
     #[test]
     fn wait_function_reports_stop_request() {
         let lua = Lua::new();
@@ -577,7 +1524,8 @@ mod tests {
         let viewport: Arc<dyn ViewportProvider + Send + Sync> =
             Arc::new(StaticViewport::new(800, 600));
         let running = Arc::new(AtomicBool::new(false));
-        let context = ScriptContext::new(model, input, viewport, Arc::clone(&running));
+        let context =
+            ScriptContext::new(model, input, viewport, Arc::clone(&running), discard_log());
         register_globals(&lua, &context).unwrap();
 
         let (ok, message): (bool, String) = lua
@@ -0,0 +1,81 @@
+//! Launch order of archive scripts.
+//!
+//! Scripts under the script prefix launch in archive order unless the
+//! prefix also holds `manifest.json`: a JSON array of script names, relative
+//! to the prefix or in full, giving the order instead. Scripts the manifest
+//! leaves out launch after the listed ones, in archive order.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+
+use crate::archive::{ArchiveFileEntry, CGameArchive};
+
+/// Entry under the script prefix holding the launch order.
+pub(super) const MANIFEST_NAME: &str = "manifest.json";
+
+/// Archive scripts in the order they launch.
+pub(super) struct ArchiveScripts {
+    pub entries: Vec<ArchiveFileEntry>,
+    /// Whether a manifest fixed the order, which callers must then keep.
+    /// The Web build runs no scripts, so only the order matters there.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub ordered: bool,
+}
+
+/// Collects the scripts under `prefix`, ordered by its manifest if any.
+pub(super) fn archive_scripts(archive: &CGameArchive, prefix: &str) -> Result<ArchiveScripts> {
+    let manifest_name = format!("{prefix}{MANIFEST_NAME}");
+    let mut entries: Vec<ArchiveFileEntry> = archive
+        .files()
+        .iter()
+        .filter(|entry| entry.name.starts_with(prefix) && entry.name != manifest_name)
+        .cloned()
+        .collect();
+    let Some(manifest) = archive.file(&manifest_name) else {
+        return Ok(ArchiveScripts {
+            entries,
+            ordered: false,
+        });
+    };
+
+    let bytes = archive
+        .extract_entry(manifest)
+        .with_context(|| format!("failed to extract {manifest_name}"))?;
+    let names: Vec<String> = serde_json::from_slice(&bytes)
+        .with_context(|| format!("{manifest_name} must be a JSON array of script names"))?;
+    let mut ordered = Vec::with_capacity(entries.len());
+    let mut seen = HashSet::new();
+    for name in names {
+        let full_name = if name.starts_with(prefix) {
+            name
+        } else {
+            format!("{prefix}{name}")
+        };
+        let index = entries
+            .iter()
+            .position(|entry| entry.name == full_name)
+            .ok_or_else(|| {
+                if seen.contains(&full_name) {
+                    anyhow!("{manifest_name} lists {full_name} more than once")
+                } else {
+                    anyhow!("{manifest_name} lists {full_name}, which is not in the archive")
+                }
+            })?;
+        seen.insert(full_name);
+        ordered.push(entries.remove(index));
+    }
+    if !entries.is_empty() {
+        let unlisted: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        warn!(
+            "{manifest_name} does not list {}; launching them last",
+            unlisted.join(", ")
+        );
+    }
+    ordered.extend(entries);
+    Ok(ArchiveScripts {
+        entries: ordered,
+        ordered: true,
+    })
+}
@@ -1,11 +1,22 @@
 #[cfg(not(target_arch = "wasm32"))]
 mod bindings;
+mod manifest;
 #[cfg(not(target_arch = "wasm32"))]
 mod native;
+#[cfg(not(target_arch = "wasm32"))]
+mod scheduler;
 #[cfg(target_arch = "wasm32")]
 mod wasm;
 
+/// Message a script sent to the host with `host.emit(name, payload)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostEvent {
+    pub name: String,
+    /// The payload encoded as JSON; `null` when the script passed none.
+    pub payload: String,
+}
+
 #[cfg(not(target_arch = "wasm32"))]
-pub use native::{LuaScriptManager, StaticViewport, ViewportProvider};
+pub use native::{LuaScriptManager, ScriptLogHandler, StaticViewport, ViewportProvider};
 #[cfg(target_arch = "wasm32")]
-pub use wasm::{LuaScriptManager, StaticViewport, ViewportProvider};
+pub use wasm::{LuaScriptManager, ScriptLogHandler, StaticViewport, ViewportProvider};
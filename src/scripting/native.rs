@@ -3,13 +3,28 @@ use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
 use anyhow::{anyhow, Context, Result};
-use mlua::{Lua, VmState};
+use log::warn;
+use mlua::{Lua, LuaOptions, StdLib, VmState};
+use parking_lot::Mutex;
 
 use crate::archive::{ArchiveFileEntry, CGameArchive};
 use crate::data_model::DataModel;
 use crate::input::InputState;
+use crate::render::DebugDraw;
+use crate::scene::InlineScript;
+use crate::telemetry::load_span;
 
 use super::bindings::{register_globals, ScriptContext};
+use super::manifest::archive_scripts;
+use super::scheduler::{run_worker, StartGate};
+use super::HostEvent;
+
+/// Archive directory whose entries are launched as scripts by default.
+const DEFAULT_SCRIPT_PREFIX: &str = "scripts/";
+
+/// Callback invoked with the level, chunk name and message of a script's
+/// `warn` or `log_error` call.
+pub type ScriptLogHandler = Arc<dyn Fn(log::Level, &str, &str) + Send + Sync>;
 
 /// Provides viewport dimensions for Lua scripts.
 pub trait ViewportProvider: Send + Sync {
@@ -41,10 +56,24 @@ pub struct LuaScriptManager {
     data_model: DataModel,
     input_state: Arc<InputState>,
     viewport: Arc<dyn ViewportProvider + Send + Sync>,
+    script_prefix: String,
+    inline_scripts: Vec<InlineScript>,
+    max_concurrency: Option<usize>,
+    log_handler: ScriptLogHandler,
+    debug_draw: Option<DebugDraw>,
+    host_events: Arc<Mutex<Vec<HostEvent>>>,
+    sandboxed: bool,
+    shared_state: bool,
     running: Arc<AtomicBool>,
     threads: Vec<JoinHandle<Result<()>>>,
 }
 
+/// Script queued for launch, either from the archive or inlined in the scene.
+pub(super) enum ScriptSource {
+    Archive(ArchiveFileEntry),
+    Inline(InlineScript),
+}
+
 impl LuaScriptManager {
     pub fn new(
         archive: Arc<CGameArchive>,
@@ -57,39 +86,253 @@ impl LuaScriptManager {
             data_model,
             input_state,
             viewport,
+            script_prefix: DEFAULT_SCRIPT_PREFIX.to_string(),
+            inline_scripts: Vec::new(),
+            max_concurrency: None,
+            log_handler: Arc::new(log_script_message),
+            debug_draw: None,
+            host_events: Arc::default(),
+            sandboxed: false,
+            shared_state: false,
             running: Arc::new(AtomicBool::new(false)),
             threads: Vec::new(),
         }
     }
 
-    /// Launches a Lua state for every file stored under the `scripts/` prefix.
+    /// Builds a manager for named script sources held in memory, with no
+    /// archive behind them. [`Self::start`] launches them in order, as it
+    /// does inline scene scripts.
+    pub fn with_scripts(
+        scripts: Vec<(String, String)>,
+        data_model: DataModel,
+        input_state: Arc<InputState>,
+        viewport: Arc<dyn ViewportProvider + Send + Sync>,
+    ) -> Self {
+        let mut manager = Self::new(
+            Arc::new(CGameArchive::empty()),
+            data_model,
+            input_state,
+            viewport,
+        );
+        manager.set_inline_scripts(
+            scripts
+                .into_iter()
+                .map(|(name, source)| InlineScript { name, source })
+                .collect(),
+        );
+        manager
+    }
+
+    /// Changes the archive path prefix that identifies script entries.
+    pub fn set_script_prefix(&mut self, prefix: &str) {
+        self.script_prefix = prefix.to_string();
+    }
+
+    /// Sets scripts taken from the scene XML, launched by [`Self::start`]
+    /// after the archive's `scripts/` entries.
+    pub fn set_inline_scripts(&mut self, scripts: Vec<InlineScript>) {
+        self.inline_scripts = scripts;
+    }
+
+    /// Caps the number of OS threads used to run scripts.
+    ///
+    /// When more scripts than `threads` are launched they are spread over a
+    /// fixed pool of workers, each multiplexing its scripts as coroutines
+    /// that yield whenever they call `wait`. Without a cap every script gets
+    /// its own thread. Either way, coroutines a script starts with `spawn`
+    /// are cooperatively scheduled on that script's worker. A script
+    /// manifest only orders how scripts start and keeps this cap;
+    /// [`set_shared_state`](Self::set_shared_state) overrides it.
+    pub fn set_max_concurrency(&mut self, threads: usize) {
+        self.max_concurrency = Some(threads.max(1));
+    }
+
+    /// Replaces the callback that receives messages from the Lua `warn` and
+    /// `log_error` globals. The default handler forwards them to the `log`
+    /// crate. Takes effect the next time scripts are started.
+    pub fn set_log_handler(&mut self, handler: ScriptLogHandler) {
+        self.log_handler = handler;
+    }
+
+    /// Routes the scripts' `debug.line` and `debug.point` calls to
+    /// `debug_draw`, usually the one returned by `Renderer::debug_draw`.
+    /// Without one those calls do nothing. Takes effect the next time
+    /// scripts are started.
+    pub fn set_debug_draw(&mut self, debug_draw: DebugDraw) {
+        self.debug_draw = Some(debug_draw);
+    }
+
+    /// Runs scripts with a reduced standard library, for archives that are
+    /// not trusted. Takes effect the next time scripts are started.
+    ///
+    /// Sandboxed scripts keep Luau's base functions (minus `require`), and
+    /// the `coroutine`, `table`, `string`, `utf8`, `bit32`, `math` and
+    /// `buffer` libraries, plus `os.clock`, `os.date`, `os.difftime` and
+    /// `os.time`. The `package` library and Luau's `debug` library are not
+    /// loaded; `debug` holds only the crate's `line` and `point`. Luau has
+    /// no `io`, `dofile`, `loadfile` or `os.execute` to begin with. All of
+    /// the crate's own globals, such as `place`, `input` and `screen`, stay
+    /// available.
+    pub fn set_sandboxed(&mut self, sandboxed: bool) {
+        self.sandboxed = sandboxed;
+    }
+
+    /// Runs every script in one Lua state, so globals such as helper
+    /// libraries defined by earlier scripts are visible to later ones.
+    /// Takes effect the next time scripts are started.
+    ///
+    /// A Lua state cannot be used from several threads at once, so shared
+    /// scripts all run on one worker thread, ignoring
+    /// [`set_max_concurrency`](Self::set_max_concurrency). They start in
+    /// launch order, each running up to its first `wait` before the next
+    /// begins, and afterwards only interleave at `wait` calls. A runtime
+    /// error in any of them stops them all.
+    pub fn set_shared_state(&mut self, shared: bool) {
+        self.shared_state = shared;
+    }
+
+    /// Takes the events scripts have sent with `host.emit` since the last
+    /// call, oldest first.
+    pub fn drain_events(&self) -> Vec<HostEvent> {
+        std::mem::take(&mut *self.host_events.lock())
+    }
+
+    /// Names of the archive entries under the script prefix followed by the
+    /// inline scripts, in the order [`Self::start`] launches them. Archive
+    /// entries are left out while the script manifest is invalid.
+    pub fn script_names(&self) -> Vec<String> {
+        let archive = match archive_scripts(&self.archive, &self.script_prefix) {
+            Ok(scripts) => scripts.entries,
+            Err(err) => {
+                warn!("{err:#}");
+                Vec::new()
+            }
+        };
+        archive
+            .into_iter()
+            .map(|entry| entry.name)
+            .chain(self.inline_scripts.iter().map(|script| script.name.clone()))
+            .collect()
+    }
+
+    /// Launches a Lua state for every file stored under the script prefix
+    /// (`scripts/` by default) and for every inline scene script.
+    ///
+    /// When the prefix holds a `manifest.json` array of script names, the
+    /// archive scripts launch in its order, followed by the inline scripts,
+    /// and each runs up to its first `wait` before the next one starts.
+    /// After that they run as usual, on the workers
+    /// [`set_max_concurrency`](Self::set_max_concurrency) allows. Fails if
+    /// the manifest is not valid JSON or names a script the archive lacks.
     pub fn start(&mut self) -> Result<usize> {
         self.stop()?;
-        let entries: Vec<ArchiveFileEntry> = self
-            .archive
-            .files()
-            .iter()
-            .filter(|entry| entry.name.starts_with("scripts/"))
-            .cloned()
+        let scripts = archive_scripts(&self.archive, &self.script_prefix)?;
+        let mut sources: Vec<ScriptSource> = scripts
+            .entries
+            .into_iter()
+            .map(ScriptSource::Archive)
             .collect();
+        sources.extend(
+            self.inline_scripts
+                .iter()
+                .cloned()
+                .map(ScriptSource::Inline),
+        );
 
-        if entries.is_empty() {
+        if sources.is_empty() {
             return Ok(0);
         }
+        Ok(self.launch(sources, scripts.ordered))
+    }
 
+    /// Stops any running scripts and launches only the archive entry
+    /// `name`, which must sit under the script prefix.
+    pub fn start_one(&mut self, name: &str) -> Result<()> {
+        if !name.starts_with(&self.script_prefix) {
+            return Err(anyhow!(
+                "{name} is not under the script prefix {}",
+                self.script_prefix
+            ));
+        }
+        let entry = self
+            .archive
+            .file(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("script {name} not found in archive"))?;
+        self.stop()?;
+        self.launch(vec![ScriptSource::Archive(entry)], false);
+        Ok(())
+    }
+
+    /// Spawns workers for `sources` and returns how many were launched.
+    /// `ordered` starts them one at a time, in order.
+    fn launch(&mut self, sources: Vec<ScriptSource>, ordered: bool) -> usize {
+        let count = sources.len();
+        let span = load_span("script_launch", &self.script_prefix);
+        let context = ScriptContext::new(
+            self.data_model.clone(),
+            Arc::clone(&self.input_state),
+            Arc::clone(&self.viewport),
+            Arc::clone(&self.running),
+            Arc::clone(&self.log_handler),
+        )
+        .with_archive(Arc::clone(&self.archive))
+        .with_debug_draw(self.debug_draw.clone())
+        .with_host_events(Arc::clone(&self.host_events))
+        .with_sandbox(self.sandboxed);
         self.running.store(true, Ordering::Release);
-        for entry in entries {
+        let shared_state = self.shared_state;
+        // One shared state already runs its scripts in launch order.
+        let gate = (ordered && !shared_state).then(StartGate::default);
+        let max_concurrency = if shared_state {
+            Some(1)
+        } else {
+            self.max_concurrency
+        };
+        let sources = sources.into_iter().enumerate();
+        let queues: Vec<Vec<(usize, ScriptSource)>> = match max_concurrency {
+            Some(workers) if count > workers => {
+                // Round robin keeps each queue in launch order, which the
+                // start gate relies on.
+                let mut queues: Vec<_> = (0..workers).map(|_| Vec::new()).collect();
+                for (index, source) in sources {
+                    queues[index % workers].push((index, source));
+                }
+                queues
+            }
+            _ => sources.map(|source| vec![source]).collect(),
+        };
+        for queue in queues {
             let archive = Arc::clone(&self.archive);
-            let data_model = self.data_model.clone();
-            let input_state = Arc::clone(&self.input_state);
-            let viewport = Arc::clone(&self.viewport);
-            let running = Arc::clone(&self.running);
+            let context = context.clone();
+            let gate = gate.clone();
             let handle = thread::spawn(move || {
-                run_script_thread(archive, data_model, input_state, viewport, running, entry)
+                run_worker(&archive, &context, queue, shared_state, gate.as_ref())
             });
             self.threads.push(handle);
         }
-        Ok(self.threads.len())
+        span.finish(count);
+        count
+    }
+
+    /// Stops the current scripts, swaps in a freshly loaded archive and
+    /// relaunches its scripts against the same data model.
+    ///
+    /// Script threads are joined before the archive is replaced, so no
+    /// thread from the previous archive is still running once this returns.
+    pub fn reload(&mut self, archive: Arc<CGameArchive>) -> Result<usize> {
+        if let Err(err) = self.stop() {
+            warn!("scripts reported errors while stopping for reload: {err:?}");
+        }
+        self.archive = archive;
+        self.start()
+    }
+
+    /// Returns whether any launched script has yet to finish, without
+    /// blocking.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+            && self.threads.iter().any(|handle| !handle.is_finished())
     }
 
     /// Blocks until every running script finishes.
@@ -136,16 +379,30 @@ impl Drop for LuaScriptManager {
     }
 }
 
-fn run_script_thread(
-    archive: Arc<CGameArchive>,
-    data_model: DataModel,
-    input_state: Arc<InputState>,
-    viewport: Arc<dyn ViewportProvider + Send + Sync>,
-    running: Arc<AtomicBool>,
-    entry: ArchiveFileEntry,
-) -> Result<()> {
-    let lua = Lua::new();
-    let hook_running = Arc::clone(&running);
+fn log_script_message(level: log::Level, chunk: &str, message: &str) {
+    log::log!(target: "lua", level, "[{chunk}] {message}");
+}
+
+/// Creates a Lua state with the runtime globals and a stop-request interrupt.
+pub(super) fn create_state(context: &ScriptContext) -> Result<Lua> {
+    let lua = if context.sandboxed {
+        // Luau's `os` only offers time queries, so it is safe to keep.
+        let libraries = StdLib::COROUTINE
+            | StdLib::TABLE
+            | StdLib::STRING
+            | StdLib::UTF8
+            | StdLib::BIT
+            | StdLib::MATH
+            | StdLib::BUFFER
+            | StdLib::OS;
+        let lua = Lua::new_with(libraries, LuaOptions::default())
+            .context("failed to create sandboxed Lua state")?;
+        lua.globals().raw_remove("require")?;
+        lua
+    } else {
+        Lua::new()
+    };
+    let hook_running = Arc::clone(&context.running);
     lua.set_interrupt(move |_| {
         if !hook_running.load(Ordering::Acquire) {
             Err(mlua::Error::RuntimeError("script stopped by host".into()))
@@ -153,20 +410,26 @@ fn run_script_thread(
             Ok(VmState::Continue)
         }
     });
+    register_globals(&lua, context)?;
+    Ok(lua)
+}
 
-    let context = ScriptContext::new(data_model, input_state, viewport, running);
-    register_globals(&lua, &context)?;
-
-    let source = archive
-        .extract_entry(&entry)
-        .with_context(|| format!("failed to extract {}", entry.name))?;
-    let script =
-        String::from_utf8(source).map_err(|err| anyhow!("{} is not UTF-8: {err}", entry.name))?;
-    lua.load(&script)
-        .set_name(&entry.name)
-        .exec()
-        .map_err(anyhow::Error::from)
-        .context("Lua runtime error")
+/// Resolves a queued script into its chunk name and source text.
+pub(super) fn load_source(
+    archive: &CGameArchive,
+    source: ScriptSource,
+) -> Result<(String, String)> {
+    match source {
+        ScriptSource::Archive(entry) => {
+            let bytes = archive
+                .extract_entry(&entry)
+                .with_context(|| format!("failed to extract {}", entry.name))?;
+            let script = String::from_utf8(bytes)
+                .map_err(|err| anyhow!("{} is not UTF-8: {err}", entry.name))?;
+            Ok((entry.name, script))
+        }
+        ScriptSource::Inline(inline) => Ok((inline.name, inline.source)),
+    }
 }
 
 #[cfg(test)]
@@ -180,37 +443,55 @@ mod tests {
     use tempfile::NamedTempFile;
 
     use std::io::Write;
+    use std::time::{Duration, Instant};
 
     static SCENE_XML: Lazy<String> = Lazy::new(|| {
         "<scene>\n  <object>\n    <name>Cube</name>\n    <type>mesh</type>\n  </object>\n</scene>\n"
             .to_string()
     });
 
+    fn cube_model() -> DataModel {
+        DataModel::from_objects(vec![SceneObject {
+            name: "Cube".into(),
+            ..SceneObject::default()
+        }])
+    }
+
     fn build_archive(script: &str) -> (NamedTempFile, Arc<CGameArchive>) {
+        build_archive_at("scripts/test.lua", script)
+    }
+
+    fn build_archive_at(entry_name: &str, script: &str) -> (NamedTempFile, Arc<CGameArchive>) {
+        build_archive_with(&[(entry_name, script)])
+    }
+
+    fn build_archive_with(scripts: &[(&str, &str)]) -> (NamedTempFile, Arc<CGameArchive>) {
         let mut tmp = NamedTempFile::new().unwrap();
         let scene_bytes = SCENE_XML.as_bytes();
-        let script_bytes = script.as_bytes();
 
         let mut buffer = Vec::new();
         buffer.extend_from_slice(b"CGME");
         buffer.extend_from_slice(&1u32.to_le_bytes());
         buffer.extend_from_slice(&0u64.to_le_bytes());
 
-        let header_len = buffer.len() as u64;
-        buffer.extend_from_slice(script_bytes);
-        let script_offset = header_len;
-        let script_size = script_bytes.len() as u64;
+        let mut entries = Vec::new();
+        for (name, script) in scripts {
+            entries.push((*name, buffer.len() as u64, script.len() as u64));
+            buffer.extend_from_slice(script.as_bytes());
+        }
 
-        let scene_offset = header_len + script_size;
+        let scene_offset = buffer.len() as u64;
         buffer.extend_from_slice(scene_bytes);
         let scene_size = scene_bytes.len() as u64;
 
         let toc_offset = scene_offset + scene_size;
-        buffer.extend_from_slice(&1u32.to_le_bytes());
-        buffer.extend_from_slice(&("scripts/test.lua".len() as u32).to_le_bytes());
-        buffer.extend_from_slice(b"scripts/test.lua");
-        buffer.extend_from_slice(&script_offset.to_le_bytes());
-        buffer.extend_from_slice(&script_size.to_le_bytes());
+        buffer.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for (name, offset, size) in entries {
+            buffer.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(name.as_bytes());
+            buffer.extend_from_slice(&offset.to_le_bytes());
+            buffer.extend_from_slice(&size.to_le_bytes());
+        }
         buffer.extend_from_slice(&scene_offset.to_le_bytes());
         buffer.extend_from_slice(&scene_size.to_le_bytes());
 
@@ -220,6 +501,59 @@ mod tests {
         (tmp, archive)
     }
 
+    #[test]
+    fn emitted_events_are_drained_in_order() {
+        let (_tmp, archive) =
+            build_archive(r#"host.emit("level_complete", { level = 3 }) host.emit("quit")"#);
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager =
+            LuaScriptManager::new(archive, cube_model(), Arc::new(InputState::new()), viewport);
+        manager.start().unwrap();
+        manager.wait().unwrap();
+
+        let events = manager.drain_events();
+        assert_eq!(
+            events,
+            [
+                HostEvent {
+                    name: "level_complete".into(),
+                    payload: r#"{"level":3}"#.into(),
+                },
+                HostEvent {
+                    name: "quit".into(),
+                    payload: "null".into(),
+                },
+            ]
+        );
+        assert!(manager.drain_events().is_empty());
+    }
+
+    #[test]
+    fn sandbox_strips_unsafe_globals() {
+        let script = r#"
+            local cube = place.get("Cube")
+            if os.execute == nil and require == nil and debug.traceback == nil
+                and os.clock ~= nil and string.format ~= nil then
+                cube.color = Color3.new(0, 255, 0)
+            end
+        "#;
+        let (_tmp, archive) = build_archive(script);
+        let model = cube_model();
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(
+            archive,
+            model.clone(),
+            Arc::new(InputState::new()),
+            viewport,
+        );
+        manager.set_sandboxed(true);
+        manager.start().unwrap();
+        manager.wait().unwrap();
+        assert_eq!(model.get("Cube").unwrap().color, Vec3::new(0.0, 1.0, 0.0));
+    }
+
     #[test]
     fn script_updates_data_model() {
         let (_tmp, archive) =
@@ -229,7 +563,7 @@ mod tests {
                 name: "Cube".into(),
                 ..SceneObject::default()
             }],
-            lights: vec![],
+            ..Scene::default()
         };
         let model = DataModel::from_objects(scene.objects.clone());
         let input = Arc::new(InputState::new());
@@ -241,4 +575,350 @@ mod tests {
         let cube = model.get("Cube").unwrap();
         assert_eq!(cube.color, Vec3::new(1.0, 0.0, 0.0));
     }
+
+    #[test]
+    fn custom_script_prefix_selects_entries() {
+        let (_tmp, archive) = build_archive_at(
+            "lua/tint.lua",
+            "place.get('Cube').color = Color3.new(0, 255, 0)",
+        );
+        let model = cube_model();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(archive, model.clone(), input, viewport);
+        assert_eq!(manager.start().unwrap(), 0);
+
+        manager.set_script_prefix("lua/");
+        assert_eq!(manager.start().unwrap(), 1);
+        manager.wait().unwrap();
+        assert_eq!(model.get("Cube").unwrap().color, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn start_one_runs_only_the_named_script() {
+        let (_tmp, archive) = build_archive_with(&[
+            (
+                "scripts/ai/enemy.lua",
+                "place.get('Cube').position = Vector3.new(1, 2, 3)",
+            ),
+            (
+                "scripts/tint.lua",
+                "place.get('Cube').color = Color3.new(0, 255, 0)",
+            ),
+        ]);
+        let model = cube_model();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(archive, model.clone(), input, viewport);
+        assert_eq!(
+            manager.script_names(),
+            vec!["scripts/ai/enemy.lua", "scripts/tint.lua"]
+        );
+        assert!(manager.start_one("scripts/missing.lua").is_err());
+        assert!(manager.start_one("ai/enemy.lua").is_err());
+
+        manager.start_one("scripts/ai/enemy.lua").unwrap();
+        manager.wait().unwrap();
+        let cube = model.get("Cube").unwrap();
+        assert_eq!(cube.position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(cube.color, SceneObject::default().color);
+    }
+
+    #[test]
+    fn inline_scripts_run_alongside_archive_scripts() {
+        let (_tmp, archive) = build_archive("place.get('Cube').position = Vector3.new(1, 2, 3)");
+        let model = cube_model();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(archive, model.clone(), input, viewport);
+        manager.set_inline_scripts(vec![InlineScript {
+            name: "tint".into(),
+            source: "place.get('Cube').color = Color3.new(0, 255, 0)".into(),
+        }]);
+        assert_eq!(manager.start().unwrap(), 2);
+        manager.wait().unwrap();
+        let cube = model.get("Cube").unwrap();
+        assert_eq!(cube.color, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(cube.position, Vec3::new(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn with_scripts_runs_sources_without_an_archive() {
+        let model = cube_model();
+        let mut manager = LuaScriptManager::with_scripts(
+            vec![(
+                "move".into(),
+                "place.get('Cube').position = Vector3.new(4, 5, 6)".into(),
+            )],
+            model.clone(),
+            Arc::new(InputState::new()),
+            Arc::new(StaticViewport::new(1280, 720)),
+        );
+        assert_eq!(manager.script_names(), ["move"]);
+        assert_eq!(manager.start().unwrap(), 1);
+        manager.wait().unwrap();
+        assert_eq!(
+            model.get("Cube").unwrap().position,
+            Vec3::new(4.0, 5.0, 6.0)
+        );
+    }
+
+    #[test]
+    fn bounded_pool_interleaves_waiting_scripts() {
+        let (_tmp, archive) = build_archive("while true do wait(5) end");
+        let model = cube_model();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(archive, model.clone(), input, viewport);
+        manager.set_max_concurrency(1);
+        manager.set_inline_scripts(vec![
+            InlineScript {
+                name: "move".into(),
+                source: "wait(5) place.get('Cube').position = Vector3.new(1, 2, 3)".into(),
+            },
+            InlineScript {
+                name: "tint".into(),
+                source: "wait(10) place.get('Cube').color = Color3.new(0, 0, 255)".into(),
+            },
+        ]);
+        assert_eq!(manager.start().unwrap(), 3);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while model.get("Cube").unwrap().color != Vec3::new(0.0, 0.0, 1.0) {
+            assert!(Instant::now() < deadline, "pooled scripts never ran");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(
+            model.get("Cube").unwrap().position,
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+        manager.stop().unwrap();
+    }
+
+    #[test]
+    fn shared_state_exposes_earlier_globals() {
+        let scripts = vec![
+            (
+                "helpers".to_string(),
+                "function offset(x) return x + 7 end".to_string(),
+            ),
+            (
+                "mover".to_string(),
+                "place.get('Cube').position = Vector3.new(offset(1), 0, 0)".to_string(),
+            ),
+        ];
+        let model = cube_model();
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::with_scripts(
+            scripts.clone(),
+            model.clone(),
+            Arc::new(InputState::new()),
+            Arc::clone(&viewport),
+        );
+        manager.set_shared_state(true);
+        assert_eq!(manager.start().unwrap(), 2);
+        manager.wait().unwrap();
+        assert_eq!(model.get("Cube").unwrap().position.x, 8.0);
+
+        let mut isolated = LuaScriptManager::with_scripts(
+            scripts,
+            cube_model(),
+            Arc::new(InputState::new()),
+            viewport,
+        );
+        isolated.start().unwrap();
+        let err = isolated.wait().unwrap_err();
+        assert!(format!("{err:#}").contains("mover"), "{err:#}");
+
+        let mut broken = LuaScriptManager::with_scripts(
+            vec![
+                ("helpers".to_string(), "local x = 1".to_string()),
+                ("typo".to_string(), "local = 2".to_string()),
+            ],
+            cube_model(),
+            Arc::new(InputState::new()),
+            Arc::new(StaticViewport::new(1280, 720)),
+        );
+        broken.set_shared_state(true);
+        broken.start().unwrap();
+        let err = broken.wait().unwrap_err();
+        assert!(format!("{err:#}").contains("script typo"), "{err:#}");
+    }
+
+    #[test]
+    fn manifest_orders_archive_scripts() {
+        let step = |digit: u32| {
+            format!(
+                "local cube = place.get('Cube') \
+                 cube.position = Vector3.new(cube.position.x * 10 + {digit}, 0, 0)"
+            )
+        };
+        let (first, second, third) = (step(1), step(2), step(3));
+        let (_tmp, archive) = build_archive_with(&[
+            ("scripts/a.lua", &first),
+            ("scripts/b.lua", &second),
+            ("scripts/c.lua", &third),
+            ("scripts/manifest.json", r#"["c.lua", "scripts/a.lua"]"#),
+        ]);
+        let model = cube_model();
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(
+            archive,
+            model.clone(),
+            Arc::new(InputState::new()),
+            viewport,
+        );
+        assert_eq!(
+            manager.script_names(),
+            ["scripts/c.lua", "scripts/a.lua", "scripts/b.lua"]
+        );
+        assert_eq!(manager.start().unwrap(), 3);
+        manager.wait().unwrap();
+        assert_eq!(model.get("Cube").unwrap().position.x, 312.0);
+
+        let (_tmp, missing) = build_archive_with(&[
+            ("scripts/a.lua", &first),
+            ("scripts/manifest.json", r#"["a.lua", "gone.lua"]"#),
+        ]);
+        manager.reload(missing).unwrap_err();
+    }
+
+    #[test]
+    fn manifest_order_keeps_concurrency_after_start() {
+        // After their first `wait`, `a` spins without yielding until `b`
+        // has moved the cube, which only happens if `b` has a worker of its
+        // own.
+        let spin = "wait(0) \
+                    local cube = place.get('Cube') \
+                    local deadline = os.clock() + 2 \
+                    while cube.position.x == 0 and os.clock() < deadline do end \
+                    cube.position = Vector3.new(cube.position.x * 10 + 1, 0, 0)";
+        let mark = "wait(0) \
+                    local cube = place.get('Cube') \
+                    cube.position = Vector3.new(cube.position.x + 1, 0, 0)";
+        let (_tmp, archive) = build_archive_with(&[
+            ("scripts/a.lua", spin),
+            ("scripts/b.lua", mark),
+            ("scripts/manifest.json", r#"["a.lua", "b.lua"]"#),
+        ]);
+        let model = cube_model();
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(
+            archive,
+            model.clone(),
+            Arc::new(InputState::new()),
+            viewport,
+        );
+        manager.set_max_concurrency(2);
+        let started = Instant::now();
+        assert_eq!(manager.start().unwrap(), 2);
+        manager.wait().unwrap();
+        assert_eq!(model.get("Cube").unwrap().position.x, 11.0);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn wait_returns_elapsed_time_and_clock() {
+        let (_tmp, archive) = build_archive(
+            r#"
+            local elapsed, clock = wait(10)
+            local _, later = coroutine.wrap(function() return wait(10) end)()
+            place.get('Cube').position = Vector3.new(elapsed, clock, later)
+            "#,
+        );
+        let model = cube_model();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(archive, model.clone(), input, viewport);
+        manager.start().unwrap();
+        manager.wait().unwrap();
+        let position = model.get("Cube").unwrap().position;
+        assert!((0.01..1.0).contains(&position.x), "elapsed {}", position.x);
+        assert!(position.y >= position.x, "clock {}", position.y);
+        assert!(
+            position.z >= position.y + 0.01,
+            "blocking clock {}",
+            position.z
+        );
+    }
+
+    #[test]
+    fn spawned_tasks_update_different_objects() {
+        let (_tmp, archive) = build_archive(
+            r#"
+            spawn(function()
+                task.wait(0.01)
+                place.get('Cube').position = Vector3.new(4, 5, 6)
+            end)
+            task.spawn(function(r, g, b)
+                wait(5)
+                place.get('Sphere').color = Color3.new(r, g, b)
+            end, 0, 255, 0)
+            "#,
+        );
+        let model = DataModel::from_objects(vec![
+            SceneObject::builder().name("Cube").build(),
+            SceneObject::builder().name("Sphere").build(),
+        ]);
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(archive, model.clone(), input, viewport);
+        manager.start().unwrap();
+        manager.wait().unwrap();
+        assert_eq!(
+            model.get("Cube").unwrap().position,
+            Vec3::new(4.0, 5.0, 6.0)
+        );
+        assert_eq!(model.get("Sphere").unwrap().color, Vec3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn is_running_clears_after_finite_script() {
+        let (_tmp, archive) =
+            build_archive("wait(20) place.get('Cube').position = Vector3.new(1, 1, 1)");
+        let model = cube_model();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(archive, model.clone(), input, viewport);
+        assert!(!manager.is_running());
+        manager.start().unwrap();
+        assert!(manager.is_running());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while manager.is_running() {
+            assert!(Instant::now() < deadline, "script never finished");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(model.get("Cube").unwrap().position, Vec3::ONE);
+        manager.wait().unwrap();
+    }
+
+    #[test]
+    fn reload_runs_scripts_from_new_archive() {
+        let (_first_tmp, first) = build_archive("place.get('Cube').color = Color3.new(255, 0, 0)");
+        let (_second_tmp, second) =
+            build_archive("place.get('Cube').color = Color3.new(0, 0, 255)");
+        let model = cube_model();
+        let input = Arc::new(InputState::new());
+        let viewport: Arc<dyn ViewportProvider + Send + Sync> =
+            Arc::new(StaticViewport::new(1280, 720));
+        let mut manager = LuaScriptManager::new(first, model.clone(), input, viewport);
+        manager.start().unwrap();
+        manager.wait().unwrap();
+        assert_eq!(model.get("Cube").unwrap().color, Vec3::new(1.0, 0.0, 0.0));
+
+        assert_eq!(manager.reload(second).unwrap(), 1);
+        manager.wait().unwrap();
+        assert_eq!(model.get("Cube").unwrap().color, Vec3::new(0.0, 0.0, 1.0));
+    }
 }
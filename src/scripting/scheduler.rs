@@ -0,0 +1,348 @@
+//! Cooperative scheduling of Lua scripts on a worker thread.
+//!
+//! Each script runs as a coroutine that yields to the worker whenever it
+//! calls `wait`. Scripts may start further coroutines with `spawn(fn, ...)`
+//! (or `task.spawn`); these share the script's Lua state and are resumed by
+//! the same worker, so they only interleave at `wait`/`task.wait` calls and
+//! never run in parallel with each other. In shared-state mode every script
+//! on the worker is a root coroutine of one Lua state.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use mlua::{Function, Lua, MultiValue, RegistryKey, Table, Thread, ThreadStatus, Value};
+use parking_lot::{Condvar, Mutex};
+
+use crate::archive::CGameArchive;
+
+use super::bindings::ScriptContext;
+use super::native::{create_state, load_source, ScriptSource};
+
+/// Longest a worker sleeps before re-checking the stop flag.
+const IDLE_CHUNK: Duration = Duration::from_millis(10);
+
+/// Installs the scheduler-aware `wait`, `spawn` and `task` globals.
+///
+/// Both `wait`s return the seconds actually waited and the seconds since
+/// the script started, like Roblox's `wait`. `wait` yields to the worker
+/// when called from a coroutine registered with `schedule`, such as a
+/// script's root, or a spawned one; coroutines scripts created themselves
+/// keep the blocking behaviour. Newly spawned coroutines are queued until
+/// the worker collects them with `take_pending`.
+const SCHEDULER_PRELUDE: &str = r#"
+local blocking = ...
+local scheduled = setmetatable({}, { __mode = "k" })
+local pending = {}
+
+local function wait(millis)
+    if scheduled[coroutine.running()] then
+        return coroutine.yield(millis or 0)
+    end
+    return blocking(millis)
+end
+
+local function spawn(callback, ...)
+    local args = table.pack(...)
+    local thread = coroutine.create(function()
+        return callback(table.unpack(args, 1, args.n))
+    end)
+    scheduled[thread] = true
+    table.insert(pending, thread)
+    return thread
+end
+
+local task = { spawn = spawn }
+function task.wait(seconds)
+    return wait(math.floor((seconds or 0) * 1000))
+end
+
+local function take_pending()
+    local spawned = pending
+    pending = {}
+    return spawned
+end
+
+local function schedule(thread)
+    scheduled[thread] = true
+end
+
+return wait, spawn, task, take_pending, schedule
+"#;
+
+/// Starts scripts spread over several workers one at a time, in launch
+/// order: each runs up to its first `wait` before the next one begins.
+#[derive(Clone, Default)]
+pub(super) struct StartGate(Arc<(Mutex<usize>, Condvar)>);
+
+impl StartGate {
+    /// Blocks until the scripts launched before `index` have started.
+    /// Returns `false` if the scripts are stopped meanwhile.
+    fn wait_turn(&self, index: usize, running: &AtomicBool) -> bool {
+        let (started, turn) = &*self.0;
+        let mut started = started.lock();
+        while *started < index {
+            if !running.load(Ordering::Acquire) {
+                return false;
+            }
+            turn.wait_for(&mut started, IDLE_CHUNK);
+        }
+        true
+    }
+
+    /// Lets the next script start.
+    fn finish_turn(&self) {
+        let (started, turn) = &*self.0;
+        *started.lock() += 1;
+        turn.notify_all();
+    }
+}
+
+/// A coroutine owned by a [`Task`] and the time it next wants to run.
+struct Coroutine {
+    thread: RegistryKey,
+    wake_at: Instant,
+    /// When the coroutine last yielded from `wait`; `None` before its
+    /// first resume.
+    waiting_since: Option<Instant>,
+    /// Index in [`Task::names`] of the script that started it.
+    script: usize,
+}
+
+/// Scripts multiplexed on a worker that share one Lua state: a single
+/// script unless the manager runs in shared-state mode.
+struct Task {
+    /// Chunk names of the scripts added with [`Task::add_script`].
+    names: Vec<String>,
+    lua: Lua,
+    take_pending: RegistryKey,
+    schedule: RegistryKey,
+    coroutines: Vec<Coroutine>,
+    /// Start of the task, the zero of the clock `wait` returns.
+    started: Instant,
+}
+
+impl Task {
+    fn new(context: &ScriptContext) -> Result<Self> {
+        let lua = create_state(context)?;
+        let (take_pending, schedule) = {
+            let blocking: Function = lua.globals().get("wait")?;
+            let (wait, spawn, task, take_pending, schedule): (
+                Function,
+                Function,
+                Table,
+                Function,
+                Function,
+            ) = lua.load(SCHEDULER_PRELUDE).call(blocking)?;
+            lua.globals().set("wait", wait)?;
+            lua.globals().set("spawn", spawn)?;
+            lua.globals().set("task", task)?;
+            (
+                lua.create_registry_value(take_pending)?,
+                lua.create_registry_value(schedule)?,
+            )
+        };
+        Ok(Self {
+            names: Vec::new(),
+            lua,
+            take_pending,
+            schedule,
+            coroutines: Vec::new(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Compiles `source` and queues it as a root coroutine that runs on the
+    /// next [`Task::step`], after the scripts added before it.
+    fn add_script(&mut self, archive: &CGameArchive, source: ScriptSource) -> Result<()> {
+        let (name, script) = load_source(archive, source)?;
+        let thread = self
+            .root_coroutine(&name, &script)
+            .with_context(|| format!("script {name}"))?;
+        self.coroutines.push(Coroutine {
+            thread,
+            wake_at: Instant::now(),
+            waiting_since: None,
+            script: self.names.len(),
+        });
+        self.names.push(name);
+        Ok(())
+    }
+
+    /// Compiles `script` into a coroutine registered with `schedule`.
+    fn root_coroutine(&self, name: &str, script: &str) -> Result<RegistryKey> {
+        let function = self.lua.load(script).set_name(name).into_function()?;
+        let root = self.lua.create_thread(function)?;
+        let schedule: Function = self.lua.registry_value(&self.schedule)?;
+        schedule.call::<_, ()>(root.clone())?;
+        Ok(self.lua.create_registry_value(root)?)
+    }
+
+    /// Resumes every coroutine that is due, then adopts any coroutines
+    /// spawned meanwhile. Returns `false` once no coroutines remain.
+    fn step(&mut self, now: Instant) -> Result<bool> {
+        let take_pending: Function = self.lua.registry_value(&self.take_pending)?;
+        let mut spawned = Vec::new();
+        let mut index = 0;
+        while index < self.coroutines.len() {
+            if self.coroutines[index].wake_at > now {
+                index += 1;
+                continue;
+            }
+            let coroutine = &self.coroutines[index];
+            let script = coroutine.script;
+            let name = &self.names[script];
+            let thread: Thread = self
+                .lua
+                .registry_value(&coroutine.thread)
+                .with_context(|| format!("script {name}"))?;
+            let resumed = match coroutine.waiting_since {
+                Some(since) => thread.resume((
+                    since.elapsed().as_secs_f64(),
+                    self.started.elapsed().as_secs_f64(),
+                )),
+                None => thread.resume(()),
+            };
+            let yielded: MultiValue = resumed.map_err(|err| {
+                anyhow::Error::from(err).context(format!("Lua runtime error in {name}"))
+            })?;
+            let pending: Table = take_pending
+                .call(())
+                .with_context(|| format!("script {name}"))?;
+            for thread in pending.sequence_values::<Thread>() {
+                spawned.push((thread.with_context(|| format!("script {name}"))?, script));
+            }
+            if thread.status() != ThreadStatus::Resumable {
+                let finished = self.coroutines.swap_remove(index);
+                self.lua
+                    .remove_registry_value(finished.thread)
+                    .with_context(|| format!("script {}", self.names[script]))?;
+                continue;
+            }
+            let millis = match yielded.into_iter().next() {
+                Some(Value::Integer(millis)) => millis.max(0) as u64,
+                Some(Value::Number(millis)) if millis > 0.0 => millis as u64,
+                _ => 0,
+            };
+            let yielded_at = Instant::now();
+            self.coroutines[index].wake_at = yielded_at + Duration::from_millis(millis);
+            self.coroutines[index].waiting_since = Some(yielded_at);
+            index += 1;
+        }
+
+        for (thread, script) in spawned {
+            let name = &self.names[script];
+            self.coroutines.push(Coroutine {
+                thread: self
+                    .lua
+                    .create_registry_value(thread)
+                    .with_context(|| format!("script {name}"))?,
+                wake_at: now,
+                waiting_since: None,
+                script,
+            });
+        }
+        Ok(!self.coroutines.is_empty())
+    }
+
+    fn next_wake(&self) -> Option<Instant> {
+        self.coroutines
+            .iter()
+            .map(|coroutine| coroutine.wake_at)
+            .min()
+    }
+}
+
+/// Runs several scripts on the calling thread, resuming each coroutine
+/// whenever its `wait` has elapsed, until they all finish or the host stops
+/// them.
+///
+/// With `shared_state` the scripts are loaded in order into one Lua state,
+/// so globals defined by earlier scripts are visible to later ones, and a
+/// runtime error in any of them stops them all.
+///
+/// Each source carries its launch index. With a `gate`, every script waits
+/// for its turn and runs up to its first `wait` before the next starts,
+/// which briefly holds up scripts this worker already started.
+pub(super) fn run_worker(
+    archive: &CGameArchive,
+    context: &ScriptContext,
+    sources: Vec<(usize, ScriptSource)>,
+    shared_state: bool,
+    gate: Option<&StartGate>,
+) -> Result<()> {
+    let mut errors = Vec::new();
+    let mut tasks = Vec::new();
+    if shared_state {
+        match Task::new(context) {
+            Ok(mut task) => {
+                for (_, source) in sources {
+                    if let Err(err) = task.add_script(archive, source) {
+                        errors.push(err);
+                    }
+                }
+                tasks.push(task);
+            }
+            Err(err) => errors.push(err),
+        }
+    } else {
+        for (index, source) in sources {
+            if let Some(gate) = gate {
+                if !gate.wait_turn(index, &context.running) {
+                    break;
+                }
+            }
+            let task = Task::new(context)
+                .and_then(|mut task| task.add_script(archive, source).map(|()| task));
+            match (task, gate) {
+                (Ok(mut task), Some(gate)) => {
+                    match task.step(Instant::now()) {
+                        Ok(true) => tasks.push(task),
+                        Ok(false) => {}
+                        Err(err) => errors.push(err),
+                    }
+                    gate.finish_turn();
+                }
+                (Ok(task), None) => tasks.push(task),
+                (Err(err), gate) => {
+                    errors.push(err);
+                    if let Some(gate) = gate {
+                        gate.finish_turn();
+                    }
+                }
+            }
+        }
+    }
+
+    while !tasks.is_empty() && context.running.load(Ordering::Acquire) {
+        let now = Instant::now();
+        tasks.retain_mut(|task| match task.step(now) {
+            Ok(alive) => alive,
+            Err(err) => {
+                errors.push(err);
+                false
+            }
+        });
+        if let Some(next) = tasks.iter().filter_map(Task::next_wake).min() {
+            let delay = next
+                .saturating_duration_since(Instant::now())
+                .min(IDLE_CHUNK);
+            if !delay.is_zero() {
+                thread::sleep(delay);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        let message = errors
+            .iter()
+            .map(|err| format!("{err:#}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(anyhow!("{message}"))
+    }
+}
@@ -1,11 +1,23 @@
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use log::warn;
 
-use crate::archive::{ArchiveFileEntry, CGameArchive};
+use crate::archive::CGameArchive;
 use crate::data_model::DataModel;
 use crate::input::InputState;
+use crate::render::DebugDraw;
+use crate::scene::InlineScript;
+
+use super::manifest::archive_scripts;
+use super::HostEvent;
+
+/// Archive directory whose entries are launched as scripts by default.
+const DEFAULT_SCRIPT_PREFIX: &str = "scripts/";
+
+/// Callback invoked with the level, chunk name and message of a script's
+/// `warn` or `log_error` call.
+pub type ScriptLogHandler = Arc<dyn Fn(log::Level, &str, &str) + Send + Sync>;
 
 /// Provides viewport dimensions for Lua scripts.
 pub trait ViewportProvider: Send + Sync {
@@ -41,6 +53,9 @@ pub struct LuaScriptManager {
     _data_model: DataModel,
     _input_state: Arc<InputState>,
     _viewport: Arc<dyn ViewportProvider + Send + Sync>,
+    script_prefix: String,
+    inline_scripts: Vec<InlineScript>,
+    _log_handler: ScriptLogHandler,
     launched: usize,
 }
 
@@ -56,20 +71,68 @@ impl LuaScriptManager {
             _data_model: data_model,
             _input_state: input_state,
             _viewport: viewport,
+            script_prefix: DEFAULT_SCRIPT_PREFIX.to_string(),
+            inline_scripts: Vec::new(),
+            _log_handler: Arc::new(log_script_message),
             launched: 0,
         }
     }
 
+    /// Builds a manager for named script sources held in memory, with no
+    /// archive behind them.
+    pub fn with_scripts(
+        scripts: Vec<(String, String)>,
+        data_model: DataModel,
+        input_state: Arc<InputState>,
+        viewport: Arc<dyn ViewportProvider + Send + Sync>,
+    ) -> Self {
+        let mut manager = Self::new(
+            Arc::new(CGameArchive::empty()),
+            data_model,
+            input_state,
+            viewport,
+        );
+        manager.set_inline_scripts(
+            scripts
+                .into_iter()
+                .map(|(name, source)| InlineScript { name, source })
+                .collect(),
+        );
+        manager
+    }
+
+    pub fn set_script_prefix(&mut self, prefix: &str) {
+        self.script_prefix = prefix.to_string();
+    }
+
+    pub fn set_inline_scripts(&mut self, scripts: Vec<InlineScript>) {
+        self.inline_scripts = scripts;
+    }
+
+    /// Kept for API parity; no script can call `warn` in the Web build.
+    pub fn set_log_handler(&mut self, handler: ScriptLogHandler) {
+        self._log_handler = handler;
+    }
+
+    /// Kept for API parity; no script can draw debug lines in the Web build.
+    pub fn set_debug_draw(&mut self, _debug_draw: DebugDraw) {}
+
+    /// Kept for API parity; no script runs in the Web build.
+    pub fn set_sandboxed(&mut self, _sandboxed: bool) {}
+
+    /// Kept for API parity; no script runs in the Web build.
+    pub fn set_shared_state(&mut self, _shared: bool) {}
+
+    /// Always empty, since no script runs to call `host.emit` in the Web
+    /// build.
+    pub fn drain_events(&self) -> Vec<HostEvent> {
+        Vec::new()
+    }
+
     pub fn start(&mut self) -> Result<usize> {
-        let entries: Vec<ArchiveFileEntry> = self
-            .archive
-            .files()
-            .iter()
-            .filter(|entry| entry.name.starts_with("scripts/"))
-            .cloned()
-            .collect();
-
-        let skipped = entries.len();
+        let entries = archive_scripts(&self.archive, &self.script_prefix)?.entries;
+
+        let skipped = entries.len() + self.inline_scripts.len();
         if skipped == 0 {
             self.launched = 0;
             return Ok(0);
@@ -89,6 +152,47 @@ impl LuaScriptManager {
         Ok(skipped)
     }
 
+    pub fn script_names(&self) -> Vec<String> {
+        let archive = match archive_scripts(&self.archive, &self.script_prefix) {
+            Ok(scripts) => scripts.entries,
+            Err(err) => {
+                warn!("{err:#}");
+                Vec::new()
+            }
+        };
+        archive
+            .into_iter()
+            .map(|entry| entry.name)
+            .chain(self.inline_scripts.iter().map(|script| script.name.clone()))
+            .collect()
+    }
+
+    /// Validates `name` like the native build, then skips it.
+    pub fn start_one(&mut self, name: &str) -> Result<()> {
+        if !name.starts_with(&self.script_prefix) {
+            return Err(anyhow!(
+                "{name} is not under the script prefix {}",
+                self.script_prefix
+            ));
+        }
+        if self.archive.file(name).is_none() {
+            return Err(anyhow!("script {name} not found in archive"));
+        }
+        warn!("Lua scripting is not available in the WebAssembly build; skipping {name}");
+        Ok(())
+    }
+
+    pub fn reload(&mut self, archive: Arc<CGameArchive>) -> Result<usize> {
+        self.archive = archive;
+        self.start()
+    }
+
+    /// Reports active scripts; the Web build never launches any, so this
+    /// stays `false`.
+    pub fn is_running(&self) -> bool {
+        self.launched > 0
+    }
+
     pub fn wait(&mut self) -> Result<()> {
         Ok(())
     }
@@ -97,3 +201,7 @@ impl LuaScriptManager {
         Ok(())
     }
 }
+
+fn log_script_message(level: log::Level, chunk: &str, message: &str) {
+    log::log!(target: "lua", level, "[{chunk}] {message}");
+}
@@ -0,0 +1,55 @@
+//! Optional `tracing` spans around load-time hot spots.
+//!
+//! With the `tracing` feature disabled every helper compiles to nothing and
+//! the crate keeps reporting through `log` alone.
+
+use std::fmt::Display;
+
+#[cfg(feature = "tracing")]
+pub(crate) struct LoadSpan {
+    span: tracing::span::EnteredSpan,
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct LoadSpan;
+
+/// Enters a span describing `operation` applied to `target` (a path, mesh
+/// name, ...). Call [`LoadSpan::finish`] once the work succeeds.
+#[cfg(feature = "tracing")]
+pub(crate) fn load_span(operation: &'static str, target: &dyn Display) -> LoadSpan {
+    let span = tracing::info_span!(
+        "load",
+        operation,
+        target = %target,
+        count = tracing::field::Empty,
+        elapsed_ms = tracing::field::Empty,
+    );
+    LoadSpan {
+        span: span.entered(),
+        #[cfg(not(target_arch = "wasm32"))]
+        start: std::time::Instant::now(),
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn load_span(_operation: &'static str, _target: &dyn Display) -> LoadSpan {
+    LoadSpan
+}
+
+impl LoadSpan {
+    /// Records how many items were produced and how long the work took.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn finish(self, count: usize) {
+        self.span.record("count", count);
+        // `Instant` is unavailable on wasm32-unknown-unknown.
+        #[cfg(not(target_arch = "wasm32"))]
+        self.span
+            .record("elapsed_ms", self.start.elapsed().as_secs_f64() * 1000.0);
+        tracing::debug!("load finished");
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn finish(self, _count: usize) {}
+}
@@ -0,0 +1,812 @@
+//! Plain data types shared by scenes, meshes and input handling.
+//!
+//! Everything here needs only `core` and `alloc`, so these types stay
+//! available when the crate is built without its `std` feature (add `libm`
+//! for the float math glam needs on such targets). The modules that parse,
+//! store and react to them re-export them under their usual paths.
+#![deny(clippy::std_instead_of_core, clippy::std_instead_of_alloc)]
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// Lua source embedded directly in the scene XML via `<script>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InlineScript {
+    pub name: String,
+    pub source: String,
+}
+
+/// Scene object as described by the authoring tools.
+///
+/// Optional fields are always serialized, even when `None`, so formats that
+/// are not self-describing, such as bincode, can read them back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneObject {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub object_type: String,
+    #[serde(default)]
+    pub mesh: Option<String>,
+    /// Name of the object this one is positioned relative to.
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default = "default_color")]
+    pub color: Vec3,
+    /// Opacity in `0..=1`, read from an optional fourth `<color>` component.
+    #[serde(default = "default_alpha")]
+    pub alpha: f32,
+    /// Light the object gives off, added after shading; black for none.
+    #[serde(default)]
+    pub emissive: Vec3,
+    #[serde(default)]
+    pub position: Vec3,
+    #[serde(default)]
+    pub rotation: Vec3,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+    /// Scale of this object's own geometry, applied before its transform.
+    /// Unlike [`Self::scale`] it does not carry over to children.
+    #[serde(default = "default_scale")]
+    pub mesh_scale: Vec3,
+    /// Baked transform from `<matrix>`, used instead of position, rotation
+    /// and scale when present.
+    #[serde(default)]
+    pub matrix: Option<Mat4>,
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    /// Near clipping distance for cameras.
+    #[serde(default = "default_near")]
+    pub near: f32,
+    /// Far clipping distance for cameras.
+    #[serde(default = "default_far")]
+    pub far: f32,
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    /// How a `light` object emits; lights aim along [`Self::forward`].
+    #[serde(default)]
+    pub light_type: LightType,
+    /// Half-angle, in degrees, of a spot light's cone.
+    #[serde(default = "default_spot_angle")]
+    pub spot_angle: f32,
+    #[serde(default)]
+    pub shading: Shading,
+    /// Draw this object's edges only, when the GPU supports line polygons.
+    #[serde(default)]
+    pub wireframe: bool,
+    /// Turn to face the camera every frame, keeping position and scale.
+    #[serde(default)]
+    pub billboard: bool,
+    /// Custom gameplay data, such as health or state, keyed by name.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, AttributeValue>,
+}
+
+/// Value of a custom object attribute.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeValue {
+    Number(f64),
+    Bool(bool),
+    String(String),
+}
+
+impl From<f64> for AttributeValue {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+/// How a light emits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LightType {
+    /// Shines in every direction from its position.
+    #[default]
+    Point,
+    /// Parallel rays along its direction, like sunlight; position is ignored.
+    Directional,
+    /// Shines from its position within a cone around its direction.
+    Spot,
+}
+
+impl LightType {
+    /// Parses `point`, `directional` or `spot`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "point" => Some(Self::Point),
+            "directional" => Some(Self::Directional),
+            "spot" => Some(Self::Spot),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Point => "point",
+            Self::Directional => "directional",
+            Self::Spot => "spot",
+        }
+    }
+}
+
+/// Alpha below which [`Shading::Cutout`] discards fragments when no
+/// threshold is given.
+pub const DEFAULT_CUTOUT_THRESHOLD: f32 = 0.5;
+
+/// How the renderer lights an object.
+///
+/// Cutout thresholds compare by bit pattern, which makes equality total.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shading {
+    /// Diffuse lighting with interpolated vertex normals.
+    #[default]
+    Lit,
+    /// The object's color, unaffected by lights or shadows.
+    Unlit,
+    /// Diffuse lighting with one normal per triangle.
+    Flat,
+    /// Lit, with a procedural checkerboard over the base color.
+    ///
+    /// Meshes carry no UVs, so the pattern is laid out in object space at
+    /// four cells per unit; it still makes scale and stretching visible.
+    Checker,
+    /// Lit, but fragments whose alpha is below `threshold` are dropped and
+    /// the rest are drawn opaque, avoiding blending sort artifacts.
+    Cutout { threshold: f32 },
+}
+
+impl Shading {
+    /// Parses `lit`, `unlit`, `flat`, `checker` or `cutout`, ignoring case.
+    /// `cutout:0.3` sets the alpha threshold, which otherwise defaults to
+    /// [`DEFAULT_CUTOUT_THRESHOLD`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if let Some(threshold) = name.strip_prefix("cutout:") {
+            let threshold = threshold.trim().parse().ok()?;
+            return Some(Self::Cutout { threshold });
+        }
+        match name.as_str() {
+            "lit" => Some(Self::Lit),
+            "unlit" => Some(Self::Unlit),
+            "flat" => Some(Self::Flat),
+            "checker" => Some(Self::Checker),
+            "cutout" => Some(Self::Cutout {
+                threshold: DEFAULT_CUTOUT_THRESHOLD,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The mode's name, without any cutout threshold; see [`Shading::name`].
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lit => "lit",
+            Self::Unlit => "unlit",
+            Self::Flat => "flat",
+            Self::Checker => "checker",
+            Self::Cutout { .. } => "cutout",
+        }
+    }
+
+    /// The name [`Shading::from_name`] parses back to this mode, such as
+    /// `cutout:0.3` for a cutout with a non-default threshold.
+    pub fn name(self) -> String {
+        match self {
+            Self::Cutout { threshold } if threshold != DEFAULT_CUTOUT_THRESHOLD => {
+                format!("cutout:{threshold}")
+            }
+            _ => self.as_str().to_string(),
+        }
+    }
+}
+
+impl PartialEq for Shading {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Cutout { threshold: a }, Self::Cutout { threshold: b }) => {
+                a.to_bits() == b.to_bits()
+            }
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for Shading {}
+
+impl Default for SceneObject {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            object_type: String::new(),
+            mesh: None,
+            parent: None,
+            color: default_color(),
+            alpha: default_alpha(),
+            emissive: Vec3::ZERO,
+            position: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+            scale: Vec3::ONE,
+            mesh_scale: Vec3::ONE,
+            matrix: None,
+            fov: default_fov(),
+            near: default_near(),
+            far: default_far(),
+            intensity: default_intensity(),
+            light_type: LightType::Point,
+            spot_angle: default_spot_angle(),
+            shading: Shading::Lit,
+            wireframe: false,
+            billboard: false,
+            attributes: BTreeMap::new(),
+        }
+    }
+}
+
+impl SceneObject {
+    /// Starts building an object from the same defaults as [`Default`].
+    pub fn builder() -> SceneObjectBuilder {
+        SceneObjectBuilder::default()
+    }
+
+    /// Transform relative to the parent: the baked `matrix` if set,
+    /// otherwise translation, then Z/Y/X rotation in degrees, then scale.
+    pub fn local_matrix(&self) -> Mat4 {
+        if let Some(matrix) = self.matrix {
+            return matrix;
+        }
+        let translation = Mat4::from_translation(self.position);
+        let rotation = Mat4::from_rotation_z(self.rotation.z.to_radians())
+            * Mat4::from_rotation_y(self.rotation.y.to_radians())
+            * Mat4::from_rotation_x(self.rotation.x.to_radians());
+        translation * rotation * Mat4::from_scale(self.scale)
+    }
+
+    /// Unit vector the object faces: local `-Z` under its rotation, the
+    /// same convention cameras use.
+    pub fn forward(&self) -> Vec3 {
+        self.local_matrix()
+            .transform_vector3(Vec3::NEG_Z)
+            .normalize_or_zero()
+    }
+}
+
+/// Composes `object`'s local matrix with those of its ancestors in `objects`.
+///
+/// Missing parents end the chain, and cycles are cut off after visiting
+/// every object once.
+pub fn world_matrix(objects: &[SceneObject], object: &SceneObject) -> Mat4 {
+    let mut matrix = object.local_matrix();
+    let mut parent = object.parent.as_deref();
+    for _ in 0..objects.len() {
+        let Some(name) = parent else {
+            break;
+        };
+        let Some(node) = objects.iter().find(|candidate| candidate.name == name) else {
+            break;
+        };
+        matrix = node.local_matrix() * matrix;
+        parent = node.parent.as_deref();
+    }
+    matrix
+}
+
+/// [`world_matrix`] for every object in `objects`, in order.
+///
+/// Parents are looked up by name once and each ancestor's matrix is
+/// computed once, so this stays linear where calling [`world_matrix`] per
+/// object is quadratic. Cycles are cut where they close.
+pub fn world_matrices(objects: &[SceneObject]) -> Vec<Mat4> {
+    let mut indices = BTreeMap::new();
+    for (index, object) in objects.iter().enumerate() {
+        // The first object with a name wins, as in `world_matrix`.
+        indices.entry(object.name.as_str()).or_insert(index);
+    }
+    let parent_of = |index: usize| {
+        objects[index]
+            .parent
+            .as_deref()
+            .and_then(|name| indices.get(name).copied())
+    };
+
+    let mut matrices: Vec<Option<Mat4>> = alloc::vec![None; objects.len()];
+    let mut chain = Vec::new();
+    for start in 0..objects.len() {
+        // Climb to the nearest ancestor already resolved, then fill in the
+        // chain top-down.
+        let mut current = Some(start);
+        while let Some(index) = current {
+            if matrices[index].is_some() || chain.contains(&index) {
+                break;
+            }
+            chain.push(index);
+            current = parent_of(index);
+        }
+        let mut matrix = current
+            .and_then(|index| matrices[index])
+            .unwrap_or(Mat4::IDENTITY);
+        while let Some(index) = chain.pop() {
+            matrix *= objects[index].local_matrix();
+            matrices[index] = Some(matrix);
+        }
+    }
+    matrices.into_iter().flatten().collect()
+}
+
+/// Chained construction for [`SceneObject`]; unset fields keep their defaults.
+#[derive(Debug, Clone, Default)]
+pub struct SceneObjectBuilder {
+    object: SceneObject,
+}
+
+impl SceneObjectBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.object.name = name.into();
+        self
+    }
+
+    pub fn object_type(mut self, object_type: impl Into<String>) -> Self {
+        self.object.object_type = object_type.into();
+        self
+    }
+
+    pub fn mesh(mut self, mesh: impl Into<String>) -> Self {
+        self.object.mesh = Some(mesh.into());
+        self
+    }
+
+    pub fn parent(mut self, parent: impl Into<String>) -> Self {
+        self.object.parent = Some(parent.into());
+        self
+    }
+
+    pub fn color(mut self, color: impl Into<Vec3>) -> Self {
+        self.object.color = color.into();
+        self
+    }
+
+    pub fn alpha(mut self, alpha: f32) -> Self {
+        self.object.alpha = alpha;
+        self
+    }
+
+    pub fn emissive(mut self, emissive: impl Into<Vec3>) -> Self {
+        self.object.emissive = emissive.into();
+        self
+    }
+
+    pub fn position(mut self, position: impl Into<Vec3>) -> Self {
+        self.object.position = position.into();
+        self
+    }
+
+    pub fn rotation(mut self, rotation: impl Into<Vec3>) -> Self {
+        self.object.rotation = rotation.into();
+        self
+    }
+
+    pub fn scale(mut self, scale: impl Into<Vec3>) -> Self {
+        self.object.scale = scale.into();
+        self
+    }
+
+    pub fn mesh_scale(mut self, mesh_scale: impl Into<Vec3>) -> Self {
+        self.object.mesh_scale = mesh_scale.into();
+        self
+    }
+
+    pub fn matrix(mut self, matrix: impl Into<Mat4>) -> Self {
+        self.object.matrix = Some(matrix.into());
+        self
+    }
+
+    pub fn fov(mut self, fov: f32) -> Self {
+        self.object.fov = fov;
+        self
+    }
+
+    pub fn near(mut self, near: f32) -> Self {
+        self.object.near = near;
+        self
+    }
+
+    pub fn far(mut self, far: f32) -> Self {
+        self.object.far = far;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.object.intensity = intensity;
+        self
+    }
+
+    pub fn light_type(mut self, light_type: LightType) -> Self {
+        self.object.light_type = light_type;
+        self
+    }
+
+    pub fn spot_angle(mut self, spot_angle: f32) -> Self {
+        self.object.spot_angle = spot_angle;
+        self
+    }
+
+    pub fn shading(mut self, shading: Shading) -> Self {
+        self.object.shading = shading;
+        self
+    }
+
+    pub fn wireframe(mut self, wireframe: bool) -> Self {
+        self.object.wireframe = wireframe;
+        self
+    }
+
+    pub fn billboard(mut self, billboard: bool) -> Self {
+        self.object.billboard = billboard;
+        self
+    }
+
+    pub fn attribute(mut self, key: impl Into<String>, value: impl Into<AttributeValue>) -> Self {
+        self.object.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> SceneObject {
+        self.object
+    }
+}
+
+fn default_color() -> Vec3 {
+    Vec3::ONE
+}
+
+fn default_alpha() -> f32 {
+    1.0
+}
+
+fn default_scale() -> Vec3 {
+    Vec3::ONE
+}
+
+pub(crate) fn default_fov() -> f32 {
+    45.0
+}
+
+fn default_near() -> f32 {
+    0.1
+}
+
+fn default_far() -> f32 {
+    100.0
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_spot_angle() -> f32 {
+    30.0
+}
+
+/// Light extracted from the scene object list.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub light_type: LightType,
+    /// Unit vector the light points along; unused by point lights.
+    pub direction: Vec3,
+    /// Cone half-angle in degrees; only used by spot lights.
+    pub spot_angle: f32,
+}
+
+impl Light {
+    /// Reads the light parameters from `object` placed by its `world`
+    /// matrix (see [`world_matrix`]), or `None` when it is not a light.
+    pub fn from_object(object: &SceneObject, world: Mat4) -> Option<Self> {
+        (object.object_type == "light").then_some(Self {
+            position: world.w_axis.truncate(),
+            color: object.color,
+            intensity: object.intensity,
+            light_type: object.light_type,
+            direction: world.transform_vector3(Vec3::NEG_Z).normalize_or_zero(),
+            spot_angle: object.spot_angle,
+        })
+    }
+}
+
+/// Number of `f32` values stored per interleaved vertex.
+pub const VERTEX_STRIDE: usize = 9;
+
+/// Smallest normal dot product [`ObjMesh::weld`] treats as the same
+/// direction, roughly 2.5 degrees apart.
+const WELD_NORMAL_DOT: f32 = 0.999;
+
+/// GPU ready mesh buffers produced from an OBJ file.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ObjMesh {
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+impl ObjMesh {
+    /// Appends `other`'s geometry, offsetting its indices past the existing
+    /// vertices.
+    pub fn append(&mut self, other: &ObjMesh) {
+        let offset = (self.vertices.len() / VERTEX_STRIDE) as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices
+            .extend(other.indices.iter().map(|index| index + offset));
+    }
+
+    /// Reverses the winding of every triangle, turning meshes authored with
+    /// the opposite handedness the right way out. Normals are kept as is.
+    pub fn flip_winding(&mut self) {
+        for triangle in self.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    /// Axis-aligned `(min, max)` corners of the vertex positions, or `None`
+    /// for a mesh without vertices.
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        self.vertices
+            .chunks_exact(VERTEX_STRIDE)
+            .map(|vertex| Vec3::new(vertex[0], vertex[1], vertex[2]))
+            .fold(None, |bounds, position| match bounds {
+                None => Some((position, position)),
+                Some((min, max)) => Some((min.min(position), max.max(position))),
+            })
+    }
+
+    /// Merges vertices whose positions and colors lie within `tolerance` of
+    /// each other and whose normals point the same way, rebuilding the
+    /// indices. Returns the number of vertices removed.
+    ///
+    /// Parsing only merges corners sharing OBJ indices; call this afterwards
+    /// for exports that duplicate coincident vertices. Hard edges survive
+    /// because their normals differ. A `tolerance` of zero only merges
+    /// vertices with bit-identical positions and colors.
+    pub fn weld(&mut self, tolerance: f32) -> usize {
+        let tolerance = if tolerance.is_finite() {
+            tolerance.max(0.0)
+        } else {
+            0.0
+        };
+        if tolerance == 0.0 {
+            return self.weld_exact();
+        }
+        let cell_size = tolerance.max(f32::EPSILON);
+        let max_distance_squared = tolerance * tolerance;
+        let cell_of = |position: Vec3| (position / cell_size).floor().as_ivec3();
+
+        let mut cells: BTreeMap<(i32, i32, i32), Vec<u32>> = BTreeMap::new();
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut remap = Vec::with_capacity(self.vertices.len() / VERTEX_STRIDE);
+        for vertex in self.vertices.chunks_exact(VERTEX_STRIDE) {
+            let position = Vec3::from_slice(&vertex[0..3]);
+            let normal = Vec3::from_slice(&vertex[3..6]);
+            let color = Vec3::from_slice(&vertex[6..9]);
+            let cell = cell_of(position);
+
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let key = (cell.x + dx, cell.y + dy, cell.z + dz);
+                        let Some(candidates) = cells.get(&key) else {
+                            continue;
+                        };
+                        for &candidate in candidates {
+                            let start = candidate as usize * VERTEX_STRIDE;
+                            let other = &vertices[start..start + VERTEX_STRIDE];
+                            let matches = Vec3::from_slice(&other[0..3]).distance_squared(position)
+                                <= max_distance_squared
+                                && Vec3::from_slice(&other[3..6]).dot(normal) >= WELD_NORMAL_DOT
+                                && Vec3::from_slice(&other[6..9]).distance_squared(color)
+                                    <= max_distance_squared;
+                            if matches {
+                                found = Some(candidate);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let index = found.unwrap_or_else(|| {
+                let index = (vertices.len() / VERTEX_STRIDE) as u32;
+                vertices.extend_from_slice(vertex);
+                cells
+                    .entry((cell.x, cell.y, cell.z))
+                    .or_default()
+                    .push(index);
+                index
+            });
+            remap.push(index);
+        }
+
+        self.apply_weld(vertices, &remap)
+    }
+
+    /// [`Self::weld`] without a tolerance: buckets vertices by the exact bit
+    /// patterns of their position and color, so far-apart coordinates never
+    /// share a grid cell.
+    fn weld_exact(&mut self) -> usize {
+        // `+ 0.0` folds -0.0 into 0.0 so equal values share a key.
+        let key_of = |vertex: &[f32]| -> [u32; 6] {
+            let mut key = [0; 6];
+            for (slot, value) in key.iter_mut().zip(vertex[0..3].iter().chain(&vertex[6..9])) {
+                *slot = (value + 0.0).to_bits();
+            }
+            key
+        };
+
+        let mut buckets: BTreeMap<[u32; 6], Vec<u32>> = BTreeMap::new();
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut remap = Vec::with_capacity(self.vertices.len() / VERTEX_STRIDE);
+        for vertex in self.vertices.chunks_exact(VERTEX_STRIDE) {
+            let normal = Vec3::from_slice(&vertex[3..6]);
+            let bucket = buckets.entry(key_of(vertex)).or_default();
+            let found = bucket.iter().copied().find(|&candidate| {
+                let start = candidate as usize * VERTEX_STRIDE + 3;
+                Vec3::from_slice(&vertices[start..start + 3]).dot(normal) >= WELD_NORMAL_DOT
+            });
+            let index = found.unwrap_or_else(|| {
+                let index = (vertices.len() / VERTEX_STRIDE) as u32;
+                vertices.extend_from_slice(vertex);
+                bucket.push(index);
+                index
+            });
+            remap.push(index);
+        }
+
+        self.apply_weld(vertices, &remap)
+    }
+
+    /// Swaps in the welded `vertices` and points every index at its merged
+    /// vertex. Returns the number of vertices removed.
+    fn apply_weld(&mut self, vertices: Vec<f32>, remap: &[u32]) -> usize {
+        let removed = remap.len() - vertices.len() / VERTEX_STRIDE;
+        for index in &mut self.indices {
+            *index = remap[*index as usize];
+        }
+        self.vertices = vertices;
+        removed
+    }
+}
+
+/// Identifier for a physical keyboard key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyCode {
+    Named(NamedKey),
+    Character(char),
+    Digit(u8),
+    Function(u8),
+}
+
+impl KeyCode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(button) = parse_named_key(name) {
+            return Some(button);
+        }
+        if name.len() == 1 {
+            let ch = name.chars().next().unwrap();
+            if ch.is_ascii_alphabetic() {
+                return Some(Self::Character(ch.to_ascii_uppercase()));
+            }
+            if ch.is_ascii_digit() {
+                return Some(Self::Digit(ch as u8 - b'0'));
+            }
+        }
+        if let Some(function) = name.strip_prefix('F').or_else(|| name.strip_prefix('f')) {
+            if let Ok(index) = function.parse::<u8>() {
+                if (1..=25).contains(&index) {
+                    return Some(Self::Function(index));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the canonical name accepted by [`KeyCode::from_name`].
+    pub fn name(&self) -> String {
+        match self {
+            // Variant names double as the primary spelling in `parse_named_key`.
+            Self::Named(key) => format!("{key:?}"),
+            Self::Character(ch) => ch.to_string(),
+            Self::Digit(digit) => digit.to_string(),
+            Self::Function(index) => format!("F{index}"),
+        }
+    }
+}
+
+fn parse_named_key(name: &str) -> Option<KeyCode> {
+    use NamedKey::*;
+    let key = match name {
+        "Space" => Space,
+        "Enter" | "Return" => Enter,
+        "Tab" => Tab,
+        "Left" => Left,
+        "Right" => Right,
+        "Up" => Up,
+        "Down" => Down,
+        "Escape" | "Esc" => Escape,
+        "Backspace" => Backspace,
+        "Home" => Home,
+        "End" => End,
+        "PageUp" => PageUp,
+        "PageDown" => PageDown,
+        "LeftShift" | "LShift" => LeftShift,
+        "RightShift" | "RShift" => RightShift,
+        "LeftCtrl" | "LControl" => LeftCtrl,
+        "RightCtrl" | "RControl" => RightCtrl,
+        "LeftAlt" | "LAlt" => LeftAlt,
+        "RightAlt" | "RAlt" => RightAlt,
+        _ => return None,
+    };
+    Some(KeyCode::Named(key))
+}
+
+/// Friendly names for a subset of keyboard keys used by existing scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NamedKey {
+    Space,
+    Enter,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Escape,
+    Backspace,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    LeftAlt,
+    RightAlt,
+}
+
+/// Identifier for a mouse button (left button is zero).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MouseButton(u8);
+
+impl MouseButton {
+    pub const LEFT: Self = Self(0);
+
+    pub fn new(index: u8) -> Self {
+        Self(index)
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+}
@@ -13,10 +13,12 @@ use winit::platform::web::{EventLoopExtWebSys, WindowAttributesExtWebSys};
 use winit::window::Window;
 
 use crate::app::{
-    camera_from_objects, light_from_objects, map_keycode, map_mouse_button, print_final_state,
+    camera_from_model, light_from_model, map_keycode, map_mouse_button, print_final_state,
+    RedrawTracker,
 };
 use crate::{
-    CGameArchive, DataModel, InputState, LuaScriptManager, Renderer, Scene, ViewportProvider,
+    CGameArchive, DataModel, DebugDraw, InputState, LuaScriptManager, Renderer, Runtime, Scene,
+    ViewportProvider,
 };
 
 #[wasm_bindgen]
@@ -28,13 +30,9 @@ pub async fn run(
     console_error_panic_hook::set_once();
 
     let bytes = archive_bytes.to_vec();
-    let archive = Arc::new(
-        CGameArchive::from_bytes("wasm-scene", bytes)
-            .map_err(|err| JsValue::from_str(&format!("failed to load archive: {err}")))?,
-    );
-
-    let scene = Scene::from_xml(archive.scene_xml())
-        .map_err(|err| JsValue::from_str(&format!("failed to parse scene XML: {err}")))?;
+    let archive = CGameArchive::from_bytes_cached("wasm-scene", bytes)
+        .map_err(|err| JsValue::from_str(&format!("failed to load archive: {err}")))?;
+    let runtime = Runtime::load(archive).map_err(|err| JsValue::from_str(&format!("{err:#}")))?;
     let window = web_sys::window().ok_or_else(|| JsValue::from_str("missing window"))?;
     let document = window
         .document()
@@ -60,9 +58,15 @@ pub async fn run(
             .map_err(|err| JsValue::from_str(&format!("window error: {err}")))?,
     );
 
-    let renderer = Renderer::new(Arc::clone(&window), Arc::clone(&archive))
+    let mut renderer = Renderer::new(Arc::clone(&window), Arc::clone(runtime.archive()))
         .await
         .map_err(|err| JsValue::from_str(&format!("renderer error: {err}")))?;
+    renderer.set_scene_root(runtime.scene().root_transform());
+    let adapter = renderer.adapter_info();
+    log_to_console(&format!(
+        "Rendering with {} ({:?}, {:?})",
+        adapter.name, adapter.backend, adapter.device_type
+    ));
 
     let viewport = Arc::new(WebViewport::new(
         window.inner_size().width,
@@ -70,16 +74,9 @@ pub async fn run(
     ));
     let viewport_provider: Arc<dyn ViewportProvider + Send + Sync> = viewport.clone();
 
-    let input = Arc::new(InputState::new());
-    let data_model = DataModel::from_objects(scene.objects.clone());
-
     let script_manager = if run_scripts {
-        let mut manager = LuaScriptManager::new(
-            Arc::clone(&archive),
-            data_model.clone(),
-            Arc::clone(&input),
-            viewport_provider,
-        );
+        let mut manager = runtime.script_manager(viewport_provider);
+        manager.set_debug_draw(renderer.debug_draw());
         let count = manager
             .start()
             .map_err(|err| JsValue::from_str(&format!("failed to launch scripts: {err}")))?;
@@ -93,14 +90,17 @@ pub async fn run(
         None
     };
 
-    log_scene_summary(&scene);
+    log_scene_summary(runtime.scene());
 
+    let debug_draw = renderer.debug_draw();
     let mut app = WebAppState {
         renderer,
-        data_model,
-        input,
+        data_model: runtime.data_model().clone(),
+        input: Arc::clone(runtime.input()),
         viewport,
         script_manager,
+        redraw: RedrawTracker::new(false),
+        debug_draw,
     };
 
     #[allow(deprecated)]
@@ -137,23 +137,29 @@ struct WebAppState {
     input: Arc<InputState>,
     viewport: Arc<WebViewport>,
     script_manager: Option<LuaScriptManager>,
+    redraw: RedrawTracker,
+    debug_draw: DebugDraw,
 }
 
 impl WebAppState {
     fn process_event(&mut self, event: &Event<()>, elwt: &ActiveEventLoop) -> Result<(), String> {
         match event {
             Event::WindowEvent { event, window_id } if *window_id == self.renderer.window_id() => {
+                if !matches!(event, WindowEvent::RedrawRequested) {
+                    self.redraw.mark_dirty();
+                }
                 match event {
                     WindowEvent::CloseRequested => elwt.exit(),
                     WindowEvent::Resized(size) => {
-                        self.renderer.resize(*size);
+                        self.renderer.set_pending_size(*size);
                         self.viewport.update(size.width, size.height);
                     }
                     WindowEvent::ScaleFactorChanged { .. } => {
                         let size = self.renderer.window().inner_size();
-                        self.renderer.resize(size);
+                        self.renderer.set_pending_size(size);
                         self.viewport.update(size.width, size.height);
                     }
+                    WindowEvent::Focused(false) => self.input.reset(),
                     WindowEvent::KeyboardInput { event, .. } => self.handle_keyboard(event),
                     WindowEvent::MouseInput { state, button, .. } => {
                         self.handle_mouse_button(*state, *button)
@@ -163,12 +169,25 @@ impl WebAppState {
                         self.input.set_mouse_position(pos);
                     }
                     WindowEvent::RedrawRequested => {
-                        let objects = self.data_model.all_objects();
+                        self.input.begin_frame();
                         let aspect = self.renderer_aspect();
-                        let camera = camera_from_objects(&objects, aspect);
-                        let light = light_from_objects(&objects);
+                        let camera = camera_from_model(&self.data_model, aspect);
+                        let light = light_from_model(&self.data_model);
                         self.renderer.update_globals(&camera, &light);
-                        if let Err(err) = self.renderer.render(&objects) {
+                        let revision = self.data_model.revision();
+                        // Only building the draws holds the read lock, so
+                        // scripts never wait on surface acquire, mesh loads
+                        // or present.
+                        let frame = {
+                            let objects = self.data_model.read();
+                            self.renderer.prepare_frame(&objects)
+                        };
+                        let result = self.renderer.render_prepared(frame);
+                        match &result {
+                            Ok(()) => self.redraw.frame_drawn(revision),
+                            Err(_) => self.redraw.mark_dirty(),
+                        }
+                        if let Err(err) = result {
                             match err {
                                 wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => {
                                     let size = self.renderer.window().inner_size();
@@ -180,10 +199,13 @@ impl WebAppState {
                                 wgpu::SurfaceError::Timeout => {
                                     log_to_console("Surface timeout; retrying next frame");
                                 }
-                                wgpu::SurfaceError::Other => {
-                                    log_to_console(
-                                        "Surface reported an unknown error; retrying next frame",
-                                    );
+                                // `Other` and any variants added by future wgpu
+                                // releases: rebuild the surface and keep going.
+                                other => {
+                                    log_to_console(&format!(
+                                        "Surface error ({other}); reconfiguring"
+                                    ));
+                                    self.renderer.reconfigure();
                                 }
                             }
                         }
@@ -192,7 +214,13 @@ impl WebAppState {
                 }
             }
             Event::AboutToWait => {
-                self.renderer.window().request_redraw();
+                self.log_host_events();
+                let dirty = self.redraw.is_dirty(self.data_model.revision())
+                    || self.input.any_held()
+                    || self.debug_draw.has_pending();
+                if dirty {
+                    self.renderer.window().request_redraw();
+                }
             }
             Event::LoopExiting => {
                 self.shutdown();
@@ -202,6 +230,16 @@ impl WebAppState {
         Ok(())
     }
 
+    /// Drains the events scripts sent with `host.emit` into the console.
+    fn log_host_events(&self) {
+        let Some(manager) = self.script_manager.as_ref() else {
+            return;
+        };
+        for event in manager.drain_events() {
+            log_to_console(&format!("script event {}: {}", event.name, event.payload));
+        }
+    }
+
     fn renderer_aspect(&self) -> f32 {
         let size = self.renderer.window().inner_size();
         if size.height == 0 {
@@ -1,3 +1,5 @@
+#![cfg(feature = "graphics")]
+
 use assert_cmd::prelude::*;
 use predicates::str::contains;
 use std::io::Write;
@@ -68,3 +70,16 @@ fn cli_runs_scripts_and_prints_final_state() {
             " - Cube pos=(0.00, 0.00, 0.00) color=(1.00, 0.00, 0.00)",
         ));
 }
+
+#[test]
+fn cli_rejects_invalid_max_fps() {
+    let archive = build_archive();
+    let mut cmd = Command::cargo_bin("crystal-runtime").expect("binary exists");
+    cmd.arg(archive.path())
+        .arg("--summary-only")
+        .arg("--max-fps")
+        .arg("fast");
+    cmd.assert()
+        .failure()
+        .stderr(contains("invalid --max-fps value \"fast\""));
+}